@@ -1,22 +1,35 @@
 
 use crate::{vec3::{Vec3, MAX, MIN}, ray::Ray};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
-
 pub struct AABB {
     pub min: Vec3,
     pub max: Vec3
 }
 
-impl AABB {
-    pub fn default() -> Self {
+// the default box is empty (inverted), so joining anything into it yields that thing's bounds
+impl Default for AABB {
+    fn default() -> Self {
         AABB { min: MAX, max: MIN}
     }
+}
 
+impl AABB {
     pub fn new(min: Vec3, max: Vec3) -> Self {
         AABB {min,max}
     }
 
+    // the box around unbounded primitives such as infinite planes
+    pub fn infinite() -> Self {
+        AABB { min: MIN, max: MAX }
+    }
+
+    // false for infinite boxes and for the empty default box
+    pub fn is_finite(&self) -> bool {
+        self.min.v.iter().chain(&self.max.v).all(|x| x.is_finite())
+    }
+
     pub fn add(&mut self, point: Vec3) {
         self.min.v[0] = if point.x() < self.min.v[0] {point.x()} else {self.min.v[0]};
         self.min.v[1] = if point.y() < self.min.v[1] {point.y()} else {self.min.v[1]};
@@ -32,6 +45,18 @@ impl AABB {
         self.add(existing.max);
     }
 
+    // the range of t over which the ray is inside the box, if it crosses it at all
+    pub fn interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (mut enter, mut exit) = (f64::NEG_INFINITY, f64::INFINITY);
+        for i in 0..3 {
+            let inv = 1.0 / ray.direction.v[i];
+            let t0 = (self.min.v[i] - ray.origin.v[i]) * inv;
+            let t1 = (self.max.v[i] - ray.origin.v[i]) * inv;
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        if enter <= exit { Some((enter, exit)) } else { None }
+    }
 
     #[allow(unused_assignments)]
     pub fn hit(&self, ray: &Ray) -> bool {
@@ -91,7 +116,7 @@ impl AABB {
         if t_z_max < t_max {
             t_max = t_z_max;
         }
-        return true;
+        true
     }
 
 }
@@ -1,7 +1,6 @@
+use crate::{aabb::AABB, hittable2::Primitive, material::MaterialTable, ray::Ray, hittable::Record, vec3::Point3};
 
-use crate::{aabb::AABB, hittable2::Primitive, ray::Ray, hittable::Record};
-
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct BVHNode {
     bounds: AABB,
     left: Option<Box<BVHNode>>,
@@ -12,13 +11,71 @@ pub struct BVHNode {
 
 const MAX_DEPTH: i32 = 2048;
 
-impl BVHNode {
-    pub fn default() -> Self {
-        BVHNode { bounds: AABB::default(), left: None, right: None, start: 0, end: 0 }
+// A BVH over a list of items, the primitives of a scene or the faces of a mesh. Building sorts a
+// list of indices rather than the items themselves, the nodes refer to ranges of that list.
+// Unbounded items would blow every box up to infinity, so they are kept in a list of their own
+// and tested alongside the tree.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct BVH {
+    root: BVHNode,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl BVH {
+    pub fn new(primitives: &[Primitive]) -> Self {
+        BVH::build(primitives.len(), |i| *primitives[i].bounds(), |i| primitives[i].centroid())
     }
 
-    pub fn new(&self, primitives: &Vec<Primitive>, indices: &mut Vec<usize>, start: usize, end: usize, depth: i32) -> Self {
-        let extent = self.bounds.max - self.bounds.min;
+    // A BVH over count items, given the bounds and centroid of each
+    pub fn build(count: usize, bounds: impl Fn(usize) -> AABB, centroid: impl Fn(usize) -> Point3) -> Self {
+        let boxes: Vec<AABB> = (0..count).map(bounds).collect();
+        let (mut indices, unbounded): (Vec<usize>, Vec<usize>) = (0..count).partition(|i| boxes[*i].is_finite());
+        let centroids: Vec<Point3> = (0..count).map(|i| if boxes[i].is_finite() { centroid(i) } else { Point3::default() }).collect();
+        let len = indices.len();
+        let root = BVHNode::new(&boxes, &centroids, &mut indices, 0, len, 0);
+        BVH { root, indices, unbounded }
+    }
+
+    // bounds of the tree, unbounded items are not included
+    pub fn bounds(&self) -> &AABB {
+        &self.root.bounds
+    }
+
+    // primitives must be the slice the BVH was built over
+    pub fn ray_hit(&self, primitives: &[Primitive], materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        self.traverse(ray, t_min, t_max, |i, t_min, t_max| {
+            let primitive = &primitives[i];
+            match primitive {
+                // instances and meshes already ran the test in their own BVH
+                Primitive::Instance(_) | Primitive::IndexedMesh(_) => primitive.ray_hit(materials, ray, t_min, t_max),
+                _ => opaque_hit(materials, t_min, |t_min| primitive.ray_hit(materials, ray, t_min, t_max)),
+            }
+        })
+    }
+
+    // The closest hit of any item, hit(i, t_min, t_max) tests item i
+    pub fn traverse(&self, ray: &Ray, t_min: f64, t_max: f64, hit: impl Fn(usize, f64, f64) -> Option<Record>) -> Option<Record> {
+        let mut closest = t_max;
+        let mut final_record = None;
+        for i in &self.unbounded {
+            if let Some(c) = hit(*i, t_min, closest) {
+                closest = c.t;
+                final_record = Some(c);
+            }
+        }
+        self.root.ray_hit(&self.indices, ray, t_min, closest, &hit).or(final_record)
+    }
+}
+
+impl BVHNode {
+    pub fn new(boxes: &[AABB], centroids: &[Point3], indices: &mut [usize], start: usize, end: usize, depth: i32) -> Self {
+        let mut centroid_bounds = AABB::default();
+        for i in &indices[start..end] {
+            centroid_bounds.add(centroids[*i]);
+        }
+        let extent = centroid_bounds.max - centroid_bounds.min;
         let mut axis = 0;
         if extent.v[0] < extent.v[1] {
             axis = 1;
@@ -29,22 +86,22 @@ impl BVHNode {
         let num_obj = end - start;
 
         match num_obj {
+            0 => BVHNode::default(),
             1 => {
-                let bounds = (&primitives[start]).bounds().clone();
-                let n =  BVHNode {
+                let bounds = boxes[indices[start]];
+                BVHNode {
                     bounds,
                     left: None,
                     right: None,
                     start,
                     end,
-                };
-                return n;
-            }, 
+                }
+            },
             _ => {
                 if depth == MAX_DEPTH {
                     let mut bounds = AABB::default();
-                    for i in start..end {
-                        bounds.join(&(primitives[i]).bounds().clone());
+                    for i in &indices[start..end] {
+                        bounds.join(&boxes[*i]);
                     }
                     return BVHNode {
                         bounds,
@@ -57,79 +114,135 @@ impl BVHNode {
 
                 let slice = &mut indices[start..end];
                 slice.sort_by(|a,b| {
-                    (&primitives[*a].centroid().v[axis]).partial_cmp(&primitives[*b].centroid().v[axis]).unwrap()
+                    centroids[*a].v[axis].partial_cmp(&centroids[*b].v[axis]).unwrap()
                 });
                 let mid = (start + end) / 2;
-                let left = BVHNode::new(&self, primitives, indices, start, mid, depth + 1);
-                let right = BVHNode::new(&self, primitives, indices, mid, end, depth + 1);
+                let left = BVHNode::new(boxes, centroids, indices, start, mid, depth + 1);
+                let right = BVHNode::new(boxes, centroids, indices, mid, end, depth + 1);
 
-                let left_bounds = &left.bounds.clone();
-                let right_bounds = &right.bounds.clone();
-        
                 let mut bounds = AABB::default();
-                bounds.join(left_bounds);
-                bounds.join(right_bounds);
-        
-                let n =  BVHNode {
+                bounds.join(&left.bounds);
+                bounds.join(&right.bounds);
+
+                BVHNode {
                     bounds,
                     left: Some(Box::new(left)) ,
                     right: Some(Box::new(right)),
                     start,
                     end,
-                };
-                return n;
-        
+                }
             }
-        };
+        }
     }
     
-    pub fn ray_hit(&self, primitives: &Vec<Primitive>, ray: &Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::Record> {
-        match self {
-            BVHNode { bounds, left:None , right: None, start, end } => {
-                if !bounds.hit(ray) {
-                    return None
-                } else {
-                    let mut final_record: Option<Record> = None;
-                    for i in *start..*end {
-                        let curr = primitives[i].ray_hit(ray, t_min, t_max);
-                        match final_record {
-                            Some(record) => {
-                                match curr {
-                                    Some(c) => if c.t < record.t {final_record = Some(c)},
-                                    None => (),
-                                }
-                            },
-                            None => {final_record = curr},
-                        }
-                        
-                    }
-                    return final_record;
-                }
-            },
-            BVHNode { bounds, left, right, start: _, end: _ } => {
-                if !bounds.hit(ray) {
-                    return None
-                } else {
-                    let l = left.as_ref().unwrap().ray_hit(primitives, ray, t_min, t_max);
-                    let r = right.as_ref().unwrap().ray_hit(primitives, ray, t_min, t_max);
-
-                    return match l {
-                        Some(l) =>  {match r {
-                            Some(r) => if l.t < r.t {Some(l)} else {Some(r)},
-                            None => Some(l)
-                        }},
-                        None => {
-                            match r {
-                                Some(r) => Some(r),
-                                None => None
-                            }
-                        }
+    pub fn ray_hit(&self, indices: &[usize], ray: &Ray, t_min: f64, t_max: f64, hit: &impl Fn(usize, f64, f64) -> Option<Record>) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => {
+                // anything the right child finds has to be closer than the left child's hit
+                let l = left.ray_hit(indices, ray, t_min, t_max, hit);
+                let closest = l.as_ref().map_or(t_max, |l| l.t);
+                let r = right.ray_hit(indices, ray, t_min, closest, hit);
+                r.or(l)
+            }
+            _ => {
+                let mut final_record: Option<Record> = None;
+                let mut closest = t_max;
+                for i in &indices[self.start..self.end] {
+                    if let Some(c) = hit(*i, t_min, closest) {
+                        closest = c.t;
+                        final_record = Some(c);
                     }
                 }
-
+                final_record
             }
         }
     }
 }
 
+// The closest hit from hit(t_min) that passes its material's alpha test. Cut out parts are
+// skipped and the search carries on just past them, to the surface's next hit if it has one.
+pub fn opaque_hit(materials: &MaterialTable, t_min: f64, hit: impl Fn(f64) -> Option<Record>) -> Option<Record> {
+    let mut t_min = t_min;
+    loop {
+        let record = hit(t_min)?;
+        if materials[record.material].alpha_test(&record) {
+            return Some(record);
+        }
+        t_min = record.t.next_up();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{material::{Material, MaterialId}, quad::Quad, sphere::Sphere, vec3::Vec3};
+
+    // a unit sphere at the origin behind a quad at z = 2 with the given opacity
+    fn sphere_behind_quad(opacity: f64) -> (Vec<Primitive>, MaterialTable) {
+        let mut materials = MaterialTable::new();
+        let red = materials.add(Material::Diffuse { color: Vec3::new(1.0, 0.0, 0.0).into() });
+        let base = Box::new(Material::Diffuse { color: Vec3::new(0.0, 1.0, 0.0).into() });
+        let mask = materials.add(Material::Masked { base, opacity: opacity.into() });
+        let primitives = vec![
+            Primitive::Quad(Quad::new(Point3::new(-2.0, -2.0, 2.0), Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0), mask)),
+            Primitive::Sphere(Sphere::new(Point3::default(), 1.0, red)),
+        ];
+        (primitives, materials)
+    }
 
+    fn first_hit(opacity: f64) -> Record {
+        let (primitives, materials) = sphere_behind_quad(opacity);
+        let bvh = BVH::new(&primitives);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        bvh.ray_hit(&primitives, &materials, &ray, 0.001, f64::INFINITY).expect("ray should hit something")
+    }
+
+    #[test]
+    fn cut_out_quad_lets_the_ray_through() {
+        let hit = first_hit(0.0);
+        assert!((hit.t - 4.0).abs() < 1e-9, "t = {}", hit.t);
+        assert_eq!(hit.material, MaterialId(1));
+    }
+
+    #[test]
+    fn opaque_quad_blocks_the_ray() {
+        let hit = first_hit(1.0);
+        assert!((hit.t - 3.0).abs() < 1e-9, "t = {}", hit.t);
+        assert_eq!(hit.material, MaterialId(2));
+    }
+
+    // the first of a surface's crossings at or past t_min
+    fn crossing(crossings: &[(f64, MaterialId)], t_min: f64) -> Option<Record> {
+        crossings.iter().find(|(t, _)| *t >= t_min).map(|(t, material)| {
+            let mut record = Record::new();
+            (record.t, record.material) = (*t, *material);
+            record
+        })
+    }
+
+    #[test]
+    fn search_resumes_just_past_each_cut_out_hit() {
+        let mut materials = MaterialTable::new();
+        let solid = materials.add(Material::Diffuse { color: Vec3::new(1.0, 1.0, 1.0).into() });
+        let hole = materials.add(Material::Masked { base: Box::new(Material::Empty), opacity: 0.0.into() });
+        // one surface crossed three times, only the last crossing is solid
+        let crossings = [(1.0, hole), (2.0, hole), (3.0, solid)];
+        let queries = RefCell::new(Vec::new());
+        let surface = |t_min: f64| {
+            queries.borrow_mut().push(t_min);
+            crossing(&crossings, t_min)
+        };
+        let hit = opaque_hit(&materials, 0.5, surface).expect("the solid crossing is hit");
+        assert_eq!(hit.t, 3.0);
+        assert_eq!(*queries.borrow(), [0.5, 1.0f64.next_up(), 2.0f64.next_up()]);
+
+        // nothing is left once every crossing is cut out
+        let crossings = [(1.0, hole), (2.0, hole)];
+        assert!(opaque_hit(&materials, 0.5, |t_min| crossing(&crossings, t_min)).is_none());
+    }
+}
@@ -1,9 +1,36 @@
-use std::time::Instant;
+use std::fmt;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-use crate::{hittable::{HittableVec, Hittable}, vec3::{Point3, Vec3, WHITE}, ray::Ray, color::Color, util::gen_random, image::Image, hittable2::Primitive, bvh::BVHNode};
+use crate::{vec3::{Point3, Vec3}, ray::Ray, color::Color, util::gen_random, image::Image, hittable2::Primitive, bvh::BVH, material::MaterialTable};
+
+#[derive(Debug)]
+pub enum CameraError {
+    // width or height is not positive, or there are more pixels than fit in a buffer
+    Resolution(i32, i32),
+    Samples(i32),
+    RayDepth(i32),
+    // field of view in degrees, must be strictly between 0 and 180
+    Fov(f64),
+    // look_from and look_at are the same point, so there is no view direction
+    LookAt(Point3),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraError::Resolution(w, h) => write!(f, "resolution {}x{} must be positive", w, h),
+            CameraError::Samples(n) => write!(f, "samples {} must be positive", n),
+            CameraError::RayDepth(n) => write!(f, "ray depth {} must be positive", n),
+            CameraError::Fov(fov) => write!(f, "fov {} must be between 0 and 180 degrees", fov),
+            CameraError::LookAt(p) => write!(f, "look_from and look_at are both {}", p),
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
 
+#[derive(Clone)]
 pub struct Camera {
     pub image_width: i32,
     pub image_height: i32,
@@ -20,8 +47,13 @@ pub struct Camera {
     pub focus_dist: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
-    v_up: Vec3,
-    pub output: Image,
+    pub v_up: Vec3,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::new()
+    }
 }
 
 impl Camera {
@@ -44,116 +76,51 @@ impl Camera {
             defocus_disk_u: Vec3::default(),
             defocus_disk_v: Vec3::default(),
             v_up: Vec3::new(0.0, 1.0, 0.0),
-            output: Image::new(4, 4, vec![])
         }
     }
 
 
-    pub fn render(&mut self, world: &HittableVec, bvh: &BVHNode, primitives: &Vec<Primitive>) {
-        self.init();
-
-        let CHUNK_SIZE:usize = 1;
-
-        //PPM header
-        // println!("P3\n{} {}\n255", self.image_width, self.image_height);
-        
-
-
-        // let _ = (0..self.image_height * self.image_width).into_par_iter().map(|pixel| {
-                // let mut color_accumulate = Color::default();
-                // let i = pixel / self.image_width;
-                // let j = pixel % self.image_width;
-
-                // for _ in 0..self.samples {
-                //     let ray: Ray = self.get_sample_ray(i, j);
-                //     color_accumulate = color_accumulate + self.ray_color(&ray, world, self.ray_depth);
-                //     color_accumulate.write_color(self.samples);
-                // }
-        // });
+    // whether the resolution, sample count, ray depth and view can be rendered
+    pub fn validate(&self) -> Result<(), CameraError> {
+        let (w, h) = (self.image_width, self.image_height);
+        if w <= 0 || h <= 0 || w.checked_mul(h).is_none() {
+            return Err(CameraError::Resolution(w, h));
+        }
+        if self.samples <= 0 {
+            return Err(CameraError::Samples(self.samples));
+        }
+        if self.ray_depth <= 0 {
+            return Err(CameraError::RayDepth(self.ray_depth));
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(CameraError::Fov(self.fov));
+        }
+        if (self.look_from - self.look_at).length() == 0.0 {
+            return Err(CameraError::LookAt(self.look_from));
+        }
+        Ok(())
+    }
 
-        let start = Instant::now();
+    // callers validate the camera first, Renderer::render does
+    pub(crate) fn render(&mut self, bvh: &BVH, primitives: &[Primitive], materials: &MaterialTable) -> Image {
+        self.init();
 
         let mut pixels = vec![Color::default(); (self.image_height * self.image_width) as usize];
 
-        // let chunks: Vec<(usize, &mut [Vec3])> = pixels.chunks_mut((1) as usize).enumerate().collect();
-        // let _ = chunks.into_par_iter().for_each(|(x, chunk)| {
-        //     // for x in 0..self.image_width {
-        //         let mut color_accumulate = Color::default();
-        //         for _ in 0..self.samples {
-        //             let ray: Ray = self.get_sample_ray(x as i32 / self.image_width, x as i32 % self.image_width);
-        //             // println!("{:?}", ray);
-        //             color_accumulate = color_accumulate + self.ray_color(&ray, world, self.ray_depth);
-        //         }
-        //         chunk[0] = color_accumulate;
-        //     // }
-        // });
-
-        
         let rows: Vec<(usize, &mut [Vec3])> = pixels.chunks_mut((self.image_width) as usize).enumerate().collect();
-        let _ = rows.into_par_iter().for_each(|(y, row)| {
+        rows.into_par_iter().for_each(|(y, row)| {
             for x in 0..self.image_width {
                 let mut color_accumulate = Color::default();
-                let mut color_accumulate2 = Color::default();
 
                 for _ in 0..self.samples {
                     let ray: Ray = self.get_sample_ray(y as i32, x);
-                    // println!("{y} {x} {:?}", ray);
-                    // color_accumulate = color_accumulate + self.ray_color(&ray, world, self.ray_depth);
-                    color_accumulate2 = color_accumulate2 + self.ray_color2(&ray, bvh, primitives, self.ray_depth);
-
+                    color_accumulate = color_accumulate + self.ray_color(&ray, bvh, primitives, materials, self.ray_depth);
                 }
-                row[x as usize] = color_accumulate2;
+                row[x as usize] = color_accumulate / self.samples as f64;
             }
         });
 
-        self.output = Image::new(self.image_width as u32, self.image_height as u32, pixels);
-
-        println!("Time elapsed: {}", start.elapsed().as_millis())
-
-
-        // let _ = (0..self.image_height * self.image_width).into_par_iter().map(|pixel| {
-        //     let mut color_accumulate = Color::default();
-        //     let i = pixel / self.image_width;
-        //     let j = pixel % self.image_width;
-
-        //     for _ in 0..self.samples {
-        //         let ray: Ray = self.get_sample_ray(i, j);
-        //         color_accumulate = color_accumulate + self.ray_color(&ray, world, self.ray_depth);
-        //         // color_accumulate.write_color(self.samples);
-        //     }
-
-        //     // self.update_pixel((i * self.image_height + j) as usize, color_accumulate);
-
-        //     pixels[(i * self.image_height + j) as usize] = color_accumulate;
-        // });
-
-        //scanning across image and firing rays
-        // for i in 0..self.image_height{
-        //     eprintln!("Progress: {}", i);
-        //     for j in 0..self.image_width {
-        //         let mut color_accumulate = Color::default();
-                
-        //         for _ in 0..self.samples {
-        //             let ray: Ray = self.get_sample_ray(i, j);
-        //             color_accumulate = color_accumulate + self.ray_color(&ray, world, self.ray_depth);
-        //         }
-
-
-
-        //         // //finding ray using pixel offset
-        //         // let pixel_vec = self.upper_left_pixel + (i as f64 * self.del_h) + (j as f64 * self.del_w);
-
-        //         // //defining ray to be shot
-        //         // let ray : Ray = Ray::new(self.camera_center, pixel_vec - self.camera_center);
-
-        //         // //where the shooting happens -> color of the pixel
-        //         // let color: Color = self.ray_color(&ray, &world);
-
-
-        //         // color_accumulate.write_color(self.samples);
-        //     }
-        // }
-        // eprintln!("Done");
+        Image::new(self.image_width as u32, self.image_height as u32, pixels)
     }
 
     fn get_sample_ray(&self, i: i32, j: i32) -> Ray {
@@ -178,7 +145,6 @@ impl Camera {
 
         //internal viewport used to translate between image pixels and ray.
         //Vieport is a rectangular box with the image aspect ratio placed 1 unit away from the origin
-        let focal_length = (self.look_from-self.look_at).length();
         let h = f64::tan(f64::to_radians(self.fov * 0.5));
 
         let viewport_width: f64 = 2.0 * self.focus_dist * h;
@@ -208,141 +174,40 @@ impl Camera {
         let camera_upper_left= self.camera_center - (self.focus_dist * z) - v_w/2.0 - v_h/2.0;
         self.upper_left_pixel = camera_upper_left + self.del_w/2.0 + self.del_h/2.0;
 
-        // self.output = Image::new(self.image_height as u32, self.image_width as u32);
-
         let defocus_radius = self.focus_dist * f64::tan(f64::to_radians(self.defocus_angle / 2.0));
         self.defocus_disk_u = x * defocus_radius;
         self.defocus_disk_v = y * defocus_radius; 
-
-        eprintln!("Viewport: {}x{}", viewport_width, viewport_height);
     }
 
-    fn ray_color(&self,r: &Ray, world: &HittableVec, curr_depth: i32) -> Color {
+    fn ray_color(&self, r: &Ray, bvh: &BVH, primitives: &[Primitive], materials: &MaterialTable, curr_depth: i32) -> Color {
 
         if curr_depth <= 0 {
             return Color::default();
-        } 
-
-        // runs a ray trace to find the closest intersection for a given ray. Returns a bool and the Record of the intersection
-        let res = world.ray_hit(r, 0.001, f64::INFINITY);
-        // eprintln!("{:?}", res);
-
-        match res {            
-            Some(x) => {
-                let res = x.material.scatter(r, &x);
-                match res {
-                    Some((color, scattered_ray)) => {
-                        return color * self.ray_color(&scattered_ray, world, curr_depth - 1)
-                    }
-                    None => {
-                        x.material.emit()
-                    }
-                }
-            },
-            None => {
-                // return Color::default();
-                let unit: Vec3 = r.direction().unit();
-                let a = (unit.y() + 1.0) * 0.5;
-                //lerp between Blue and White to create sky
-                (1.0 - a) * Color::new(1.0,1.0,1.0) + a * Color::new(0.5, 0.7,  1.0)
-
-            }
         }
-        // Only if an intersection is identified, calculate the color of the object
-        // if intersect {
-        //     let scatter: bool;
-        //     let color: Color;
-        //     let scattered_ray: Ray;
-
-        //     (scatter, color, scattered_ray) = intersection_record.material.scatter(r, &intersection_record);
-
-        //     if scatter {
-        //         return color * self.ray_color(&scattered_ray, world, curr_depth - 1);
-        //     } else {
-        //         return color;
-        //     }
-
-            // return Color::default();
-
-
-            // let record:Record = res.1;
-            // let random_dir = Vec3::vec_in_unit_hemisphere(record.normal) + record.normal;
-            // let ray_bounce = Ray::new(record.point, random_dir);
-            // eprintln!("About to fire bounce");
-
-            // Color based on the normal vector at the point of intersection.
-            // [-1,1] -> [0,1] transformation for x,y,z coordinates
-            // return (Color::new(record.normal.x(), record.normal.y(), record.normal.z()) + Vec3::new(1.0, 1.0, 1.0)) * 0.5;
-        // }
-    
-        //Background color gradient, uses the y component of the ray to create a vertical gradient
-        
-    }
 
-    fn ray_color2(&self,r: &Ray, bvh: &BVHNode, primitives: &Vec<Primitive>, curr_depth: i32) -> Color {
+        // runs a ray trace to find the closest intersection for a given ray
+        let res = bvh.ray_hit(primitives, materials, r, 0.001, f64::INFINITY);
 
-        if curr_depth <= 0 {
-            return Color::default();
-        } 
-
-        // runs a ray trace to find the closest intersection for a given ray. Returns a bool and the Record of the intersection
-        let res = bvh.ray_hit(primitives, r, 0.001, f64::INFINITY);
-        // .ray_hit(r, 0.001, f64::INFINITY);
-        // eprintln!("{:?}", res);
-
-        match res {            
+        match res {
             Some(x) => {
-                let res = x.material.scatter(r, &x);
+                let material = &materials[x.material];
+                let res = material.scatter(r, &x);
                 match res {
                     Some((color, scattered_ray)) => {
-                        return color * self.ray_color2(&scattered_ray, bvh, primitives, curr_depth - 1)
+                        color * self.ray_color(&scattered_ray, bvh, primitives, materials, curr_depth - 1)
                     }
                     None => {
-                        x.material.emit()
+                        material.emit(&x)
                     }
                 }
             },
             None => {
-                // return Color::new(1.0, 0.4, 0.3);
-                // return Color::new(0.1, 0.1, 0.1);
-                return Color::default();
-                let unit: Vec3 = r.direction().unit();
-                let a = (unit.y() + 1.0) * 0.5;
-                //lerp between Blue and White to create sky
-                (1.0 - a) * Color::new(1.0,1.0,1.0) + a * Color::new(0.5, 0.7,  1.0)
-                // (1.0 - a) * Color::new(0.98,0.38,0.0) + a * Color::new(0.62, 0.54,  0.77)
-
-
+                // let unit: Vec3 = r.direction().unit();
+                // let a = (unit.y() + 1.0) * 0.5;
+                // //lerp between Blue and White to create sky
+                // (1.0 - a) * Color::new(1.0,1.0,1.0) + a * Color::new(0.5, 0.7,  1.0)
+                Color::default()
             }
         }
-        // Only if an intersection is identified, calculate the color of the object
-        // if intersect {
-        //     let scatter: bool;
-        //     let color: Color;
-        //     let scattered_ray: Ray;
-
-        //     (scatter, color, scattered_ray) = intersection_record.material.scatter(r, &intersection_record);
-
-        //     if scatter {
-        //         return color * self.ray_color(&scattered_ray, world, curr_depth - 1);
-        //     } else {
-        //         return color;
-        //     }
-
-            // return Color::default();
-
-
-            // let record:Record = res.1;
-            // let random_dir = Vec3::vec_in_unit_hemisphere(record.normal) + record.normal;
-            // let ray_bounce = Ray::new(record.point, random_dir);
-            // eprintln!("About to fire bounce");
-
-            // Color based on the normal vector at the point of intersection.
-            // [-1,1] -> [0,1] transformation for x,y,z coordinates
-            // return (Color::new(record.normal.x(), record.normal.y(), record.normal.z()) + Vec3::new(1.0, 1.0, 1.0)) * 0.5;
-        // }
-    
-        //Background color gradient, uses the y component of the ray to create a vertical gradient
-        
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,171 @@
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, transform::Transform, cylinder::{around_y, cap_uv}};
+
+// A cone with a capped circular base at base and its tip at base + axis. Like Cylinder it is
+// intersected in a local frame where it stands on the origin along +y.
+#[derive(Debug, Clone)]
+pub struct Cone {
+    radius: f64,
+    height: f64,
+    to_world: Transform,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Cone {
+    pub fn new(base: Point3, axis: Vec3, radius: f64, material: MaterialId) -> Self {
+        let height = axis.length();
+        let to_world = Transform::orient(base, axis);
+        let local = AABB::new(Vec3::new(-radius, 0.0, -radius), Vec3::new(radius, height, radius));
+        Cone { radius, height, to_world, material, bounds: to_world.bounds(&local) }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let local = self.to_world.inverse().ray(ray);
+        let (o, d) = (local.origin(), local.direction());
+
+        let mut closest: Option<(f64, Vec3)> = None;
+        let mut consider = |t: f64, normal: Vec3| {
+            if t >= t_min && t <= t_max && closest.is_none_or(|(c, _)| t < c) {
+                closest = Some((t, normal));
+            }
+        };
+
+        // x^2 + z^2 = k^2 (h - y)^2, the radius shrinks linearly from the base to the tip
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let q = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z() + k2 * q * d.y());
+        let c = o.x() * o.x() + o.z() * o.z() - k2 * q * q;
+        let roots = if a.abs() < 1e-12 {
+            // rays parallel to the slope cross it once
+            if b != 0.0 { vec![-c / b] } else { vec![] }
+        } else {
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 { vec![] } else { vec![(-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a)] }
+        };
+        for t in roots {
+            let p = local.ray_at(t);
+            if (0.0..=self.height).contains(&p.y()) {
+                let normal = Vec3::new(p.x(), k2 * (self.height - p.y()), p.z());
+                // the tip has no well defined normal, point it up the axis
+                let normal = if normal.length() > 0.0 { normal.unit() } else { Vec3::new(0.0, 1.0, 0.0) };
+                consider(t, normal);
+            }
+        }
+
+        if d.y() != 0.0 {
+            let t = -o.y() / d.y();
+            let p = local.ray_at(t);
+            if p.x() * p.x() + p.z() * p.z() <= self.radius * self.radius {
+                consider(t, Vec3::new(0.0, -1.0, 0.0));
+            }
+        }
+
+        let (t, normal) = closest?;
+        let p = local.ray_at(t);
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.object_point = p;
+        return_record.material = self.material;
+        if normal.y() == -1.0 {
+            (return_record.u, return_record.v, return_record.tangent, return_record.bitangent) = cap_uv(p, self.radius, -1.0);
+        } else {
+            (return_record.u, return_record.v) = (around_y(p), p.y() / self.height);
+            // around the axis, and up the slope towards the tip
+            let tangent = Vec3::new(p.z(), 0.0, -p.x());
+            let (tangent, bitangent) = if tangent.length() > 0.0 {
+                let tangent = tangent.unit();
+                (tangent, Vec3::cross(normal, tangent))
+            } else {
+                Vec3::basis(normal)
+            };
+            (return_record.tangent, return_record.bitangent) = (tangent, bitangent);
+        }
+        return_record.calculate_normal(&local, normal);
+        return_record.transform(&self.to_world, ray);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.to_world.point(Vec3::new(0.0, self.height / 4.0, 0.0))
+    }
+}
+
+impl Hittable for Cone {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Cone::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Cone::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // base radius 1 on the origin, tip at y = 2
+    fn cone() -> Cone {
+        Cone::new(Point3::default(), Vec3::new(0.0, 2.0, 0.0), 1.0, MaterialId::EMPTY)
+    }
+
+    fn hit(origin: Point3, direction: Vec3) -> Record {
+        cone().ray_hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).expect("ray should hit the cone")
+    }
+
+    fn assert_vec(v: Vec3, expected: Vec3) {
+        assert!((v - expected).length() < 1e-9, "{:?}, expected {:?}", v, expected);
+    }
+
+    #[test]
+    fn base_cap_faces_down() {
+        let cap = hit(Point3::new(0.2, -5.0, 0.3), Vec3::new(0.0, 1.0, 0.0));
+        assert!((cap.t - 5.0).abs() < 1e-9, "t = {}", cap.t);
+        assert_vec(cap.normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(cap.outside_face);
+    }
+
+    #[test]
+    fn side_normals_lean_up_the_slope() {
+        // the radius is 0.5 halfway up
+        let side = hit(Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!((side.t - 4.5).abs() < 1e-9, "t = {}", side.t);
+        assert_vec(side.normal, Vec3::new(2.0, 1.0, 0.0).unit());
+        assert!((side.v - 0.5).abs() < 1e-9);
+        assert!(Vec3::dot(side.tangent, side.normal).abs() < 1e-9 && Vec3::dot(side.bitangent, side.normal).abs() < 1e-9);
+        // past the side of the tip
+        let ray = Ray::new(Point3::new(5.0, 1.8, 0.0), Vec3::new(-1.0, 0.0, 0.3));
+        assert!(cone().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ray_down_the_axis_hits_the_tip() {
+        let tip = hit(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((tip.t - 3.0).abs() < 1e-9, "t = {}", tip.t);
+        assert_vec(tip.normal, Vec3::new(0.0, 1.0, 0.0));
+        for v in [tip.tangent, tip.bitangent] {
+            assert!((v.length() - 1.0).abs() < 1e-9 && Vec3::dot(v, tip.normal).abs() < 1e-9, "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn rays_from_inside_hit_the_far_side() {
+        let side = hit(Point3::new(0.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((side.t - 0.75).abs() < 1e-9, "t = {}", side.t);
+        assert!(!side.outside_face);
+        assert_vec(side.normal, -Vec3::new(2.0, 1.0, 0.0).unit());
+        let cap = hit(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((cap.t - 0.5).abs() < 1e-9, "t = {}", cap.t);
+        assert_vec(cap.normal, Vec3::new(0.0, 1.0, 0.0));
+    }
+}
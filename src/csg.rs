@@ -0,0 +1,204 @@
+use crate::{vec3::Point3, hittable::Record, hittable2::Primitive, material::MaterialTable, ray::Ray, aabb::AABB};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    // left minus right
+    Difference,
+}
+
+impl CsgOp {
+    // whether a point inside (or outside) each operand is inside the result
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Boolean combination of two closed primitives, e.g. a sphere with a box cut out of it. Operands
+// can be Spheres, the analytic shapes or other Csg nodes; open surfaces such as quads have no
+// inside and give odd results.
+//
+// Along a ray each operand is entered and left at its surfaces. The node walks both operands'
+// hits in order and stops at the first one where the ray crosses into or out of the result, so
+// the record (material, uvs, normal) is that operand's own.
+#[derive(Clone)]
+pub struct Csg {
+    op: CsgOp,
+    left: Box<Primitive>,
+    right: Box<Primitive>,
+    pub bounds: AABB,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Primitive, right: Primitive) -> Self {
+        let (l, r) = (left.bounds(), right.bounds());
+        let bounds = match op {
+            CsgOp::Union => {
+                let mut bounds = *l;
+                bounds.join(r);
+                bounds
+            }
+            // the overlap of the two boxes, which may be empty
+            CsgOp::Intersection => AABB::new(
+                Point3 { v: [0, 1, 2].map(|i| l.min.v[i].max(r.min.v[i])) },
+                Point3 { v: [0, 1, 2].map(|i| l.max.v[i].min(r.max.v[i])) },
+            ),
+            // cutting away never makes the left operand bigger
+            CsgOp::Difference => *l,
+        };
+        Csg { op, left: Box::new(left), right: Box::new(right), bounds }
+    }
+
+    pub fn op(&self) -> CsgOp {
+        self.op
+    }
+
+    pub fn ray_hit(&self, materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+
+        // Next surface of each operand past t_min. Hits are looked for all the way along the ray
+        // since the result boundary can come after any number of operand surfaces, but never
+        // further than needed. An operand whose first hit is an exit starts out containing the ray.
+        let mut left = self.left.ray_hit(materials, ray, t_min, f64::INFINITY);
+        let mut right = self.right.ray_hit(materials, ray, t_min, f64::INFINITY);
+        let mut in_left = left.as_ref().is_some_and(|r| !r.outside_face);
+        let mut in_right = right.as_ref().is_some_and(|r| !r.outside_face);
+        let inside = self.op.contains(in_left, in_right);
+
+        loop {
+            let from_left = match (&left, &right) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(l), Some(r)) => l.t <= r.t,
+            };
+            let (next, operand) = if from_left { (&mut left, &self.left) } else { (&mut right, &self.right) };
+            let record = next.take()?;
+            if record.t > t_max {
+                return None;
+            }
+
+            // front faces enter an operand and back faces leave it
+            if from_left {
+                in_left = record.outside_face;
+            } else {
+                in_right = record.outside_face;
+            }
+            let now = self.op.contains(in_left, in_right);
+            if now != inside {
+                return Some(Csg::boundary(record, now));
+            }
+            *next = operand.ray_hit(materials, ray, record.t.next_up(), f64::INFINITY);
+        }
+    }
+
+    // An operand's hit as a surface of the result, entering it or not. The record's normal already
+    // faces the ray; only where the outside of the result is the inside of the operand (the cut
+    // surfaces of a difference) the face flips, and the bitangent with it so the tangent frame
+    // stays right handed around the new outward normal.
+    fn boundary(mut record: Record, entering: bool) -> Record {
+        if record.outside_face != entering {
+            record.outside_face = entering;
+            record.bitangent = -record.bitangent;
+        }
+        record
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cuboid::Cuboid, material::MaterialId, sphere::Sphere, vec3::Vec3};
+
+    // a unit sphere with everything above z = 0.5 cut away
+    fn capped_sphere() -> Csg {
+        let sphere = Primitive::Sphere(Sphere::new(Point3::default(), 1.0, MaterialId::EMPTY));
+        let cut = Primitive::Cuboid(Cuboid::new(Point3::new(-2.0, -2.0, 0.5), Point3::new(2.0, 2.0, 2.0), MaterialId::EMPTY));
+        Csg::new(CsgOp::Difference, sphere, cut)
+    }
+
+    fn hit(csg: &Csg, origin: Point3, direction: Vec3) -> Record {
+        csg.ray_hit(&MaterialTable::new(), &Ray::new(origin, direction), 0.001, f64::INFINITY).expect("ray should hit")
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    // the normal pointing out of the result, and the one the tangent frame turns around
+    fn check_frame(record: &Record, outward: Vec3) {
+        assert_close(if record.outside_face { record.normal } else { -record.normal }, outward);
+        assert_close(Vec3::cross(record.tangent, record.bitangent), outward);
+    }
+
+    #[test]
+    fn difference_enters_on_the_cut_face() {
+        // passes through the box and the sphere's cap, both cut away, and enters at the flat face
+        let record = hit(&capped_sphere(), Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((record.t - 4.5).abs() < 1e-9, "t = {}", record.t);
+        assert!(record.outside_face);
+        assert_close(record.normal, Vec3::new(0.0, 0.0, 1.0));
+        check_frame(&record, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn difference_keeps_the_uncut_surface() {
+        let record = hit(&capped_sphere(), Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((record.t - 4.0).abs() < 1e-9, "t = {}", record.t);
+        assert!(record.outside_face);
+        assert_close(record.normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn ray_starting_inside_the_result() {
+        // inside the sphere and outside the box, so inside the result: leaves it through the cut face
+        let record = hit(&capped_sphere(), Point3::default(), Vec3::new(0.0, 0.0, 1.0));
+        assert!((record.t - 0.5).abs() < 1e-9, "t = {}", record.t);
+        assert!(!record.outside_face);
+        assert_close(record.normal, Vec3::new(0.0, 0.0, -1.0));
+        check_frame(&record, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn ray_starting_inside_the_cut_operand() {
+        // inside both operands, so outside the result, and enters it through the cut face
+        let record = hit(&capped_sphere(), Point3::new(0.0, 0.0, 0.8), Vec3::new(0.0, 0.0, -1.0));
+        assert!((record.t - 0.3).abs() < 1e-9, "t = {}", record.t);
+        assert!(record.outside_face);
+        check_frame(&record, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn ray_through_the_removed_part_misses() {
+        let csg = capped_sphere();
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.8), Vec3::new(1.0, 0.0, 0.0));
+        assert!(csg.ray_hit(&MaterialTable::new(), &ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn intersection_and_union() {
+        let a = Primitive::Sphere(Sphere::new(Point3::new(-0.5, 0.0, 0.0), 1.0, MaterialId::EMPTY));
+        let b = Primitive::Sphere(Sphere::new(Point3::new(0.5, 0.0, 0.0), 1.0, MaterialId::EMPTY));
+        let ray = (Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        // the lens between the spheres starts where the right one does
+        let lens = Csg::new(CsgOp::Intersection, a.clone(), b.clone());
+        assert!((hit(&lens, ray.0, ray.1).t - 4.5).abs() < 1e-9);
+        // from inside the left sphere the union is only left at the far side of the right one
+        let union = Csg::new(CsgOp::Union, a, b);
+        assert!((hit(&union, ray.0, ray.1).t - 3.5).abs() < 1e-9);
+        let record = hit(&union, Point3::new(-0.5, 0.0, 0.0), ray.1);
+        assert!((record.t - 2.0).abs() < 1e-9 && !record.outside_face, "t = {}", record.t);
+    }
+}
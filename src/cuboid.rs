@@ -0,0 +1,167 @@
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, transform::Transform};
+
+// A box, either axis aligned or oriented by a rotation around its centre. Every face gets
+// uvs from 0 to 1, upright when seen from outside.
+#[derive(Debug, Clone)]
+pub struct Cuboid {
+    // half the size along each local axis, the box is centred on the local origin
+    half: Vec3,
+    to_world: Transform,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Cuboid {
+    pub fn new(min: Point3, max: Point3, material: MaterialId) -> Self {
+        Cuboid::oriented((min + max) * 0.5, max - min, &Transform::identity(), material)
+    }
+
+    // a box of the given size around center, turned by rotation
+    pub fn oriented(center: Point3, size: Vec3, rotation: &Transform, material: MaterialId) -> Self {
+        let half = Vec3::new(size.x().abs(), size.y().abs(), size.z().abs()) * 0.5;
+        let to_world = rotation.then(&Transform::translate(center));
+        let bounds = to_world.bounds(&AABB::new(-half, half));
+        Cuboid { half, to_world, material, bounds }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let local = self.to_world.inverse().ray(ray);
+        let (o, d) = (local.origin(), local.direction());
+
+        // slab test, remembering which axis the ray enters and leaves through
+        let (mut enter, mut exit) = (f64::NEG_INFINITY, f64::INFINITY);
+        let (mut enter_axis, mut exit_axis) = (0, 0);
+        for i in 0..3 {
+            let inv = 1.0 / d.v[i];
+            let t0 = (-self.half.v[i] - o.v[i]) * inv;
+            let t1 = (self.half.v[i] - o.v[i]) * inv;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if near > enter {
+                (enter, enter_axis) = (near, i);
+            }
+            if far < exit {
+                (exit, exit_axis) = (far, i);
+            }
+        }
+        if enter > exit {
+            return None;
+        }
+        let (t, axis) = if enter >= t_min && enter <= t_max {
+            (enter, enter_axis)
+        } else if exit >= t_min && exit <= t_max {
+            (exit, exit_axis)
+        } else {
+            return None;
+        };
+
+        let p = local.ray_at(t);
+        let mut normal = Vec3::default();
+        normal.v[axis] = p.v[axis].signum();
+        let (tangent, bitangent) = face_frame(axis, normal.v[axis]);
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.object_point = p;
+        return_record.material = self.material;
+        let extent = |v: Vec3| Vec3::dot(Vec3::new(v.x().abs(), v.y().abs(), v.z().abs()), self.half);
+        return_record.u = 0.5 + Vec3::dot(p, tangent) / (2.0 * extent(tangent));
+        return_record.v = 0.5 + Vec3::dot(p, bitangent) / (2.0 * extent(bitangent));
+        (return_record.tangent, return_record.bitangent) = (tangent, bitangent);
+        return_record.calculate_normal(&local, normal);
+        return_record.transform(&self.to_world, ray);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.to_world.point(Vec3::default())
+    }
+}
+
+// (tangent, bitangent) of the face on the side sign of axis. Side faces keep v pointing up,
+// the top and bottom keep u along +x
+fn face_frame(axis: usize, sign: f64) -> (Vec3, Vec3) {
+    match axis {
+        0 => (Vec3::new(0.0, 0.0, -sign), Vec3::new(0.0, 1.0, 0.0)),
+        1 => (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -sign)),
+        _ => (Vec3::new(sign, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+    }
+}
+
+impl Hittable for Cuboid {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Cuboid::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Cuboid::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(cuboid: &Cuboid, origin: Point3, direction: Vec3) -> Record {
+        cuboid.ray_hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).expect("ray should hit the box")
+    }
+
+    fn assert_vec(v: Vec3, expected: Vec3) {
+        assert!((v - expected).length() < 1e-9, "{:?}, expected {:?}", v, expected);
+    }
+
+    #[test]
+    fn each_face_has_its_own_normal() {
+        let cuboid = Cuboid::new(Point3::new(-1.0, -2.0, -3.0), Point3::new(1.0, 2.0, 3.0), MaterialId::EMPTY);
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut normal = Vec3::default();
+                normal.v[axis] = sign;
+                let record = hit(&cuboid, 10.0 * normal, -normal);
+                assert_vec(record.normal, normal);
+                assert!((record.t - (10.0 - cuboid.half.v[axis])).abs() < 1e-9, "t = {}", record.t);
+                assert!(record.outside_face);
+                // u, v across the face and a frame that matches the normal
+                assert!((record.u - 0.5).abs() < 1e-9 && (record.v - 0.5).abs() < 1e-9);
+                assert_vec(Vec3::cross(record.tangent, record.bitangent), normal);
+            }
+        }
+    }
+
+    #[test]
+    fn ray_from_inside_hits_the_far_face() {
+        let cuboid = Cuboid::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0), MaterialId::EMPTY);
+        let record = hit(&cuboid, Point3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((record.t - 0.5).abs() < 1e-9, "t = {}", record.t);
+        assert_vec(record.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(!record.outside_face);
+    }
+
+    #[test]
+    fn oriented_box_turns_its_faces_and_bounds() {
+        let rotation = Transform::rotate_y(45.0);
+        let cuboid = Cuboid::oriented(Point3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 2.0, 2.0), &rotation, MaterialId::EMPTY);
+        let r = f64::sqrt(2.0);
+        assert_vec(cuboid.bounds.min, Vec3::new(-r, 0.0, -r));
+        assert_vec(cuboid.bounds.max, Vec3::new(r, 2.0, r));
+        // straight at the middle of a turned side face
+        let normal = rotation.vector(Vec3::new(1.0, 0.0, 0.0));
+        let center = Point3::new(0.0, 1.0, 0.0);
+        let record = hit(&cuboid, center + 5.0 * normal, -normal);
+        assert!((record.t - 4.0).abs() < 1e-9, "t = {}", record.t);
+        assert_vec(record.normal, normal);
+        // the top stays on top
+        let record = hit(&cuboid, Point3::new(0.2, 5.0, 0.1), Vec3::new(0.0, -1.0, 0.0));
+        assert!((record.t - 3.0).abs() < 1e-9, "t = {}", record.t);
+        assert_vec(record.normal, Vec3::new(0.0, 1.0, 0.0));
+        // the corner of the bounds is outside the turned box
+        let ray = Ray::new(Point3::new(1.3, 5.0, 1.3), Vec3::new(0.0, -1.0, 0.0));
+        assert!(cuboid.ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+}
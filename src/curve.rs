@@ -0,0 +1,389 @@
+// Cubic Bézier curves with a width that changes from one end to the other, for hair, fur and
+// grass. A curve is intersected as a ribbon that always faces the ray: Flat ribbons shade like
+// the flat strip they are, Cylinder ones bend their normal across the width to look round.
+//
+// The intersection follows pbrt: the control points are moved into a frame where the ray runs
+// down the z axis, and the curve is split in halves until each piece is nearly straight, skipping
+// pieces whose bounds miss the ray. u runs along the strand and v across it.
+
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, mesh::MeshError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurveMode {
+    Flat,
+    #[default]
+    Cylinder,
+}
+
+#[derive(Debug, Clone)]
+pub struct Curve {
+    points: [Point3; 4],
+    // at the first and the last control point
+    width: [f64; 2],
+    // the part of the whole strand this curve covers, for u
+    range: [f64; 2],
+    mode: CurveMode,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+// the closest crossing found so far, in the ray's frame
+struct Hit {
+    z: f64,
+    // along the curve, from 0 to 1
+    w: f64,
+    // signed distance from the middle of the ribbon as a fraction of its width, -0.5 to 0.5
+    side: f64,
+}
+
+impl Curve {
+    // both widths have to be positive and finite, strand files check this when they load
+    pub fn new(points: [Point3; 4], width: [f64; 2], mode: CurveMode, material: MaterialId) -> Self {
+        Curve::piece(points, width, [0.0, 1.0], mode, material)
+    }
+
+    fn piece(points: [Point3; 4], width: [f64; 2], range: [f64; 2], mode: CurveMode, material: MaterialId) -> Self {
+        // the extremes of the middle line, padded by the wider end
+        let mut bounds = AABB::default();
+        bounds.add(points[0]);
+        bounds.add(points[3]);
+        for axis in 0..3 {
+            for w in extrema(points.map(|p| p.v[axis])) {
+                bounds.add(bezier(&points, w).0);
+            }
+        }
+        let pad = 0.5 * width[0].max(width[1]);
+        let pad = Vec3::new(pad, pad, pad);
+        let bounds = AABB::new(bounds.min - pad, bounds.max + pad);
+        Curve { points, width, range, mode, material, bounds }
+    }
+
+    // The curve cut into pieces of equal parameter length. Long curves get much tighter bounds
+    // this way, and the BVH can skip most of them.
+    pub fn split(&self, pieces: usize) -> Vec<Curve> {
+        (0..pieces).map(|i| {
+            let (a, b) = (i as f64 / pieces as f64, (i + 1) as f64 / pieces as f64);
+            let points = bezier_range(&self.points, a, b);
+            let width = [lerp(a, self.width), lerp(b, self.width)];
+            let range = [lerp(a, self.range), lerp(b, self.range)];
+            Curve::piece(points, width, range, self.mode, self.material)
+        }).collect()
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let length = ray.direction().length();
+        let dir = ray.direction() / length;
+        let (x_axis, y_axis) = Vec3::basis(dir);
+        let cp = self.points.map(|p| {
+            let q = p - ray.origin();
+            Vec3::new(Vec3::dot(q, x_axis), Vec3::dot(q, y_axis), Vec3::dot(q, dir))
+        });
+
+        // split until the pieces are within 5% of the width of a straight line
+        let l0 = (0..2).map(|i| {
+            let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+            d.x().abs().max(d.y().abs()).max(d.z().abs())
+        }).fold(0.0, f64::max);
+        let eps = 0.05 * self.width[0].max(self.width[1]);
+        let depth = if l0 > 0.0 && eps > 0.0 {
+            (f64::log2(f64::sqrt(2.0) * 6.0 * l0 / (8.0 * eps)) / 2.0).clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+
+        let mut best = None;
+        let mut z_max = t_max * length;
+        self.intersect(&cp, 0.0, 1.0, depth, t_min * length, &mut z_max, &mut best);
+        let hit = best?;
+
+        let t = hit.z / length;
+        let (center, tangent) = bezier(&self.points, hit.w);
+        // the derivative vanishes at cusps and ends with doubled control points, the chord or the
+        // middle leg of the control polygon gives the direction there; a curve folded onto a single
+        // point has none, and any will do
+        let tangent = [tangent, self.points[3] - self.points[0], self.points[2] - self.points[1]].into_iter()
+            .find(|t| t.length() > 0.0)
+            .map_or(Vec3::new(0.0, 1.0, 0.0), |t| t.unit());
+        let point = ray.ray_at(t);
+
+        // the ribbon turns to face the ray
+        let facing = dir - Vec3::dot(dir, tangent) * tangent;
+        let flat = if facing.length() > 0.0 { -facing.unit() } else { Vec3::basis(tangent).0 };
+        let normal = match self.mode {
+            CurveMode::Flat => flat,
+            CurveMode::Cylinder => {
+                // tilt towards the edge the hit is on, as far as a round strand would
+                let across = Vec3::cross(tangent, flat);
+                let sign = Vec3::dot(point - center, across).signum();
+                let sin = (2.0 * hit.side.abs()).min(1.0) * sign;
+                (f64::sqrt(1.0 - sin * sin) * flat + sin * across).unit()
+            }
+        };
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.point = point;
+        return_record.object_point = point;
+        return_record.material = self.material;
+        return_record.u = lerp(hit.w, self.range);
+        return_record.v = hit.side + 0.5;
+        return_record.calculate_normal(ray, normal);
+        (return_record.tangent, return_record.bitangent) = (tangent, Vec3::cross(normal, tangent));
+        Some(return_record)
+    }
+
+    // cp is the piece from w0 to w1 in the ray's frame
+    #[allow(clippy::too_many_arguments)]
+    fn intersect(&self, cp: &[Vec3; 4], w0: f64, w1: f64, depth: u32, z_min: f64, z_max: &mut f64, best: &mut Option<Hit>) {
+        let half = 0.5 * lerp(w0, self.width).max(lerp(w1, self.width));
+        let mut bounds = AABB::default();
+        for p in cp {
+            bounds.add(*p);
+        }
+        if bounds.min.x() - half > 0.0 || bounds.max.x() + half < 0.0
+            || bounds.min.y() - half > 0.0 || bounds.max.y() + half < 0.0
+            || bounds.max.z() + half < z_min || bounds.min.z() - half > *z_max {
+            return;
+        }
+
+        if depth > 0 {
+            let mid = 0.5 * (w0 + w1);
+            let (left, right) = split_at(cp, 0.5);
+            self.intersect(&left, w0, mid, depth - 1, z_min, z_max, best);
+            self.intersect(&right, mid, w1, depth - 1, z_min, z_max, best);
+            return;
+        }
+
+        // the ray has to pass between the lines through the ends, at right angles to the piece
+        let edge = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        if edge < 0.0 {
+            return;
+        }
+        let edge = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if edge < 0.0 {
+            return;
+        }
+
+        // closest point to the ray on the piece taken as a straight line
+        let (sx, sy) = (cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let denom = sx * sx + sy * sy;
+        if denom == 0.0 {
+            return;
+        }
+        let s = ((-cp[0].x() * sx - cp[0].y() * sy) / denom).clamp(0.0, 1.0);
+        let w = w0 + s * (w1 - w0);
+        let width = lerp(w, self.width);
+        let (p, dp) = bezier(cp, s);
+        let dist2 = p.x() * p.x() + p.y() * p.y();
+        if dist2 > 0.25 * width * width || p.z() < z_min || p.z() > *z_max {
+            return;
+        }
+
+        let dist = dist2.sqrt();
+        let left = dp.x() * -p.y() + p.x() * dp.y() > 0.0;
+        *z_max = p.z();
+        *best = Some(Hit { z: p.z(), w, side: if left { dist / width } else { -dist / width } });
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
+
+fn lerp(t: f64, [a, b]: [f64; 2]) -> f64 {
+    a + t * (b - a)
+}
+
+// point and derivative at t
+fn bezier(cp: &[Vec3; 4], t: f64) -> (Vec3, Vec3) {
+    let s = 1.0 - t;
+    let point = s * s * s * cp[0] + 3.0 * s * s * t * cp[1] + 3.0 * s * t * t * cp[2] + t * t * t * cp[3];
+    let derivative = 3.0 * (s * s * (cp[1] - cp[0]) + 2.0 * s * t * (cp[2] - cp[1]) + t * t * (cp[3] - cp[2]));
+    (point, derivative)
+}
+
+// de Casteljau at t, the two halves share the point on the curve
+fn split_at(cp: &[Vec3; 4], t: f64) -> ([Vec3; 4], [Vec3; 4]) {
+    let mix = |a: Vec3, b: Vec3| a + t * (b - a);
+    let (a, b, c) = (mix(cp[0], cp[1]), mix(cp[1], cp[2]), mix(cp[2], cp[3]));
+    let (d, e) = (mix(a, b), mix(b, c));
+    let f = mix(d, e);
+    ([cp[0], a, d, f], [f, e, c, cp[3]])
+}
+
+// the control points of the part of the curve from a to b, with b > 0
+fn bezier_range(cp: &[Vec3; 4], a: f64, b: f64) -> [Vec3; 4] {
+    let (head, _) = split_at(cp, b);
+    split_at(&head, a / b).1
+}
+
+// parameters in (0, 1) where one coordinate of the curve turns around
+fn extrema([p0, p1, p2, p3]: [f64; 4]) -> Vec<f64> {
+    // the derivative divided by 3 is a t^2 + b t + c
+    let a = -p0 + 3.0 * p1 - 3.0 * p2 + p3;
+    let b = 2.0 * (p0 - 2.0 * p1 + p2);
+    let c = p1 - p0;
+    let roots = if a.abs() < 1e-12 {
+        if b != 0.0 { vec![-c / b] } else { vec![] }
+    } else {
+        let disc = b * b - 4.0 * a * c;
+        if disc < 0.0 { vec![] } else { vec![(-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a)] }
+    };
+    roots.into_iter().filter(|t| *t > 0.0 && *t < 1.0).collect()
+}
+
+impl Hittable for Curve {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Curve::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Curve::centroid(self)
+    }
+}
+
+// Strand files have one strand per line: the width at the root and at the tip (both positive), then the points
+// the strand passes through, at least two. Blank lines and lines starting with # are skipped.
+//
+// # a blade of grass
+// 0.02 0.002  0 0 0  0 0.3 0.05  0.05 0.6 0.2  0.15 0.8 0.4
+//
+// The strand runs smoothly through its points (Catmull-Rom), one curve between each pair.
+pub fn load_strands(path: impl AsRef<Path>, mode: CurveMode, material: MaterialId) -> Result<Vec<Curve>, MeshError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|_| MeshError::MissingFile(path.to_path_buf()))?;
+    parse_strands(&text, mode, material).map_err(|msg| MeshError::Parse(path.to_path_buf(), msg))
+}
+
+// the strands of a strand file already read into text
+pub fn parse_strands(text: &str, mode: CurveMode, material: MaterialId) -> Result<Vec<Curve>, String> {
+    let mut curves = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |msg: &str| format!("line {}: {}", number + 1, msg);
+        let values = line.split_whitespace()
+            .map(|x| x.parse::<f64>().map_err(|_| error(&format!("'{x}' is not a number"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.len() < 8 || (values.len() - 2) % 3 != 0 {
+            return Err(error("expected two widths and at least two points"));
+        }
+        let width = [values[0], values[1]];
+        if let Some(w) = width.iter().find(|w| !(**w > 0.0 && w.is_finite())) {
+            return Err(error(&format!("width {} must be positive", w)));
+        }
+        let mut points: Vec<Point3> = values[2..].chunks_exact(3).map(|p| Point3::new(p[0], p[1], p[2])).collect();
+        // repeated points would leave segments without a direction
+        points.dedup_by(|a, b| a.v == b.v);
+        if points.len() < 2 {
+            return Err(error("strand has zero length"));
+        }
+
+        // Catmull-Rom tangents, one sided at the ends
+        let n = points.len();
+        let tangent = |i: usize| match i {
+            0 => points[1] - points[0],
+            i if i == n - 1 => points[i] - points[i - 1],
+            i => 0.5 * (points[i + 1] - points[i - 1]),
+        };
+        for i in 0..n - 1 {
+            let (a, b) = (points[i], points[i + 1]);
+            let control = [a, a + tangent(i) / 3.0, b - tangent(i + 1) / 3.0, b];
+            let range = [i as f64 / (n - 1) as f64, (i + 1) as f64 / (n - 1) as f64];
+            let curve = Curve::piece(control, range.map(|u| lerp(u, width)), range, mode, material);
+            // long segments of thin strands get a box that is mostly empty, pieces fit tighter
+            let length = (control[1] - control[0]).length() + (control[2] - control[1]).length() + (control[3] - control[2]).length();
+            let pieces = (length / (16.0 * width[0].max(width[1]))).ceil().clamp(1.0, 8.0) as usize;
+            if pieces > 1 {
+                curves.extend(curve.split(pieces));
+            } else {
+                curves.push(curve);
+            }
+        }
+    }
+    Ok(curves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // straight up the y axis from -1 to 1, 0.2 wide
+    fn ribbon(mode: CurveMode) -> Curve {
+        let points = [Point3::new(0.0, -1.0, 0.0), Point3::new(0.0, -1.0 / 3.0, 0.0), Point3::new(0.0, 1.0 / 3.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        Curve::new(points, [0.2, 0.2], mode, MaterialId::EMPTY)
+    }
+
+    #[test]
+    fn flat_ribbon_faces_the_ray() {
+        let ray = Ray::new(Point3::new(0.05, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = ribbon(CurveMode::Flat).ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the ribbon");
+        assert!((hit.t - 5.0).abs() < 1e-6, "t = {}", hit.t);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9, "normal = {:?}", hit.normal);
+        assert!((hit.u - 0.75).abs() < 1e-3, "u = {}", hit.u);
+        // a quarter of the width off the middle
+        assert!(((hit.v - 0.5).abs() - 0.25).abs() < 1e-6, "v = {}", hit.v);
+        assert!((hit.tangent - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9, "tangent = {:?}", hit.tangent);
+
+        // the round strand tilts its normal towards the edge instead
+        let hit = ribbon(CurveMode::Cylinder).ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the strand");
+        assert!(hit.normal.x() > 0.4 && hit.normal.z() > 0.0, "normal = {:?}", hit.normal);
+
+        // past the edge the ray misses
+        let ray = Ray::new(Point3::new(0.11, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(ribbon(CurveMode::Flat).ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn zero_length_strands_are_an_error() {
+        let error = parse_strands("0.1 0.1  0 0 0  0 0 0", CurveMode::Flat, MaterialId::EMPTY).err();
+        assert_eq!(error.as_deref(), Some("line 1: strand has zero length"));
+        // repeated points inside a strand are fine, they are dropped
+        let curves = parse_strands("# grass\n0.1 0.1  0 0 0  0 0 0  0 0.5 0", CurveMode::Flat, MaterialId::EMPTY).unwrap();
+        assert_eq!(curves.len(), 1);
+        assert!(parse_strands("0.1 0.1  0 0 0", CurveMode::Flat, MaterialId::EMPTY).is_err());
+        assert!(parse_strands("0.1 0.1  0 0 0  0 x 0", CurveMode::Flat, MaterialId::EMPTY).is_err());
+    }
+
+    #[test]
+    fn widths_must_be_positive() {
+        for (widths, bad) in [("-0.1 0.1", "-0.1"), ("0.1 0", "0"), ("nan 0.1", "NaN"), ("0.1 inf", "inf")] {
+            let text = format!("# fur\n\n{}  0 0 0  0 1 0", widths);
+            let error = parse_strands(&text, CurveMode::Flat, MaterialId::EMPTY).err();
+            assert_eq!(error, Some(format!("line 3: width {} must be positive", bad)));
+        }
+        assert!(parse_strands("0.1 0.001  0 0 0  0 1 0", CurveMode::Flat, MaterialId::EMPTY).is_ok());
+    }
+
+    #[test]
+    fn long_thin_segments_are_split() {
+        // 1 long and 0.01 wide, cut into pieces of at most 16 widths
+        let curves = parse_strands("0.01 0.01  0 0 0  0 1 0", CurveMode::Flat, MaterialId::EMPTY).unwrap();
+        assert_eq!(curves.len(), 7);
+        for (i, curve) in curves.iter().enumerate() {
+            assert!((curve.range[0] - i as f64 / 7.0).abs() < 1e-12 && (curve.range[1] - (i + 1) as f64 / 7.0).abs() < 1e-12);
+            let height = curve.bounds.max.y() - curve.bounds.min.y();
+            assert!(height < 1.0 / 7.0 + 0.011, "piece {} is {} high", i, height);
+        }
+
+        // the pieces still make up the whole strand, u runs along all of it
+        let ray = Ray::new(Point3::new(0.0, 0.3, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hits: Vec<Record> = curves.iter().filter_map(|c| c.ray_hit(&ray, 0.001, f64::INFINITY)).collect();
+        assert!(!hits.is_empty());
+        assert!(hits.iter().all(|h| (h.u - 0.3).abs() < 1e-3), "u = {:?}", hits.iter().map(|h| h.u).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,184 @@
+use std::f64::consts::PI;
+
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, transform::Transform};
+
+// A capped cylinder from base to base + axis. It is intersected in a local frame where it
+// stands on the origin along +y. The side gets u around the axis and v from base to top, the
+// caps are mapped like a Disk.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    radius: f64,
+    height: f64,
+    to_world: Transform,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, axis: Vec3, radius: f64, material: MaterialId) -> Self {
+        let height = axis.length();
+        let to_world = Transform::orient(base, axis);
+        let local = AABB::new(Vec3::new(-radius, 0.0, -radius), Vec3::new(radius, height, radius));
+        Cylinder { radius, height, to_world, material, bounds: to_world.bounds(&local) }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let local = self.to_world.inverse().ray(ray);
+        let (o, d) = (local.origin(), local.direction());
+        let r2 = self.radius * self.radius;
+
+        // closest of the side and the two caps, with its outward normal
+        let mut closest: Option<(f64, Vec3)> = None;
+        let mut consider = |t: f64, normal: Vec3| {
+            if t >= t_min && t <= t_max && closest.is_none_or(|(c, _)| t < c) {
+                closest = Some((t, normal));
+            }
+        };
+
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z());
+        let c = o.x() * o.x() + o.z() * o.z() - r2;
+        let disc = b * b - 4.0 * a * c;
+        if a != 0.0 && disc >= 0.0 {
+            for t in [(-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a)] {
+                let p = local.ray_at(t);
+                if (0.0..=self.height).contains(&p.y()) {
+                    consider(t, Vec3::new(p.x(), 0.0, p.z()) / self.radius);
+                }
+            }
+        }
+
+        if d.y() != 0.0 {
+            for (y, normal) in [(0.0, Vec3::new(0.0, -1.0, 0.0)), (self.height, Vec3::new(0.0, 1.0, 0.0))] {
+                let t = (y - o.y()) / d.y();
+                let p = local.ray_at(t);
+                if p.x() * p.x() + p.z() * p.z() <= r2 {
+                    consider(t, normal);
+                }
+            }
+        }
+
+        let (t, normal) = closest?;
+        let p = local.ray_at(t);
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.object_point = p;
+        return_record.material = self.material;
+        if normal.y() == 0.0 {
+            (return_record.u, return_record.v) = (around_y(p), p.y() / self.height);
+            return_record.tangent = Vec3::new(normal.z(), 0.0, -normal.x());
+            return_record.bitangent = Vec3::new(0.0, 1.0, 0.0);
+        } else {
+            (return_record.u, return_record.v, return_record.tangent, return_record.bitangent) = cap_uv(p, self.radius, normal.y());
+        }
+        return_record.calculate_normal(&local, normal);
+        return_record.transform(&self.to_world, ray);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.to_world.point(Vec3::new(0.0, self.height / 2.0, 0.0))
+    }
+}
+
+// Angle of p around the local y axis as u in [0,1]. It runs the same way as on a Sphere, so
+// textures are not mirrored when seen from outside
+pub(crate) fn around_y(p: Point3) -> f64 {
+    (f64::atan2(-p.z(), p.x()) + PI) / (2.0 * PI)
+}
+
+// Decal mapping of a cap facing up (side > 0) or down onto the square around a circle of radius r,
+// with the matching (tangent, bitangent) so that tangent x bitangent is the cap's normal
+pub(crate) fn cap_uv(p: Point3, r: f64, side: f64) -> (f64, f64, Vec3, Vec3) {
+    let u = 0.5 + p.x() / (2.0 * r);
+    let v = 0.5 - side.signum() * p.z() / (2.0 * r);
+    (u, v, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -side.signum()))
+}
+
+impl Hittable for Cylinder {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Cylinder::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Cylinder::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // radius 1, from the origin up to y = 2
+    fn cylinder() -> Cylinder {
+        Cylinder::new(Point3::default(), Vec3::new(0.0, 2.0, 0.0), 1.0, MaterialId::EMPTY)
+    }
+
+    fn hit(cylinder: &Cylinder, origin: Point3, direction: Vec3) -> Record {
+        cylinder.ray_hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).expect("ray should hit the cylinder")
+    }
+
+    fn assert_vec(v: Vec3, expected: Vec3) {
+        assert!((v - expected).length() < 1e-9, "{:?}, expected {:?}", v, expected);
+    }
+
+    #[test]
+    fn caps_face_along_the_axis() {
+        let top = hit(&cylinder(), Point3::new(0.3, 5.0, 0.2), Vec3::new(0.0, -1.0, 0.0));
+        assert!((top.t - 3.0).abs() < 1e-9, "t = {}", top.t);
+        assert_vec(top.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(top.outside_face);
+        // decal mapped, the centre of the cap is the centre of the texture
+        assert!((top.u - 0.65).abs() < 1e-9 && (top.v - 0.4).abs() < 1e-9, "uv ({}, {})", top.u, top.v);
+        let bottom = hit(&cylinder(), Point3::new(-0.5, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((bottom.t - 5.0).abs() < 1e-9, "t = {}", bottom.t);
+        assert_vec(bottom.normal, Vec3::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn side_normals_point_away_from_the_axis() {
+        let side = hit(&cylinder(), Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!((side.t - 4.0).abs() < 1e-9, "t = {}", side.t);
+        assert_vec(side.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!((side.v - 0.5).abs() < 1e-9);
+        let side = hit(&cylinder(), Point3::new(0.0, 0.5, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_vec(side.normal, Vec3::new(0.0, 0.0, -1.0));
+        // above the top the side is not there
+        let ray = Ray::new(Point3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(cylinder().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn rays_from_inside_hit_the_far_side() {
+        let side = hit(&cylinder(), Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((side.t - 1.0).abs() < 1e-9, "t = {}", side.t);
+        assert_vec(side.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(!side.outside_face);
+        let cap = hit(&cylinder(), Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((cap.t - 1.0).abs() < 1e-9, "t = {}", cap.t);
+        assert_vec(cap.normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(!cap.outside_face);
+    }
+
+    #[test]
+    fn tilted_cylinder_carries_its_normals() {
+        // lying along +x from (1, 1, 1)
+        let cylinder = Cylinder::new(Point3::new(1.0, 1.0, 1.0), Vec3::new(2.0, 0.0, 0.0), 1.0, MaterialId::EMPTY);
+        assert_vec(cylinder.bounds.min, Vec3::new(1.0, 0.0, 0.0));
+        assert_vec(cylinder.bounds.max, Vec3::new(3.0, 2.0, 2.0));
+        let side = hit(&cylinder, Point3::new(2.0, 5.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((side.t - 3.0).abs() < 1e-9, "t = {}", side.t);
+        assert_vec(side.normal, Vec3::new(0.0, 1.0, 0.0));
+        let cap = hit(&cylinder, Point3::new(5.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!((cap.t - 2.0).abs() < 1e-9, "t = {}", cap.t);
+        assert_vec(cap.normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB};
+
+// A flat circle, or a ring when it has an inner radius. Textures are mapped onto it like a decal:
+// the square of side 2 * radius around the disk gets uvs from 0 to 1, along Vec3::basis(normal).
+#[derive(Debug, Clone)]
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    radius: f64,
+    // radius of the hole in the middle, 0 for a full disk
+    inner_radius: f64,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, material: MaterialId) -> Self {
+        Disk::ring(center, normal, 0.0, radius, material)
+    }
+
+    // the part of the disk between inner_radius and radius
+    pub fn ring(center: Point3, normal: Vec3, inner_radius: f64, radius: f64, material: MaterialId) -> Self {
+        let normal = normal.unit();
+        let (tangent, bitangent) = Vec3::basis(normal);
+
+        // the disk reaches radius * sqrt(1 - n_i^2) along each axis i, padded so axis aligned disks keep some thickness
+        let extent = Vec3::new(
+            radius * f64::sqrt((1.0 - normal.x() * normal.x()).max(0.0)) + 1e-6,
+            radius * f64::sqrt((1.0 - normal.y() * normal.y()).max(0.0)) + 1e-6,
+            radius * f64::sqrt((1.0 - normal.z() * normal.z()).max(0.0)) + 1e-6,
+        );
+        let bounds = AABB::new(center - extent, center + extent);
+
+        Disk { center, normal, radius, inner_radius, tangent, bitangent, material, bounds }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        let v_dot_n = Vec3::dot(ray.direction(), self.normal);
+        if v_dot_n == 0.0 {
+            return None;
+        }
+
+        let t = Vec3::dot(self.center - ray.origin(), self.normal) / v_dot_n;
+        if t_min > t || t > t_max {
+            return None;
+        }
+
+        let point = ray.ray_at(t);
+        let d = point - self.center;
+        let r2 = Vec3::dot(d, d);
+        if r2 > self.radius * self.radius || r2 < self.inner_radius * self.inner_radius {
+            return None;
+        }
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.point = point;
+        return_record.object_point = d;
+        return_record.material = self.material;
+        return_record.calculate_normal(ray, self.normal);
+        return_record.u = 0.5 + Vec3::dot(d, self.tangent) / (2.0 * self.radius);
+        return_record.v = 0.5 + Vec3::dot(d, self.bitangent) / (2.0 * self.radius);
+        return_record.tangent = self.tangent;
+        return_record.bitangent = self.bitangent;
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.center
+    }
+}
+
+impl Hittable for Disk {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Disk::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Point3::new(x, 5.0, z), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn hits_inside_the_radius_only() {
+        let disk = Disk::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), 2.0, MaterialId::EMPTY);
+        let hit = disk.ray_hit(&down(0.0, 0.0), 0.001, f64::INFINITY).expect("ray should hit the centre");
+        assert!((hit.t - 4.0).abs() < 1e-9, "t = {}", hit.t);
+        assert!((hit.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9 && hit.outside_face);
+        assert!((hit.u - 0.5).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9, "uv ({}, {})", hit.u, hit.v);
+        // the uv square spans the diameter
+        let hit = disk.ray_hit(&down(1.0, 0.0), 0.001, f64::INFINITY).unwrap();
+        let expected = 0.5 + Vec3::dot(Vec3::new(1.0, 0.0, 0.0), disk.tangent) / 4.0;
+        assert!((hit.u - expected).abs() < 1e-9, "u = {}", hit.u);
+        assert!(disk.ray_hit(&down(1.9, 0.0), 0.001, f64::INFINITY).is_some());
+        assert!(disk.ray_hit(&down(1.5, 1.5), 0.001, f64::INFINITY).is_none());
+        // from below the normal flips to face the ray
+        let up = Ray::new(Point3::new(0.5, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let hit = disk.ray_hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.normal - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9 && !hit.outside_face);
+    }
+
+    #[test]
+    fn ring_has_a_hole() {
+        let ring = Disk::ring(Point3::default(), Vec3::new(0.0, 1.0, 0.0), 0.5, 1.0, MaterialId::EMPTY);
+        assert!(ring.ray_hit(&down(0.0, 0.0), 0.001, f64::INFINITY).is_none());
+        assert!(ring.ray_hit(&down(0.3, -0.3), 0.001, f64::INFINITY).is_none());
+        assert!(ring.ray_hit(&down(0.0, 0.75), 0.001, f64::INFINITY).is_some());
+        assert!(ring.ray_hit(&down(0.0, 1.1), 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ray_in_the_plane_misses() {
+        let disk = Disk::new(Point3::default(), Vec3::new(0.0, 1.0, 0.0), 1.0, MaterialId::EMPTY);
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(disk.ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn bounds_hug_the_circle() {
+        // facing +y the disk is flat in y, padded to keep some thickness
+        let disk = Disk::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 1.0, 0.0), 2.0, MaterialId::EMPTY);
+        assert!((disk.bounds.min - Vec3::new(-1.0, 2.0, 1.0)).length() < 1e-5, "{:?}", disk.bounds);
+        assert!((disk.bounds.max - Vec3::new(3.0, 2.0, 5.0)).length() < 1e-5, "{:?}", disk.bounds);
+        assert!(disk.bounds.max.y() > disk.bounds.min.y());
+        // tilted 45 degrees around z it reaches radius / sqrt(2) along x and y
+        let disk = Disk::new(Point3::default(), Vec3::new(1.0, 1.0, 0.0), 2.0, MaterialId::EMPTY);
+        let r = f64::sqrt(2.0);
+        assert!((disk.bounds.max - Vec3::new(r, r, 2.0)).length() < 1e-5, "{:?}", disk.bounds);
+    }
+}
@@ -0,0 +1,571 @@
+// Import of glTF 2.0 scenes, .gltf files with their buffers and images next to them or single
+// .glb files. Every mesh becomes one Geometry, placed by each node that uses it with the node's
+// transform composed down the hierarchy, so a wheel used four times is stored once.
+//
+// Metallic-roughness materials are mapped onto the closest Material, as MTL materials are:
+// emissive surfaces become Emission, mostly metallic ones Metal and the rest Glossy with the
+// 4% reflectance glTF gives dielectrics. Factors are multiplied into their textures on load,
+// the roughness texture's green channel becomes a grey roughness map, normal textures wrap the
+// material in NormalMapped, and Mask and Blend alpha modes cut it out with Masked.
+//
+// let gltf = GltfScene::load("car.glb")?;
+// for (geometry, transform) in &gltf.instances {
+//     scene.add_instance(geometry.clone(), *transform);
+// }
+// if let Some(camera) = gltf.cameras.first() {
+//     camera.apply(&mut scene.camera);
+// }
+
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use gltf::{camera::Projection, image::Source as ImageSource, material::AlphaMode, mesh::Mode, texture::{MagFilter, WrappingMode}};
+
+use crate::{camera::Camera, color::Color, hittable2::Primitive, instance::Geometry, material::{Material, MaterialId, MaterialTable, NormalMap}, mesh::{MeshData, MeshError, MeshOptions, NormalMode}, texture::{srgb_to_linear, Filter, ImageTexture, Texture, WrapMode}, transform::{Matrix4, Transform}, vec3::{Point3, Vec3}};
+
+// A perspective camera from the file. glTF cameras look down their -z axis with +y up.
+#[derive(Debug, Clone)]
+pub struct GltfCamera {
+    // camera to world, with the node hierarchy applied
+    pub transform: Transform,
+    // vertical field of view in degrees
+    pub yfov: f64,
+    // width over height, when the file fixes one
+    pub aspect_ratio: Option<f64>,
+}
+
+impl GltfCamera {
+    // Points camera the same way. Camera's fov is horizontal, so it is worked out from yfov and
+    // the aspect ratio; a file that fixes the aspect ratio sets the image height to match.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.look_from = self.transform.point(Point3::default());
+        camera.look_at = self.transform.point(Point3::new(0.0, 0.0, -1.0));
+        camera.v_up = self.transform.vector(Vec3::new(0.0, 1.0, 0.0));
+        let aspect = match self.aspect_ratio {
+            Some(aspect) => {
+                camera.image_height = ((camera.image_width as f64 / aspect).round() as i32).max(1);
+                aspect
+            }
+            None => camera.image_width as f64 / camera.image_height as f64,
+        };
+        let half = f64::to_radians(self.yfov / 2.0).tan() * aspect;
+        camera.fov = f64::to_degrees(2.0 * half.atan());
+    }
+}
+
+pub struct GltfScene {
+    // every placement of every mesh in the default scene
+    pub instances: Vec<(Arc<Geometry>, Transform)>,
+    // the perspective cameras, in the order the nodes are found
+    pub cameras: Vec<GltfCamera>,
+}
+
+impl GltfScene {
+    // the file's materials are added to materials
+    pub fn load(path: impl AsRef<Path>, materials: &mut MaterialTable) -> Result<GltfScene, MeshError> {
+        let path = path.as_ref();
+        let parse = |e: gltf::Error| MeshError::Parse(path.to_path_buf(), e.to_string());
+        let data = fs::read(path).map_err(|e| read_error(path, e))?;
+        let gltf = gltf::Gltf::from_slice(&data).map_err(parse)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut buffers = Vec::new();
+        for buffer in gltf.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf.blob.clone().ok_or_else(|| {
+                    MeshError::Parse(path.to_path_buf(), "buffer refers to a missing .glb binary chunk".to_string())
+                })?,
+                gltf::buffer::Source::Uri(uri) => read_uri(uri, dir, path)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(MeshError::Parse(path.to_path_buf(), format!("buffer {} is shorter than declared", buffer.index())));
+            }
+            buffers.push(data);
+        }
+
+        let mut loader = Loader { path, dir, buffers: &buffers, table: materials, images: HashMap::new(), materials: HashMap::new(), meshes: HashMap::new() };
+        let mut scene = GltfScene { instances: Vec::new(), cameras: Vec::new() };
+        // files without a default scene show their first one
+        if let Some(root) = gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            for node in root.nodes() {
+                loader.node(&node, Transform::identity(), &mut scene)?;
+            }
+        }
+        Ok(scene)
+    }
+}
+
+fn read_error(path: &Path, e: io::Error) -> MeshError {
+    match e.kind() {
+        io::ErrorKind::NotFound => MeshError::MissingFile(path.to_path_buf()),
+        _ => MeshError::Parse(path.to_path_buf(), e.to_string()),
+    }
+}
+
+// the bytes behind a buffer or image uri, embedded as base64 or a file relative to the glTF
+fn read_uri(uri: &str, dir: &Path, path: &Path) -> Result<Vec<u8>, MeshError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data.split_once(";base64,")
+            .ok_or_else(|| MeshError::Parse(path.to_path_buf(), "only base64 data uris are supported".to_string()))?;
+        return base64::decode(encoded).map_err(|e| MeshError::Parse(path.to_path_buf(), format!("bad base64 data: {e}")));
+    }
+    let file = dir.join(percent_decode(uri));
+    fs::read(&file).map_err(|e| read_error(&file, e))
+}
+
+// file uris escape spaces and other characters as %xx
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// scale given to an axis a node flattens to nothing
+const FLAT_SCALE: f32 = 1e-6;
+
+// glTF matrices are column major, ours are row major
+fn row_major(columns: [[f32; 4]; 4]) -> Matrix4 {
+    let mut matrix = [[0.0; 4]; 4];
+    for (c, column) in columns.iter().enumerate() {
+        for (r, x) in column.iter().enumerate() {
+            matrix[r][c] = *x as f64;
+        }
+    }
+    matrix
+}
+
+// Builds meshes, materials and decoded images the first time they are used
+struct Loader<'a> {
+    path: &'a Path,
+    dir: &'a Path,
+    buffers: &'a [Vec<u8>],
+    table: &'a mut MaterialTable,
+    images: HashMap<usize, Arc<image::Rgba32FImage>>,
+    materials: HashMap<usize, MaterialId>,
+    meshes: HashMap<usize, Arc<Geometry>>,
+}
+
+impl Loader<'_> {
+    fn node(&mut self, node: &gltf::Node, parent: Transform, scene: &mut GltfScene) -> Result<(), MeshError> {
+        let local = match Transform::from_matrix(row_major(node.transform().matrix())) {
+            Some(local) => local,
+            None => {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                // a node scaled to nothing hides itself and everything under it
+                if scale.iter().all(|s| *s == 0.0) {
+                    return Ok(());
+                }
+                // Flattened along an axis, e.g. a decal. Instances need an inverse to move rays
+                // into, so the flat axis keeps a sliver of thickness
+                let scale = scale.map(|s| if s == 0.0 { FLAT_SCALE } else { s });
+                let matrix = gltf::scene::Transform::Decomposed { translation, rotation, scale }.matrix();
+                Transform::from_matrix(row_major(matrix)).ok_or_else(|| MeshError::Parse(
+                    self.path.to_path_buf(),
+                    format!("node {} has a transform that can't be inverted", node.index()),
+                ))?
+            }
+        };
+        let world = parent * local;
+        if let Some(mesh) = node.mesh() {
+            let geometry = self.mesh(&mesh)?;
+            scene.instances.push((geometry, world));
+        }
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(p) = camera.projection() {
+                scene.cameras.push(GltfCamera {
+                    transform: world,
+                    yfov: f64::to_degrees(p.yfov() as f64),
+                    aspect_ratio: p.aspect_ratio().map(|a| a as f64),
+                });
+            }
+        }
+        for child in node.children() {
+            self.node(&child, world, scene)?;
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> Result<Arc<Geometry>, MeshError> {
+        if let Some(geometry) = self.meshes.get(&mesh.index()) {
+            return Ok(geometry.clone());
+        }
+        let buffers = self.buffers;
+        let mut parts = Vec::new();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|b| b.as_slice()));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<Point3> = positions.map(|[x, y, z]| Vec3::newf32(x, y, z)).collect();
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+            if let Some(index) = indices.iter().find(|i| **i >= positions.len()) {
+                return Err(MeshError::IndexOutOfRange { file: self.path.to_path_buf(), index: *index, len: positions.len() });
+            }
+            let faces: Vec<[usize; 3]> = match primitive.mode() {
+                Mode::Triangles => indices.chunks_exact(3).map(|f| [f[0], f[1], f[2]]).collect(),
+                // every other strip triangle is wound the other way round
+                Mode::TriangleStrip => (2..indices.len())
+                    .map(|k| if k % 2 == 0 { [indices[k - 2], indices[k - 1], indices[k]] } else { [indices[k - 1], indices[k - 2], indices[k]] })
+                    .collect(),
+                Mode::TriangleFan => (2..indices.len()).map(|k| [indices[0], indices[k - 1], indices[k]]).collect(),
+                // points and lines have no surface
+                _ => continue,
+            };
+            if faces.is_empty() {
+                continue;
+            }
+
+            let data = MeshData {
+                positions,
+                normals: reader.read_normals().map(|n| n.map(|[x, y, z]| Vec3::newf32(x, y, z)).collect()),
+                // glTF puts v = 0 at the top of the image
+                uvs: reader.read_tex_coords(self.uv_set(&primitive.material())?).map(|t| t.into_f32().map(|[u, v]| (u as f64, 1.0 - v as f64)).collect()),
+                colors: reader.read_colors(0).map(|c| c.into_rgb_f32().map(|[r, g, b]| Color::newf32(r, g, b)).collect()),
+                faces,
+            };
+            let material = self.material(&primitive.material())?;
+            // the spec asks for flat shading where a primitive has no normals
+            let options = MeshOptions { normals: NormalMode::Flat, ..MeshOptions::default() };
+            parts.push(Primitive::IndexedMesh(Arc::new(data.into_mesh(self.path, material, &options, self.table)?)));
+        }
+        let geometry = Arc::new(Geometry::new(parts));
+        self.meshes.insert(mesh.index(), geometry.clone());
+        Ok(geometry)
+    }
+
+    fn material(&mut self, m: &gltf::Material) -> Result<MaterialId, MeshError> {
+        // primitives without a material, the vertex colours take over where there are any
+        let Some(index) = m.index() else {
+            return Ok(MaterialId::EMPTY);
+        };
+        if let Some(id) = self.materials.get(&index) {
+            return Ok(*id);
+        }
+        let material = self.pbr_material(m)?;
+        let id = self.table.add(material);
+        self.materials.insert(index, id);
+        Ok(id)
+    }
+
+    // The texture coordinate set the material's textures are looked up with. Meshes carry one set
+    // of uvs, so all of a material's textures have to use the same one.
+    fn uv_set(&self, m: &gltf::Material) -> Result<u32, MeshError> {
+        let pbr = m.pbr_metallic_roughness();
+        let sets = [
+            pbr.base_color_texture().map(|t| t.tex_coord()),
+            pbr.metallic_roughness_texture().map(|t| t.tex_coord()),
+            m.normal_texture().map(|t| t.tex_coord()),
+            m.emissive_texture().map(|t| t.tex_coord()),
+        ];
+        let mut sets = sets.into_iter().flatten();
+        let first = sets.next().unwrap_or(0);
+        match sets.find(|set| *set != first) {
+            Some(other) => Err(MeshError::Parse(self.path.to_path_buf(), format!(
+                "material {} uses texture coordinate sets {} and {}, only one set per material is supported",
+                m.index().unwrap_or_default(), first, other,
+            ))),
+            None => Ok(first),
+        }
+    }
+
+    fn pbr_material(&mut self, m: &gltf::Material) -> Result<Material, MeshError> {
+        let pbr = m.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let cutout = match m.alpha_mode() {
+            AlphaMode::Opaque => None,
+            AlphaMode::Mask => Some(m.alpha_cutoff().unwrap_or(0.5)),
+            // a cutoff of 0 keeps the alpha as a fraction
+            AlphaMode::Blend => Some(0.0),
+        };
+        // alpha is 0 or 1 with a cutoff and the blend fraction without one
+        let alpha = move |x: f32| match cutout {
+            Some(cutoff) if cutoff > 0.0 => if x >= cutoff { 1.0 } else { 0.0 },
+            _ => x as f64,
+        };
+
+        let (color, opacity): (Texture, Option<Texture>) = match pbr.base_color_texture() {
+            Some(info) => {
+                let mut image = self.texture(&info.texture(), |p| {
+                    Color::new(srgb_to_linear(p[0] as f64) * r as f64, srgb_to_linear(p[1] as f64) * g as f64, srgb_to_linear(p[2] as f64) * b as f64)
+                })?;
+                if cutout.is_some() {
+                    image = image.with_alpha(self.channel(&info.texture(), |p| alpha(p[3] * a))?);
+                }
+                let texture = Texture::from(image);
+                let opacity = cutout.map(|_| texture.clone().into_opacity());
+                (texture, opacity)
+            }
+            None => (Color::newf32(r, g, b).into(), cutout.map(|_| alpha(a).into())),
+        };
+
+        let roughness_factor = pbr.roughness_factor();
+        let (roughness, metallic) = match pbr.metallic_roughness_texture() {
+            // green is roughness and blue metalness. A material is either Metal or Glossy
+            // throughout, so the metalness is averaged over the image
+            Some(info) => {
+                let roughness: Texture = self.texture(&info.texture(), |p| {
+                    let x = (p[1] * roughness_factor) as f64;
+                    Color::new(x, x, x)
+                })?.into();
+                let blue = self.channel(&info.texture(), |p| p[2] as f64)?;
+                let metallic = pbr.metallic_factor() as f64 * blue.iter().sum::<f64>() / blue.len().max(1) as f64;
+                (roughness, metallic)
+            }
+            None => ((roughness_factor as f64).into(), pbr.metallic_factor() as f64),
+        };
+
+        let [er, eg, eb] = m.emissive_factor();
+        let emissive = Color::newf32(er, eg, eb);
+        let strength = emissive.x().max(emissive.y()).max(emissive.z());
+        let mut material = if strength > 0.0 {
+            let color = match m.emissive_texture() {
+                Some(info) => self.texture(&info.texture(), |p| {
+                    Color::new(srgb_to_linear(p[0] as f64) * er as f64, srgb_to_linear(p[1] as f64) * eg as f64, srgb_to_linear(p[2] as f64) * eb as f64) / strength
+                })?.into(),
+                None => (emissive / strength).into(),
+            };
+            Material::Emission { color, strength }
+        } else if metallic >= 0.5 {
+            Material::Metal { color, roughness }
+        } else {
+            Material::Glossy { color, specularity: 0.04.into(), roughness }
+        };
+
+        if let Some(normal) = m.normal_texture() {
+            let map = self.texture(&normal.texture(), |p| Color::newf32(p[0], p[1], p[2]))?.into();
+            material = Material::NormalMapped { base: Box::new(material), map: NormalMap::Tangent { map, strength: normal.scale() as f64 } };
+        }
+        if let Some(opacity) = opacity {
+            material = Material::Masked { base: Box::new(material), opacity };
+        }
+        Ok(material)
+    }
+
+    // An image texture from a glTF texture, each texel made from its rgba values by texel and
+    // wrapped and filtered as the texture's sampler says
+    fn texture(&mut self, texture: &gltf::Texture, texel: impl Fn(&[f32; 4]) -> Color) -> Result<ImageTexture, MeshError> {
+        let image = self.image(&texture.source())?;
+        let pixels = image.pixels().map(|p| texel(&p.0)).collect();
+        let mut texture_out = ImageTexture::new(image.width(), image.height(), pixels);
+        let sampler = texture.sampler();
+        // one wrap mode is used for both directions, the one along u
+        texture_out.wrap = match sampler.wrap_s() {
+            WrappingMode::ClampToEdge => WrapMode::Clamp,
+            WrappingMode::MirroredRepeat => WrapMode::Mirror,
+            WrappingMode::Repeat => WrapMode::Repeat,
+        };
+        if sampler.mag_filter() == Some(MagFilter::Nearest) {
+            texture_out.filter = Filter::Nearest;
+        }
+        Ok(texture_out)
+    }
+
+    // one value per texel, row by row
+    fn channel(&mut self, texture: &gltf::Texture, value: impl Fn(&[f32; 4]) -> f64) -> Result<Vec<f64>, MeshError> {
+        let image = self.image(&texture.source())?;
+        Ok(image.pixels().map(|p| value(&p.0)).collect())
+    }
+
+    fn image(&mut self, image: &gltf::Image) -> Result<Arc<image::Rgba32FImage>, MeshError> {
+        if let Some(decoded) = self.images.get(&image.index()) {
+            return Ok(decoded.clone());
+        }
+        // where the image came from, for errors
+        let (bytes, source): (Vec<u8>, PathBuf) = match image.source() {
+            ImageSource::View { view, .. } => {
+                let buffer = &self.buffers[view.buffer().index()];
+                let bytes = buffer.get(view.offset()..view.offset() + view.length())
+                    .ok_or_else(|| MeshError::Parse(self.path.to_path_buf(), format!("image {} lies outside its buffer", image.index())))?;
+                (bytes.to_vec(), self.path.to_path_buf())
+            }
+            ImageSource::Uri { uri, .. } => {
+                let source = if uri.starts_with("data:") { self.path.to_path_buf() } else { self.dir.join(percent_decode(uri)) };
+                let bytes = read_uri(uri, self.dir, self.path).map_err(|e| match e {
+                    MeshError::MissingFile(path) => MeshError::Texture(path, "file not found".to_string()),
+                    e => e,
+                })?;
+                (bytes, source)
+            }
+        };
+        let decoded = image::load_from_memory(&bytes).map_err(|e| MeshError::Texture(source, e.to_string()))?;
+        let decoded = Arc::new(decoded.into_rgba32f());
+        self.images.insert(image.index(), decoded.clone());
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    // A unit square in the xy plane facing +z, with index lists for it as triangles, as a strip,
+    // as a fan, and one list that points past the last vertex
+    fn buffer() -> String {
+        let mut data = Vec::new();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in p {
+                data.extend(x.to_le_bytes());
+            }
+        }
+        for i in [0u16, 1, 2, 0, 2, 3, 0, 1, 3, 2, 0, 1, 2, 3, 0, 1, 9, 0] {
+            data.extend(i.to_le_bytes());
+        }
+        base64::encode(data)
+    }
+
+    // a .gltf with the square's accessors, and meshes, nodes and the rest filled in by the test
+    fn gltf(rest: &str) -> String {
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": 84, "uri": "data:application/octet-stream;base64,{}" }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 48 }},
+                {{ "buffer": 0, "byteOffset": 48, "byteLength": 36 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "byteOffset": 0, "componentType": 5123, "count": 6, "type": "SCALAR" }},
+                {{ "bufferView": 1, "byteOffset": 12, "componentType": 5123, "count": 4, "type": "SCALAR" }},
+                {{ "bufferView": 1, "byteOffset": 20, "componentType": 5123, "count": 4, "type": "SCALAR" }},
+                {{ "bufferView": 1, "byteOffset": 28, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            {}
+        }}"#, buffer(), rest)
+    }
+
+    // the square drawn with the index accessor, and mode (4 triangles, 5 strip, 6 fan)
+    fn square(indices: usize, mode: u32) -> String {
+        format!(r#""meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": {}, "mode": {} }}] }}]"#, indices, mode)
+    }
+
+    fn load(name: &str, json: &str) -> Result<GltfScene, MeshError> {
+        let path = std::env::temp_dir().join(format!("glint-{}-{}.gltf", name, std::process::id()));
+        fs::write(&path, json).unwrap();
+        let scene = GltfScene::load(&path, &mut MaterialTable::new());
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    fn origin_of(transform: &Transform) -> Point3 {
+        transform.point(Point3::default())
+    }
+
+    #[test]
+    fn hierarchy_composes_down_to_the_children() {
+        let json = gltf(&format!(r#"{},
+            "nodes": [
+                {{ "translation": [1, 0, 0], "scale": [2, 2, 2], "children": [1] }},
+                {{ "translation": [0, 1, 0], "mesh": 0 }}
+            ],
+            "scenes": [{{ "nodes": [0] }}]"#, square(1, 4)));
+        let scene = load("hierarchy", &json).unwrap();
+        assert_eq!(scene.instances.len(), 1);
+        let (_, transform) = &scene.instances[0];
+        // the child's offset is scaled by its parent, then moved with it
+        assert!((origin_of(transform) - Point3::new(1.0, 2.0, 0.0)).length() < 1e-9, "{:?}", origin_of(transform));
+        assert!((transform.vector(Vec3::new(1.0, 0.0, 0.0)) - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn nodes_sharing_a_mesh_share_its_geometry() {
+        let json = gltf(&format!(r#"{},
+            "nodes": [{{ "mesh": 0 }}, {{ "mesh": 0, "translation": [3, 0, 0] }}],
+            "scenes": [{{ "nodes": [0, 1] }}]"#, square(1, 4)));
+        let scene = load("instancing", &json).unwrap();
+        assert_eq!(scene.instances.len(), 2);
+        assert!(Arc::ptr_eq(&scene.instances[0].0, &scene.instances[1].0));
+        assert!((origin_of(&scene.instances[1].1) - Point3::new(3.0, 0.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn strips_and_fans_face_the_same_way_as_triangles() {
+        for (name, indices, mode) in [("triangles", 1, 4), ("strip", 2, 5), ("fan", 3, 6)] {
+            let json = gltf(&format!(r#"{}, "nodes": [{{ "mesh": 0 }}], "scenes": [{{ "nodes": [0] }}]"#, square(indices, mode)));
+            let scene = load(name, &json).unwrap();
+            let geometry = &scene.instances[0].0;
+            // both triangles of the square, seen from the front
+            for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+                let ray = Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+                let hit = geometry.ray_hit(&MaterialTable::new(), &ray, 0.001, f64::INFINITY)
+                    .unwrap_or_else(|| panic!("{}: ray at {}, {} should hit", name, x, y));
+                assert!(hit.outside_face, "{}: triangle at {}, {} faces away", name, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn camera_turns_yfov_into_a_horizontal_fov() {
+        let json = gltf(r#""cameras": [{ "type": "perspective", "perspective": { "yfov": 1.0471975512, "aspectRatio": 2.0, "znear": 0.1 } }],
+            "nodes": [{ "camera": 0, "translation": [0, 0, 5] }],
+            "scenes": [{ "nodes": [0] }]"#);
+        let scene = load("camera", &json).unwrap();
+        let mut camera = Camera::new();
+        camera.image_width = 400;
+        scene.cameras[0].apply(&mut camera);
+        assert_eq!(camera.image_height, 200);
+        // yfov is stored as f32
+        let fov = f64::to_degrees(2.0 * f64::atan(2.0 * f64::to_radians(30.0).tan()));
+        assert!((camera.fov - fov).abs() < 1e-4, "fov = {}, expected {}", camera.fov, fov);
+        assert!((camera.look_from - Point3::new(0.0, 0.0, 5.0)).length() < 1e-9);
+        assert!((camera.look_at - Point3::new(0.0, 0.0, 4.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn index_past_the_vertices_is_an_error() {
+        let json = gltf(&format!(r#"{}, "nodes": [{{ "mesh": 0 }}], "scenes": [{{ "nodes": [0] }}]"#, square(4, 4)));
+        assert!(matches!(load("out-of-range", &json), Err(MeshError::IndexOutOfRange { index: 9, len: 4, .. })));
+    }
+
+    #[test]
+    fn textures_on_different_uv_sets_are_an_error() {
+        let json = gltf(r#""meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+            "images": [{ "uri": "never_read.png" }],
+            "textures": [{ "source": 0 }],
+            "materials": [{
+                "pbrMetallicRoughness": { "baseColorTexture": { "index": 0, "texCoord": 0 } },
+                "normalTexture": { "index": 0, "texCoord": 1 }
+            }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]"#);
+        match load("uv-sets", &json) {
+            Err(MeshError::Parse(_, msg)) => assert!(msg.contains("texture coordinate sets 0 and 1"), "{}", msg),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("mixed uv sets should not load"),
+        }
+    }
+
+    #[test]
+    fn flattened_nodes_stay_and_vanished_ones_hide_their_children() {
+        let json = gltf(&format!(r#"{},
+            "nodes": [
+                {{ "mesh": 0, "scale": [1, 1, 0] }},
+                {{ "scale": [0, 0, 0], "children": [2] }},
+                {{ "mesh": 0 }}
+            ],
+            "scenes": [{{ "nodes": [0, 1] }}]"#, square(1, 4)));
+        let scene = load("flattened", &json).unwrap();
+        assert_eq!(scene.instances.len(), 1);
+        let (geometry, transform) = &scene.instances[0];
+        let instance = crate::instance::Instance::new(geometry.clone(), *transform);
+        let ray = Ray::new(Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = instance.ray_hit(&MaterialTable::new(), &ray, 0.001, f64::INFINITY).expect("the decal should still be hit");
+        assert!((hit.t - 1.0).abs() < 1e-6, "t = {}", hit.t);
+    }
+}
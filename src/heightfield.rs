@@ -0,0 +1,308 @@
+use std::{fmt, path::Path};
+
+use image::ImageError;
+
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, transform::Transform};
+
+// Terrain from a grid of heights, e.g. a heightmap or DEM image. Every pixel is a vertex, and each
+// square between four of them is split into two triangles. Rays walk the squares under them with
+// a grid DDA, so only the few they pass over are tested.
+//
+// The grid is intersected in a local frame where the vertices sit on whole numbers in x and z and
+// y is the height from 0 to 1. Column i of the image runs along +x, row j along +z, and the uvs
+// put each vertex on the centre of its pixel, so a colour image of the same size lines up.
+#[derive(Debug)]
+pub enum HeightfieldError {
+    Image(ImageError),
+    // fewer than 2 x 2 heights
+    TooSmall(usize, usize),
+    // the height buffer does not hold nx * nz heights
+    WrongLength { len: usize, nx: usize, nz: usize },
+    // size is zero or not finite along x or z, or not finite along y
+    BadSize([f64; 3]),
+}
+
+impl fmt::Display for HeightfieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightfieldError::Image(e) => write!(f, "{}", e),
+            HeightfieldError::TooSmall(nx, nz) => write!(f, "a heightfield needs at least 2 x 2 heights, got {} x {}", nx, nz),
+            HeightfieldError::WrongLength { len, nx, nz } => write!(f, "{} heights do not fill a {} x {} grid", len, nx, nz),
+            HeightfieldError::BadSize(size) => write!(f, "size {:?} must be finite, and non-zero along x and z", size),
+        }
+    }
+}
+
+impl std::error::Error for HeightfieldError {}
+
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    heights: Vec<f64>,
+    // number of vertices along x and z
+    nx: usize,
+    nz: usize,
+    // lowest and highest height in each square, to skip squares the ray passes above or below
+    ranges: Vec<(f64, f64)>,
+    // smooth normals at the vertices, in the local frame
+    normals: Vec<Vec3>,
+    to_world: Transform,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Heightfield {
+    // heights from 0 to 1, row by row along x. The grid fills the box from corner to corner + size,
+    // a height of 1 reaching size.y. A size.y of 0 gives a flat field at corner.y.
+    pub fn new(heights: Vec<f64>, nx: usize, nz: usize, corner: Point3, size: Vec3, material: MaterialId) -> Result<Self, HeightfieldError> {
+        if nx < 2 || nz < 2 {
+            return Err(HeightfieldError::TooSmall(nx, nz));
+        }
+        if heights.len() != nx * nz {
+            return Err(HeightfieldError::WrongLength { len: heights.len(), nx, nz });
+        }
+        // every height lands on corner.y when there is no span to scale them by, so a flat field
+        // keeps zero heights and a unit y scale, which leaves the local frame invertible
+        let (heights, height_scale) = if size.y() == 0.0 { (vec![0.0; heights.len()], 1.0) } else { (heights, size.y()) };
+        let to_world = Transform::try_scale(Vec3::new(size.x() / (nx - 1) as f64, height_scale, size.z() / (nz - 1) as f64))
+            .ok_or(HeightfieldError::BadSize(size.v))?
+            .then(&Transform::translate(corner));
+        let at = |x: usize, z: usize| heights[z * nx + x];
+
+        let mut ranges = Vec::with_capacity((nx - 1) * (nz - 1));
+        for z in 0..nz - 1 {
+            for x in 0..nx - 1 {
+                let corners = [at(x, z), at(x + 1, z), at(x, z + 1), at(x + 1, z + 1)];
+                ranges.push((corners.into_iter().fold(f64::INFINITY, f64::min), corners.into_iter().fold(f64::NEG_INFINITY, f64::max)));
+            }
+        }
+
+        // central differences, one sided at the edges
+        let mut normals = Vec::with_capacity(nx * nz);
+        for z in 0..nz {
+            for x in 0..nx {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(nx - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(nz - 1));
+                let dx = (at(x1, z) - at(x0, z)) / (x1 - x0) as f64;
+                let dz = (at(x, z1) - at(x, z0)) / (z1 - z0) as f64;
+                normals.push(Vec3::new(-dx, 1.0, -dz));
+            }
+        }
+
+        let low = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        // a flat grid still needs some thickness for the local box
+        let local = AABB::new(Vec3::new(0.0, low - 1e-6, 0.0), Vec3::new((nx - 1) as f64, high + 1e-6, (nz - 1) as f64));
+        Ok(Heightfield { heights, nx, nz, ranges, normals, to_world, material, bounds: to_world.bounds(&local) })
+    }
+
+    // A grayscale image as heights, black at corner.y and white at corner.y + size.y. 16 bit
+    // images keep their precision, colour images use their luminance.
+    pub fn load(path: impl AsRef<Path>, corner: Point3, size: Vec3, material: MaterialId) -> Result<Self, HeightfieldError> {
+        let img = image::open(path).map_err(HeightfieldError::Image)?.into_luma16();
+        let heights = img.pixels().map(|p| p[0] as f64 / u16::MAX as f64).collect();
+        Heightfield::new(heights, img.width() as usize, img.height() as usize, corner, size, material)
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.nx + x]
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> Point3 {
+        Point3::new(x as f64, self.height(x, z), z as f64)
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let local = self.to_world.inverse().ray(ray);
+        let (o, d) = (local.origin(), local.direction());
+        let grid = AABB::new(
+            Vec3::new(0.0, f64::NEG_INFINITY, 0.0),
+            Vec3::new((self.nx - 1) as f64, f64::INFINITY, (self.nz - 1) as f64),
+        );
+        let (enter, exit) = grid.interval(&local)?;
+        let (enter, exit) = (enter.max(t_min), exit.min(t_max));
+        if enter > exit {
+            return None;
+        }
+
+        // the square the ray enters the grid in, and the t of the next line it crosses along x and z
+        let start = local.ray_at(enter);
+        let cell = |p: f64, n: usize| (p.floor().max(0.0) as usize).min(n - 2);
+        let (mut x, mut z) = (cell(start.x(), self.nx), cell(start.z(), self.nz));
+        let step = |d: f64| if d > 0.0 { 1 } else { -1 };
+        let next = |o: f64, d: f64, i: usize| {
+            if d == 0.0 { f64::INFINITY } else { ((i as f64 + if d > 0.0 { 1.0 } else { 0.0 }) - o) / d }
+        };
+        let (mut next_x, mut next_z) = (next(o.x(), d.x(), x), next(o.z(), d.z(), z));
+        let (delta_x, delta_z) = ((1.0 / d.x()).abs(), (1.0 / d.z()).abs());
+
+        let mut t = enter;
+        loop {
+            let leave = next_x.min(next_z).min(exit);
+            let (low, high) = self.ranges[z * (self.nx - 1) + x];
+            let (y0, y1) = (o.y() + t * d.y(), o.y() + leave * d.y());
+            if y0.min(y1) <= high && y0.max(y1) >= low {
+                if let Some(record) = self.hit_square(&local, ray, x, z, t_min, t_max) {
+                    return Some(record);
+                }
+            }
+            if leave >= exit {
+                return None;
+            }
+            t = leave;
+            if next_x < next_z {
+                let i = x as i64 + step(d.x());
+                if i < 0 || i > self.nx as i64 - 2 {
+                    return None;
+                }
+                x = i as usize;
+                next_x += delta_x;
+            } else {
+                let i = z as i64 + step(d.z());
+                if i < 0 || i > self.nz as i64 - 2 {
+                    return None;
+                }
+                z = i as usize;
+                next_z += delta_z;
+            }
+        }
+    }
+
+    // the closer of the two triangles of the square at (x, z)
+    fn hit_square(&self, local: &Ray, ray: &Ray, x: usize, z: usize, t_min: f64, t_max: f64) -> Option<Record> {
+        // both wound so that their normals point up
+        let corners = [(x, z), (x, z + 1), (x + 1, z + 1), (x + 1, z)];
+        let triangles = [[corners[0], corners[1], corners[2]], [corners[0], corners[2], corners[3]]];
+        // t, which triangle, and the weights of its second and third corner
+        let mut closest: Option<(f64, usize, f64, f64)> = None;
+        for (i, tri) in triangles.iter().enumerate() {
+            let [a, b, c] = tri.map(|(x, z)| self.vertex(x, z));
+            let limit = closest.map_or(t_max, |c| c.0);
+            if let Some((t, beta, gamma)) = intersect_triangle(local, a, b, c, t_min, limit) {
+                closest = Some((t, i, beta, gamma));
+            }
+        }
+        let (t, i, beta, gamma) = closest?;
+        let alpha = 1.0 - beta - gamma;
+        let [a, b, c] = triangles[i];
+        let p = local.ray_at(t);
+
+        let normal = alpha * self.normals[a.1 * self.nx + a.0] + beta * self.normals[b.1 * self.nx + b.0] + gamma * self.normals[c.1 * self.nx + c.0];
+        let normal = normal.unit();
+        // u follows x and v runs against z, as image rows go down
+        let tangent = Vec3::new(1.0, 0.0, 0.0) - normal.x() * normal;
+        let tangent = tangent.unit();
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.object_point = p;
+        return_record.material = self.material;
+        return_record.u = (p.x() + 0.5) / self.nx as f64;
+        return_record.v = 1.0 - (p.z() + 0.5) / self.nz as f64;
+        (return_record.tangent, return_record.bitangent) = (tangent, Vec3::cross(normal, tangent));
+        return_record.calculate_normal(local, normal);
+        return_record.transform(&self.to_world, ray);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
+
+// Möller-Trumbore, the t and the barycentric weights of b and c
+fn intersect_triangle(ray: &Ray, a: Point3, b: Point3, c: Point3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let (e1, e2) = (b - a, c - a);
+    let p = Vec3::cross(ray.direction(), e2);
+    let det = Vec3::dot(e1, p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let s = ray.origin() - a;
+    let beta = Vec3::dot(s, p) / det;
+    if !(0.0..=1.0).contains(&beta) {
+        return None;
+    }
+    let q = Vec3::cross(s, e1);
+    let gamma = Vec3::dot(ray.direction(), q) / det;
+    if gamma < 0.0 || beta + gamma > 1.0 {
+        return None;
+    }
+    let t = Vec3::dot(e2, q) / det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, beta, gamma))
+}
+
+impl Hittable for Heightfield {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Heightfield::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Point3 {
+        Heightfield::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3 x 3 unit squares at height 0, with the vertices at x, z >= 2 raised to 1, so the square
+    // at (2, 2) is a flat top with slopes leading up to it
+    fn field() -> Heightfield {
+        let heights = (0..16).map(|i| if i % 4 >= 2 && i / 4 >= 2 { 1.0 } else { 0.0 }).collect();
+        Heightfield::new(heights, 4, 4, Point3::default(), Vec3::new(3.0, 1.0, 3.0), MaterialId::EMPTY).unwrap()
+    }
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Point3::new(x, 5.0, z), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn ray_hits_a_cell_at_its_height() {
+        let hit = field().ray_hit(&down(2.5, 2.5), 0.001, f64::INFINITY).expect("ray should hit the top");
+        assert!((hit.t - 4.0).abs() < 1e-9, "t = {}", hit.t);
+        // the smooth normals lean towards the slopes around the top
+        assert!(hit.normal.y() > 0.9, "normal = {:?}", hit.normal);
+
+        let hit = field().ray_hit(&down(0.5, 0.5), 0.001, f64::INFINITY).expect("ray should hit the ground");
+        assert!((hit.t - 5.0).abs() < 1e-9, "t = {}", hit.t);
+        assert!((hit.point - Point3::new(0.5, 0.0, 0.5)).length() < 1e-9, "point = {:?}", hit.point);
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.75).abs() < 1e-9, "uv = {}, {}", hit.u, hit.v);
+    }
+
+    #[test]
+    fn grazing_ray_crosses_cells_to_the_slope() {
+        // low over the ground along x, until the slope up to the top at x = 1 to 2 rises to meet it
+        let ray = Ray::new(Point3::new(-1.0, 0.5, 2.5), Vec3::new(1.0, 0.0, 0.0));
+        let hit = field().ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the slope");
+        assert!((hit.t - 2.5).abs() < 1e-9, "t = {}", hit.t);
+        assert!(hit.normal.x() < 0.0 && hit.normal.y() > 0.0, "normal = {:?}", hit.normal);
+        // just over the top it crosses every cell and misses
+        let ray = Ray::new(Point3::new(-1.0, 1.0 + 1e-6, 2.5), Vec3::new(1.0, 0.0, 0.0));
+        assert!(field().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+        // and diagonally, stepping in x and z, it still finds the slope
+        let ray = Ray::new(Point3::new(-0.5, 0.5, -0.5), Vec3::new(1.0, 0.0, 1.0));
+        let hit = field().ray_hit(&ray, 0.001, f64::INFINITY).expect("diagonal ray should hit the slope");
+        assert!((hit.point.y() - 0.5).abs() < 1e-9 && hit.point.x() > 1.0 && hit.point.x() < 2.0, "point = {:?}", hit.point);
+    }
+
+    #[test]
+    fn ray_leaving_through_the_side_misses() {
+        // inside the bounds and sinking, but out of the grid at x = 3 before it reaches the ground
+        let ray = Ray::new(Point3::new(0.5, 0.9, 0.5), Vec3::new(1.0, -0.01, 0.0));
+        assert!(field().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+        let ray = Ray::new(Point3::new(1.5, 0.9, 0.5), Vec3::new(0.0, -0.01, -1.0));
+        assert!(field().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+        // outside the grid the ground does not go on
+        assert!(field().ray_hit(&down(3.5, 0.5), 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -1,140 +1,62 @@
 // Abstraction of hittable objects
-use crate::{ray::Ray, vec3::{Point3, Vec3}, material::Material, aabb::AABB};
+use crate::{ray::Ray, vec3::{Point3, Vec3, WHITE}, color::Color, material::MaterialId, aabb::AABB, transform::Transform};
 
 //A 'log' of the ray intersections that occured, stores important metadata
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Record{
     pub t: f64,
     pub point: Point3,
+    // the hit position in the primitive's own frame, procedural textures in object space use it
+    pub object_point: Point3,
     pub normal: Vec3,
+    // shading frame for normal and bump maps, unit vectors along increasing u and v.
+    // They belong to the outward facing normal, so they are not flipped with it for back faces
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub outside_face: bool,
-    pub material: Material,
-    pub u: Option<f64>,
-    pub v: Option<f64>
+    pub material: MaterialId,
+    // surface coordinates of the hit, in [0,1] for primitives with a natural parameterisation
+    pub u: f64,
+    pub v: f64,
+    // vertex colour interpolated across the face, white for primitives without one
+    pub color: Color,
 }
 
-pub struct HittableVec {
-    pub list: Vec<Box<dyn Hittable + Send + Sync>>,
-    bounds: AABB,
-}
-
-// #[derive(Clone, Copy)]
-// pub struct Hittables {
-//     pub list: [Hittable_Enum],
-//     pub bounds: AABB,
-
-// }
-
-// impl Hittables {
-//     pub fn new() -> Self {
-//         Hittables { list: [], bounds: AABB::default()}
-//     }
-
-
-//     pub fn add(&mut self, hittable: Hittable_Enum) {
-//         self.bounds.join(hittable.bounds());
-//         self.list.push(hittable);
-//     }
-
-//     pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
-//         // println!("World: {:?}", self.bounds);
-//         if self.bounds.hit(ray) { 
-//             let mut curr_record: Record = Record::new();
-//             let mut curr_hit: bool = false;
-//             let mut curr_closest: f64 = t_max;
-
-//             for object in &self.list {
-//                 // println!("Obj: {:?}", object.bounds());
-//                 let res = object.ray_hit(ray, t_min, curr_closest);
-//                 match res {
-//                     Some(x) => {
-//                         curr_record = x;
-//                         curr_hit = true;
-//                         curr_closest = curr_record.t;
-//                     },
-//                     None => {}
-//                 }
-//             }
-//             return if curr_hit {Some(curr_record)} else {None};
-//         }
-//         None
-//     }
-
-//     pub fn bounds(&self) -> &AABB {
-//         &self.bounds
-//     }
-
-//     pub fn centroid(&self) -> Vec3 {
-//         (self.bounds.min + self.bounds.max) * 0.5
-//     }
-
-//     pub fn length(&self) -> usize {
-//         self.list.len()
-//     }
-// }
-
-
-impl HittableVec {
-    pub fn new() -> Self {
-        HittableVec { list: Vec::new(), bounds: AABB::default()}
-    }
-
-    pub fn add(&mut self, hittable: impl Hittable + 'static + Send + Sync) -> () {
-        self.bounds.join(hittable.bounds());
-        self.list.push(Box::new(hittable));
+impl Default for Record {
+    fn default() -> Self {
+        Record::new()
     }
 }
 
-impl Hittable for HittableVec {
-    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
-        // println!("World: {:?}", self.bounds);
-        if self.bounds.hit(ray) { 
-            let mut curr_record: Record = Record::new();
-            let mut curr_hit: bool = false;
-            let mut curr_closest: f64 = t_max;
-
-            for object in &self.list {
-                // println!("Obj: {:?}", object.bounds());
-                let res = object.ray_hit(ray, t_min, curr_closest);
-                match res {
-                    Some(x) => {
-                        curr_record = x;
-                        curr_hit = true;
-                        curr_closest = curr_record.t;
-                    },
-                    None => {}
-                }
-            }
-            return if curr_hit {Some(curr_record)} else {None};
-        }
-        None
-    }
-
-    fn bounds(&self) -> &AABB {
-        &self.bounds
-    }
-
-    fn centroid(&self) -> Vec3 {
-        (self.bounds.min + self.bounds.max) * 0.5
-    }
-
-}
-
 impl Record{
     //default cons
     pub fn new() -> Record {
         Record{
             t: 0.0,
             point: Vec3::new(0.0,0.0,0.0),
+            object_point: Vec3::new(0.0,0.0,0.0),
             normal: Vec3::new(0.0,0.0,0.0),
+            tangent: Vec3::new(0.0,0.0,0.0),
+            bitangent: Vec3::new(0.0,0.0,0.0),
             outside_face: true,
-            material: Material::Empty,
-            u: None,
-            v: None
+            material: MaterialId::EMPTY,
+            u: 0.0,
+            v: 0.0,
+            color: WHITE,
         }
     }
 
-    pub fn calculate_normal(&mut self,ray: &Ray, normal: Vec3) -> () {
+    // Moves a hit found along the local version of ray (transform.inverse().ray(ray)) out to
+    // world space. t carries over, object_point stays in local space
+    pub fn transform(&mut self, transform: &Transform, ray: &Ray) {
+        let unit = |v: Vec3| if v.length() > 0.0 { v.unit() } else { v };
+        self.point = ray.ray_at(self.t);
+        self.normal = unit(transform.normal(self.normal));
+        self.tangent = unit(transform.vector(self.tangent));
+        self.bitangent = unit(transform.vector(self.bitangent));
+    }
+
+    pub fn calculate_normal(&mut self,ray: &Ray, normal: Vec3) {
         if Vec3::dot(ray.direction(), normal) > 0.0 {
             self.normal = -normal;
             self.outside_face = false;
@@ -1,32 +1,94 @@
-use crate::{material::Material, vec3::{Vec3, Point3}, sphere::Sphere, triangle::Triangle, hittable::{Record, Hittable}, ray::Ray, aabb::AABB, mesh::TriMesh};
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
+use crate::{vec3::Point3, material::MaterialTable, sphere::Sphere, triangle::Triangle, indexed_mesh::IndexedMesh, instance::Instance, plane::Plane, quad::Quad, disk::Disk, cylinder::Cylinder, cone::Cone, torus::Torus, cuboid::Cuboid, csg::Csg, sdf::Sdf, curve::Curve, heightfield::Heightfield, hittable::Record, ray::Ray, aabb::AABB};
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 pub enum Primitive {
     Sphere(Sphere),
     Triangle(Triangle),
+    IndexedMesh(Arc<IndexedMesh>),
+    Instance(Instance),
+    Plane(Plane),
+    Quad(Quad),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Cuboid(Cuboid),
+    Csg(Csg),
+    Sdf(Sdf),
+    Curve(Curve),
+    Heightfield(Heightfield),
 }
 
 
 impl Primitive {
-   pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
-    return match self {
+   // materials are only needed for the alpha tests of instances and meshes, which run their own BVH
+   pub fn ray_hit(&self, materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+    match self {
         Primitive::Sphere(s) => s.ray_hit(ray, t_min, t_max),
         Primitive::Triangle(t) => t.ray_hit(ray, t_min, t_max),
+        Primitive::IndexedMesh(m) => m.ray_hit(materials, ray, t_min, t_max),
+        Primitive::Instance(i) => i.ray_hit(materials, ray, t_min, t_max),
+        Primitive::Plane(p) => p.ray_hit(ray, t_min, t_max),
+        Primitive::Quad(q) => q.ray_hit(ray, t_min, t_max),
+        Primitive::Disk(d) => d.ray_hit(ray, t_min, t_max),
+        Primitive::Cylinder(c) => c.ray_hit(ray, t_min, t_max),
+        Primitive::Cone(c) => c.ray_hit(ray, t_min, t_max),
+        Primitive::Torus(t) => t.ray_hit(ray, t_min, t_max),
+        Primitive::Cuboid(c) => c.ray_hit(ray, t_min, t_max),
+        Primitive::Csg(c) => c.ray_hit(materials, ray, t_min, t_max),
+        Primitive::Sdf(s) => s.ray_hit(ray, t_min, t_max),
+        Primitive::Curve(c) => c.ray_hit(ray, t_min, t_max),
+        Primitive::Heightfield(h) => h.ray_hit(ray, t_min, t_max),
     }
    }
 
    pub fn bounds(&self) -> &AABB {
-    return match self {
+    match self {
         Primitive::Sphere(s) => &s.bounds,
         Primitive::Triangle(t) => &t.bounds,
+        Primitive::IndexedMesh(m) => &m.bounds,
+        Primitive::Instance(i) => &i.bounds,
+        Primitive::Plane(p) => &p.bounds,
+        Primitive::Quad(q) => &q.bounds,
+        Primitive::Disk(d) => &d.bounds,
+        Primitive::Cylinder(c) => &c.bounds,
+        Primitive::Cone(c) => &c.bounds,
+        Primitive::Torus(t) => &t.bounds,
+        Primitive::Cuboid(c) => &c.bounds,
+        Primitive::Csg(c) => &c.bounds,
+        Primitive::Sdf(s) => &s.bounds,
+        Primitive::Curve(c) => &c.bounds,
+        Primitive::Heightfield(h) => &h.bounds,
     }
    }
 
+   // false for infinite planes and anything instancing them. Empty instances count as unbounded
+   // too, their empty box has no centroid to sort by
+   pub fn is_bounded(&self) -> bool {
+    self.bounds().is_finite()
+   }
+
    pub fn centroid(&self) -> Point3 {
-    return match self {
+    match self {
         Primitive::Sphere(s) => s.centroid(),
         Primitive::Triangle(t) => t.centroid(),
-    };
+        Primitive::IndexedMesh(m) => m.centroid(),
+        Primitive::Instance(i) => i.centroid(),
+        Primitive::Plane(p) => p.centroid(),
+        Primitive::Quad(q) => q.centroid(),
+        Primitive::Disk(d) => d.centroid(),
+        Primitive::Cylinder(c) => c.centroid(),
+        Primitive::Cone(c) => c.centroid(),
+        Primitive::Torus(t) => t.centroid(),
+        Primitive::Cuboid(c) => c.centroid(),
+        Primitive::Csg(c) => c.centroid(),
+        Primitive::Sdf(s) => s.centroid(),
+        Primitive::Curve(c) => c.centroid(),
+        Primitive::Heightfield(h) => h.centroid(),
+    }
    }
 
 }
\ No newline at end of file
@@ -1,16 +1,20 @@
-use image::{RgbImage, Rgb};
+use std::path::Path;
+
+use image::{ImageResult, RgbImage, Rgb};
 
 use crate::color::Color;
 
-#[derive(Debug)]
+// A rendered frame. Pixels are stored row by row, top to bottom, as linear colour averaged over all samples.
+#[derive(Debug, Clone)]
 pub struct Image {
     width: u32,
     height: u32,
-    pub pixels: Vec<Color>
+    pixels: Vec<Color>
 }
 
 impl Image {
     pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize, "pixel buffer does not match image dimensions");
         Image {
             width,
             height,
@@ -18,22 +22,40 @@ impl Image {
         }
     }
 
-    pub fn export(&self, sample_size: i32) {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    // gamma corrected (gamma 2) 8 bit image
+    pub fn to_rgb8(&self) -> RgbImage {
         let mut img = RgbImage::new(self.width, self.height);
         for (x,y,pixel) in img.enumerate_pixels_mut() {
-            let color = &self.pixels[(y * self.width + x) as usize];
-            let sample_size = sample_size as f64;
-
-            let r = (f64::sqrt(color.x() / sample_size) * 255.0) as u8;
-            let g = (f64::sqrt(color.y() / sample_size) * 255.0) as u8;
-            let b = (f64::sqrt(color.z() / sample_size) * 255.0) as u8;
+            let color = self.pixel(x, y);
 
+            let r = (f64::sqrt(color.x()) * 255.0) as u8;
+            let g = (f64::sqrt(color.y()) * 255.0) as u8;
+            let b = (f64::sqrt(color.z()) * 255.0) as u8;
 
             *pixel = Rgb([r,g,b]);
-
         }
+        img
+    }
 
-        let _ = img.save("output.png");
+    // the file format is picked from the extension of path
+    pub fn export(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        self.to_rgb8().save(path)
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,201 @@
+// Triangles that share their vertices: one buffer per vertex attribute, in f32, and three u32
+// indices into them per face. The material is an id into the scene's MaterialTable. Faces are
+// only turned into full triangles at hit time, the mesh's own BVH refers to them by index. That
+// BVH dominates the memory: it has one leaf per face, so about two 80 byte nodes and a usize
+// index for each, which puts a face at roughly 180 bytes on top of its share of the vertices.
+
+use crate::{aabb::AABB, bvh::{opaque_hit, BVH}, hittable::Record, material::{MaterialId, MaterialTable}, mesh::MeshError, ray::Ray, triangle::{face_tangent, intersect, tangent_frame}, vec3::{Point3, Vec3}};
+
+#[derive(Debug)]
+pub struct IndexedMesh {
+    positions: Vec<[f32; 3]>,
+    indices: Vec<[u32; 3]>,
+    // optional vertex attributes, when present one per position
+    normals: Option<Vec<[f32; 3]>>,
+    uvs: Option<Vec<[f32; 2]>>,
+    // direction of increasing u, see mesh::vertex_tangents
+    tangents: Option<Vec<[f32; 3]>>,
+    // linear vertex colours, for materials with a vertex colour texture
+    colors: Option<Vec<[f32; 3]>>,
+    pub material: MaterialId,
+    bvh: BVH,
+    pub bounds: AABB,
+}
+
+impl IndexedMesh {
+    // fails if an index is not smaller than positions.len()
+    pub fn new(positions: Vec<[f32; 3]>, indices: Vec<[u32; 3]>, material: MaterialId) -> Result<Self, MeshError> {
+        let len = positions.len();
+        if let Some(index) = indices.iter().flatten().map(|i| *i as usize).find(|i| *i >= len) {
+            return Err(MeshError::VertexIndex { index, len });
+        }
+        let mut mesh = IndexedMesh {
+            positions, indices, normals: None, uvs: None, tangents: None, colors: None, material,
+            bvh: BVH::default(), bounds: AABB::default(),
+        };
+        mesh.bvh = BVH::build(mesh.indices.len(), |i| mesh.face_bounds(i), |i| {
+            let [p1, p2, p3] = mesh.corners(i);
+            (p1 + p2 + p3) / 3.0
+        });
+        mesh.bounds = *mesh.bvh.bounds();
+        Ok(mesh)
+    }
+
+    // The vertex attributes, each either None or one value per position
+    pub fn set_normals(&mut self, normals: Option<Vec<[f32; 3]>>) -> Result<(), MeshError> {
+        self.normals = self.per_vertex("normals", normals)?;
+        Ok(())
+    }
+
+    pub fn set_uvs(&mut self, uvs: Option<Vec<[f32; 2]>>) -> Result<(), MeshError> {
+        self.uvs = self.per_vertex("uvs", uvs)?;
+        Ok(())
+    }
+
+    pub fn set_tangents(&mut self, tangents: Option<Vec<[f32; 3]>>) -> Result<(), MeshError> {
+        self.tangents = self.per_vertex("tangents", tangents)?;
+        Ok(())
+    }
+
+    pub fn set_colors(&mut self, colors: Option<Vec<[f32; 3]>>) -> Result<(), MeshError> {
+        self.colors = self.per_vertex("colors", colors)?;
+        Ok(())
+    }
+
+    fn per_vertex<T>(&self, attribute: &'static str, values: Option<Vec<T>>) -> Result<Option<Vec<T>>, MeshError> {
+        match values {
+            Some(values) if values.len() != self.positions.len() => {
+                Err(MeshError::AttributeLength { attribute, len: values.len(), vertices: self.positions.len() })
+            }
+            values => Ok(values),
+        }
+    }
+
+    pub fn positions(&self) -> &[[f32; 3]] {
+        &self.positions
+    }
+
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    pub fn normals(&self) -> Option<&[[f32; 3]]> {
+        self.normals.as_deref()
+    }
+
+    pub fn uvs(&self) -> Option<&[[f32; 2]]> {
+        self.uvs.as_deref()
+    }
+
+    pub fn tangents(&self) -> Option<&[[f32; 3]]> {
+        self.tangents.as_deref()
+    }
+
+    pub fn colors(&self) -> Option<&[[f32; 3]]> {
+        self.colors.as_deref()
+    }
+
+    fn corners(&self, face: usize) -> [Point3; 3] {
+        self.indices[face].map(|i| vector(self.positions[i as usize]))
+    }
+
+    fn face_bounds(&self, face: usize) -> AABB {
+        let mut bounds = AABB::default();
+        for p in self.corners(face) {
+            bounds.add(p);
+        }
+        bounds
+    }
+
+    pub fn ray_hit(&self, materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        self.bvh.traverse(ray, t_min, t_max, |i, t_min, t_max| {
+            opaque_hit(materials, t_min, |t_min| self.face_hit(i, ray, t_min, t_max))
+        })
+    }
+
+    // the same shading as a Triangle with the face's vertex attributes
+    fn face_hit(&self, face: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        let p = self.corners(face);
+        let face_normal = Vec3::cross(p[1] - p[0], p[2] - p[0]);
+        let (t, w) = intersect(p, face_normal, ray, t_min, t_max)?;
+        let [i1, i2, i3] = self.indices[face].map(|i| i as usize);
+        // barycentric blend of a vertex attribute
+        let blend = |values: &[[f32; 3]]| w[0] * vector(values[i1]) + w[1] * vector(values[i2]) + w[2] * vector(values[i3]);
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.point = ray.ray_at(t);
+        return_record.object_point = return_record.point;
+        return_record.material = self.material;
+
+        let normal = match &self.normals {
+            Some(normals) => blend(normals),
+            None => face_normal,
+        };
+
+        // without texture coordinates the corners get (0,0), (1,0) and (0,1)
+        let uvs = match &self.uvs {
+            Some(uvs) => [i1, i2, i3].map(|i| (uvs[i][0] as f64, uvs[i][1] as f64)),
+            None => [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+        };
+        return_record.u = w[0] * uvs[0].0 + w[1] * uvs[1].0 + w[2] * uvs[2].0;
+        return_record.v = w[0] * uvs[0].1 + w[1] * uvs[1].1 + w[2] * uvs[2].1;
+
+        if let Some(colors) = &self.colors {
+            return_record.color = blend(colors);
+        }
+
+        let normal = normal.unit();
+        let (face_tangent, handedness) = face_tangent(p, uvs);
+        let tangent = match &self.tangents {
+            Some(tangents) => blend(tangents),
+            None => face_tangent,
+        };
+        (return_record.tangent, return_record.bitangent) = tangent_frame(normal, tangent, handedness);
+
+        return_record.calculate_normal(ray, normal);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
+
+fn vector([x, y, z]: [f32; 3]) -> Vec3 {
+    Vec3::newf32(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Vec<[f32; 3]> {
+        vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+    }
+
+    #[test]
+    fn index_past_the_vertices_is_an_error() {
+        let result = IndexedMesh::new(triangle(), vec![[0, 1, 3]], MaterialId::EMPTY);
+        assert!(matches!(result, Err(MeshError::VertexIndex { index: 3, len: 3 })));
+        assert!(IndexedMesh::new(triangle(), vec![[0, 1, 2]], MaterialId::EMPTY).is_ok());
+    }
+
+    #[test]
+    fn attributes_need_one_value_per_vertex() {
+        let mut mesh = IndexedMesh::new(triangle(), vec![[0, 1, 2]], MaterialId::EMPTY).unwrap();
+        let result = mesh.set_normals(Some(vec![[0.0, 0.0, 1.0]; 2]));
+        assert!(matches!(result, Err(MeshError::AttributeLength { attribute: "normals", len: 2, vertices: 3 })));
+        assert!(mesh.normals().is_none());
+        assert!(mesh.set_uvs(Some(vec![[0.0, 0.0]; 4])).is_err());
+        assert!(mesh.set_colors(Some(vec![[1.0, 0.0, 0.0]; 3])).is_ok());
+
+        // the attributes are blended across the face on a hit
+        let ray = Ray::new(Point3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh.ray_hit(&MaterialTable::new(), &ray, 0.001, f64::INFINITY).expect("ray should hit the triangle");
+        assert!((hit.color - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-6);
+    }
+}
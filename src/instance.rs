@@ -0,0 +1,89 @@
+// Instancing: one copy of some geometry, placed any number of times with a transform each.
+//
+// let car = Arc::new(Geometry::from(TriMesh::new("car.obj", MaterialId::EMPTY, scene.materials_mut())?));
+// for i in 0..100 {
+//     let transform = Transform::translate(Vec3::new(4.0 * i as f64, 0.0, 0.0));
+//     scene.add(Primitive::Instance(Instance::new(car.clone(), transform)));
+// }
+
+use std::sync::Arc;
+
+use crate::{aabb::AABB, bvh::BVH, hittable::Record, hittable2::Primitive, material::MaterialTable, mesh::TriMesh, ray::Ray, transform::Transform, vec3::Point3};
+
+// Primitives in their own space with their own bottom level BVH, shared between instances.
+// Moving an instance never touches this tree.
+pub struct Geometry {
+    primitives: Vec<Primitive>,
+    bvh: BVH,
+    bounds: AABB,
+}
+
+impl Geometry {
+    pub fn new(primitives: Vec<Primitive>) -> Self {
+        let bvh = BVH::new(&primitives);
+        let mut bounds = AABB::default();
+        for p in &primitives {
+            bounds.join(p.bounds());
+        }
+        Geometry { primitives, bvh, bounds }
+    }
+
+    pub fn primitives(&self) -> &[Primitive] {
+        &self.primitives
+    }
+
+    pub fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    pub fn ray_hit(&self, materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        self.bvh.ray_hit(&self.primitives, materials, ray, t_min, t_max)
+    }
+}
+
+impl From<TriMesh> for Geometry {
+    fn from(mesh: TriMesh) -> Self {
+        Geometry::new(mesh.meshes.into_iter().map(|m| Primitive::IndexedMesh(Arc::new(m))).collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct Instance {
+    geometry: Arc<Geometry>,
+    transform: Transform,
+    pub bounds: AABB,
+}
+
+impl Instance {
+    pub fn new(geometry: Arc<Geometry>, transform: Transform) -> Self {
+        // an empty geometry keeps the empty box rather than becoming unbounded
+        let bounds = if geometry.primitives.is_empty() { AABB::default() } else { transform.bounds(geometry.bounds()) };
+        Instance { geometry, transform, bounds }
+    }
+
+    pub fn geometry(&self) -> &Arc<Geometry> {
+        &self.geometry
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    // The ray is moved into the geometry's space and the hit moved back out. object_point is
+    // left in the geometry's space, so object space textures move with the instance.
+    pub fn ray_hit(&self, materials: &MaterialTable, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+
+        let local_ray = self.transform.inverse().ray(ray);
+        let mut record = self.geometry.ray_hit(materials, &local_ray, t_min, t_max)?;
+
+        record.transform(&self.transform, ray);
+        Some(record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
@@ -0,0 +1,95 @@
+//! Glint, a hobby pathtracer.
+//!
+//! Build a [`Scene`] in code or load one from a TOML scene file, then hand it
+//! to a [`Renderer`] to get an in-memory [`Image`]:
+//!
+//! ```no_run
+//! use glint::{Camera, Material, Point3, Primitive, Renderer, Scene, Sphere, Color};
+//!
+//! let mut camera = Camera::new();
+//! camera.image_width = 320;
+//! camera.image_height = 180;
+//!
+//! let mut scene = Scene::new(camera);
+//! let red = scene.add_material(Material::Diffuse { color: Color::new(0.8, 0.2, 0.1).into() });
+//! scene.add(Primitive::Sphere(Sphere::new(Point3::new(0.0, 0.0, -2.0), 1.0, red)));
+//!
+//! let image = Renderer::new().threads(4).render(&scene)?;
+//! let top_left = image.pixel(0, 0);
+//! # Ok::<(), glint::CameraError>(())
+//! ```
+//!
+//! The modules are private, everything a caller needs is re-exported from the crate root.
+
+mod vec3;
+mod color;
+mod ray;
+mod hittable;
+mod sphere;
+mod camera;
+mod util;
+mod noise;
+mod material;
+mod plane;
+mod quad;
+mod disk;
+mod cylinder;
+mod cone;
+mod torus;
+mod cuboid;
+mod csg;
+mod sdf;
+mod curve;
+mod heightfield;
+mod image;
+mod triangle;
+mod indexed_mesh;
+mod mesh;
+mod ply;
+mod stl;
+mod gltf_import;
+mod subdivision;
+mod aabb;
+mod bvh;
+mod hittable2;
+mod scene;
+mod renderer;
+mod texture;
+mod transform;
+mod instance;
+
+pub use crate::{
+    aabb::AABB,
+    bvh::{BVHNode, BVH},
+    camera::{Camera, CameraError},
+    color::Color,
+    cone::Cone,
+    cuboid::Cuboid,
+    curve::{load_strands, parse_strands, Curve, CurveMode},
+    csg::{Csg, CsgOp},
+    cylinder::Cylinder,
+    disk::Disk,
+    gltf_import::{GltfCamera, GltfScene},
+    heightfield::{Heightfield, HeightfieldError},
+    hittable::{Hittable, Record},
+    hittable2::Primitive,
+    image::Image,
+    indexed_mesh::IndexedMesh,
+    instance::{Geometry, Instance},
+    material::{Material, MaterialId, MaterialTable, NormalMap},
+    mesh::{Displacement, MeshError, MeshOptions, NormalMode, TriMesh},
+    plane::Plane,
+    ply::Ply,
+    quad::Quad,
+    ray::Ray,
+    renderer::Renderer,
+    scene::{ObjectId, Scene, SceneError},
+    sdf::{Sdf, SdfShape},
+    sphere::Sphere,
+    stl::Stl,
+    texture::{ColorSpace, Filter, ImageTexture, Pattern, Procedural, Texture, TextureSpace, WrapMode},
+    torus::Torus,
+    transform::{Matrix4, Transform},
+    triangle::Triangle,
+    vec3::{Point3, Vec3},
+};
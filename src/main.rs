@@ -1,124 +1,163 @@
-mod vec3;
-mod color;
-mod ray;
-mod hittable;
-mod sphere;
-mod camera;
-mod util;
-mod material;
-mod plane;
-mod image;
-mod triangle;
-mod mesh;
-mod aabb;
-mod bvh;
-mod hittable2;
+use std::{path::PathBuf, process, sync::Arc, time::Instant};
 
-use camera::Camera;
-use color::Color;
-use material::Material;
-use sphere::Sphere;
-use mesh::TriMesh;
-use vec3::{Point3, WHITE};
+use clap::{Args, Parser, Subcommand};
+use glint::{Geometry, Instance, Primitive, Renderer, Scene};
 
-use crate::{vec3::{Vec3, BLACK}, hittable::HittableVec, bvh::BVHNode, hittable2::Primitive};
-
-// where the raytracing appens
-
-fn main() {
-
-
-    //World is a list of objects that we want to be raytraced. So far there are spheres :)
-    let mut world: HittableVec = HittableVec::new();
+#[derive(Parser)]
+#[command(name = "raytracer", version, about = "Glint, a hobby pathtracer")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
+#[derive(Subcommand)]
+enum Command {
+    /// Render a scene file to an image
+    Render(RenderArgs),
+    /// Print a summary of a scene file
+    Info {
+        scene: PathBuf,
+    },
+    /// Check that a scene file loads, exiting non-zero if it does not
+    Validate {
+        scene: PathBuf,
+    },
+}
 
-    // let ground_material = Material::Metal { color: Color::new(0.98, 0.75, 0.24), roughness: 0.0};
-    let ground_material = Material::Glossy { color: Color::new(1.0, 0.3, 0.2), specularity: 0.15, roughness: 0.3};
+#[derive(Args)]
+struct RenderArgs {
+    scene: PathBuf,
+    /// Output image, the format is picked from the extension
+    #[arg(short, long, default_value = "output.png")]
+    output: PathBuf,
+    #[arg(long)]
+    width: Option<i32>,
+    #[arg(long)]
+    height: Option<i32>,
+    #[arg(short, long)]
+    samples: Option<i32>,
+    #[arg(long)]
+    ray_depth: Option<i32>,
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+}
 
-    let m = TriMesh::new("car.obj", Material::Glossy { color: WHITE, roughness: 0.0, specularity: 0.02});
+// where the raytracing appens
 
-    let mut primitives: Vec<Primitive> = vec![];
-    let mut i: usize = 0;
-    let mut indices = vec![];
-    for t in m.triangles {
-    // primitives.push(Primitive::Sphere(Sphere::new(Vec3::default(), 1.0, Material::Empty)));
-    // indices.push(i);
-    // i += 1;
-    // primitives.push(Primitive::Sphere(Sphere::new(Vec3::default(), 2.0, Material::Empty)));
-    // indices.push(i);
-    // i += 1;
-    // primitives.push(Primitive::Sphere(Sphere::new(Vec3::default(), 3.0, Material::Empty)));
-        primitives.push(Primitive::Triangle(t));
-        indices.push(i);
-        i += 1;
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Render(args) => render(args),
+        Command::Info { scene } => info(&load(&scene)),
+        Command::Validate { scene } => {
+            load(&scene);
+            println!("{}: ok", scene.display());
+        }
     }
+}
 
-    // primitives.push(Primitive::Sphere(
-    //     Sphere::new(
-    //         Point3::new(0.0, -50.0, 0.0), 
-    //         50.0, 
-    //         ground_material
-    //     )
-    // ));
-    // indices.push(i);
-    // i+= 1;
-
-    // primitives.push(Primitive::Sphere(
-    //     Sphere::new(
-    //         Point3::new(0.0, 1100.0, 0.0), 
-    //         1000.0, 
-    //         Material::Emission { color: WHITE, strength: 1.0 }
-    //     )
-    // ));
-    // indices.push(i);
-    // i+= 1;
-
-
-    // primitives.push(Primitive::Sphere(
-    //     Sphere::new(
-    //         Point3::new(-15.0, 0.0, 10.0), 
-    //         5.0, 
-    //         Material::Emission { 
-    //             color: Color::new(0.0, 0.0, 1.0), 
-    //             strength: 2.0 }
-    //     )
-    // ));
-    // indices.push(i);
-    // i+= 1;
-
-    let mut bvh = BVHNode::default();
-    println!("Building BVH...");
-    bvh = bvh.new(&primitives, &mut indices, 0, i, 1024);
-    println!("BVH Built");
-
-    let mut camera: Camera = Camera::new();
-    camera.image_width = 1280;
-    camera.image_height = 720;
-    camera.samples = 100;
-    camera.ray_depth = 5;
-    camera.fov = 35.0;
-    // camera.look_from = Point3::new(60.0, 48.0,80.0);
-    // camera.look_at = Vec3::new(0.0, 23.0, 0.0);
-
-    // camera.look_from = Point3::new(16.0, 6.0,18.0);
-    // camera.look_at = Vec3::new(0.0, 1.5, 0.0);
-
-    // camera.look_from = Point3::new(-1.4, -0.3,9.0);
-    // camera.look_at = Vec3::new(-1.4, -0.3, 0.0);
-
-    // camera.look_from = Point3::new(4.0, 1.5,15.3);
-    camera.look_from = Point3::new(4.67,0.77,15.2);
-    camera.look_at = Vec3::new(0.34, 1.42, 7.78);
-
-    // camera.look_at = Vec3::new(-3.0, 1.65, 1.92);
+fn load(path: &PathBuf) -> Scene {
+    match Scene::load(path) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
 
-    camera.defocus_angle = 2.0;
-    camera.focus_dist = 8.5;
+fn render(args: RenderArgs) {
+    let mut scene = load(&args.scene);
 
+    let camera = &mut scene.camera;
+    if let Some(width) = args.width {
+        camera.image_width = width;
+    }
+    if let Some(height) = args.height {
+        camera.image_height = height;
+    }
+    if let Some(samples) = args.samples {
+        camera.samples = samples;
+    }
+    if let Some(ray_depth) = args.ray_depth {
+        camera.ray_depth = ray_depth;
+    }
+    // the scene file was checked when it loaded, this catches bad overrides
+    if let Err(e) = camera.validate() {
+        eprintln!("error: camera {e}");
+        process::exit(2);
+    }
 
+    let mut renderer = Renderer::new();
+    if let Some(threads) = args.threads {
+        renderer = renderer.threads(threads);
+    }
 
-    camera.render(&world, &bvh, &primitives);
-    camera.output.export(camera.samples);
+    println!("Rendering {}x{} at {} samples...", scene.camera.image_width, scene.camera.image_height, scene.camera.samples);
+    let start = Instant::now();
+    let image = renderer.render(&scene).expect("camera was validated above");
+    println!("Rendered in {:.1}s", start.elapsed().as_secs_f64());
+    if let Err(e) = image.export(&args.output) {
+        eprintln!("error: could not write {}: {e}", args.output.display());
+        process::exit(1);
+    }
+    println!("Wrote {}", args.output.display());
+}
 
-   
+fn info(scene: &Scene) {
+    let camera = &scene.camera;
+    println!("Camera:");
+    println!("  resolution: {}x{}", camera.image_width, camera.image_height);
+    println!("  samples: {}, ray depth: {}", camera.samples, camera.ray_depth);
+    println!("  look from: {}, look at: {}", camera.look_from, camera.look_at);
+    println!("  fov: {}, defocus angle: {}, focus dist: {}", camera.fov, camera.defocus_angle, camera.focus_dist);
+
+    let primitives = scene.primitives();
+    let spheres = primitives.iter().filter(|p| matches!(p, Primitive::Sphere(_))).count();
+    let planes = primitives.iter().filter(|p| matches!(p, Primitive::Plane(_))).count();
+    let quads = primitives.iter().filter(|p| matches!(p, Primitive::Quad(_))).count();
+    let disks = primitives.iter().filter(|p| matches!(p, Primitive::Disk(_))).count();
+    let solids = primitives.iter().filter(|p| matches!(p, Primitive::Cylinder(_) | Primitive::Cone(_) | Primitive::Torus(_) | Primitive::Cuboid(_))).count();
+    let csg = primitives.iter().filter(|p| matches!(p, Primitive::Csg(_))).count();
+    let sdfs = primitives.iter().filter(|p| matches!(p, Primitive::Sdf(_))).count();
+    let heightfields = primitives.iter().filter(|p| matches!(p, Primitive::Heightfield(_))).count();
+    // meshes count their faces
+    let triangle_count = |p: &Primitive| match p {
+        Primitive::Triangle(_) => 1,
+        Primitive::IndexedMesh(m) => m.indices().len(),
+        _ => 0,
+    };
+    let mut triangles: usize = primitives.iter().map(triangle_count).sum();
+    let instances: Vec<&Instance> = primitives.iter().filter_map(|p| match p {
+        Primitive::Instance(i) => Some(i),
+        _ => None,
+    }).collect();
+    // instanced geometry is only stored once
+    let mut geometries: Vec<&Arc<Geometry>> = instances.iter().map(|i| i.geometry()).collect();
+    geometries.sort_by_key(|g| Arc::as_ptr(g));
+    geometries.dedup_by_key(|g| Arc::as_ptr(g));
+    let mut curves = primitives.iter().filter(|p| matches!(p, Primitive::Curve(_))).count();
+    for g in &geometries {
+        triangles += g.primitives().iter().map(triangle_count).sum::<usize>();
+        curves += g.primitives().iter().filter(|p| matches!(p, Primitive::Curve(_))).count();
+    }
+    println!("Objects: {}", primitives.len());
+    println!("  spheres: {spheres}");
+    println!("  planes: {planes}");
+    println!("  quads: {quads}");
+    println!("  disks: {disks}");
+    println!("  cylinders, cones, tori and boxes: {solids}");
+    println!("  csg objects: {csg}");
+    println!("  distance fields: {sdfs}");
+    println!("  heightfields: {heightfields}");
+    println!("  instances: {} of {} geometries", instances.len(), geometries.len());
+    println!("Triangles: {triangles}");
+    println!("Curves: {curves}");
+
+    if primitives.iter().any(|p| p.is_bounded()) {
+        let bounds = scene.bounds();
+        println!("Bounds: {} to {}", bounds.min, bounds.max);
+    }
 }
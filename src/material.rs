@@ -1,41 +1,148 @@
 #![allow(unused)]
-use crate::{hittable::Record, color::Color, ray::Ray, vec3::{Vec3, WHITE, BLACK}, util::gen_random};
+use std::ops::Index;
 
-#[derive(Debug, Clone, Copy)]
+use crate::{hittable::Record, color::Color, ray::Ray, vec3::{Vec3, WHITE, BLACK}, util::gen_random, texture::Texture};
+
+// Colours, roughness and specularity are textures looked up at the hit's uv, plain values
+// convert with `.into()`, e.g. Material::Diffuse { color: Color::new(1.0, 0.0, 0.0).into() }
+#[derive(Debug, Clone)]
 pub enum Material {
     Diffuse {
-        color: Color
-    }, 
+        color: Texture
+    },
     Metal {
-        color: Color,
-        roughness: f64,
+        color: Texture,
+        roughness: Texture,
     },
     Dielectric {
-        color: Color,
+        color: Texture,
         ior: f64
     },
     Glossy {
-        color: Color,
-        specularity:f64,
-        roughness: f64
+        color: Texture,
+        specularity: Texture,
+        roughness: Texture
     },
     Emission {
-        color: Color,
+        color: Texture,
         strength: f64,
     },
+    // another material shaded with a perturbed normal
+    NormalMapped {
+        base: Box<Material>,
+        map: NormalMap,
+    },
+    // another material cut out by an opacity texture (1 opaque, 0 a hole). The BVH skips hits
+    // that fail the alpha test, fractional opacity lets a matching share of rays through
+    Masked {
+        base: Box<Material>,
+        opacity: Texture,
+    },
     UV,
     Stripes,
     Empty,
 }
 
+#[derive(Debug, Clone)]
+pub enum NormalMap {
+    // tangent space normals encoded as rgb = (n + 1) / 2, strength scales the tilt
+    Tangent { map: Texture, strength: f64 },
+    // grayscale heights, strength scales the slope. A full black to white step between two
+    // neighbouring texels tilts the normal by 45 degrees at strength 1
+    Bump { height: Texture, strength: f64 },
+}
+
+impl NormalMap {
+    pub fn perturb(&self, record: &Record) -> Vec3 {
+        // the tangent frame belongs to the outward normal, mirror it along with the normal for back faces
+        let (tangent, bitangent) = if record.outside_face {
+            (record.tangent, record.bitangent)
+        } else {
+            (-record.tangent, -record.bitangent)
+        };
+        let normal = match self {
+            NormalMap::Tangent { map, strength } => {
+                let c = map.at(record);
+                let (x, y, z) = (2.0 * c.x() - 1.0, 2.0 * c.y() - 1.0, 2.0 * c.z() - 1.0);
+                (x * strength) * tangent + (y * strength) * bitangent + z * record.normal
+            }
+            NormalMap::Bump { height, strength } => {
+                let (du, dv) = height.height_gradient(record);
+                record.normal - (du * strength) * tangent - (dv * strength) * bitangent
+            }
+        };
+        // keep the geometric normal when the map would turn the surface away from the ray
+        if normal.length() == 0.0 || Vec3::dot(normal, record.normal) <= 0.0 {
+            return record.normal;
+        }
+        normal.unit()
+    }
+}
+
+// Index of a material in a MaterialTable. Primitives and hit records carry these instead of the
+// material itself, so a million triangles share one copy of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaterialId(pub u32);
+
+impl MaterialId {
+    // Material::Empty, every table starts with it
+    pub const EMPTY: MaterialId = MaterialId(0);
+}
+
+// The materials of a scene, looked up with the ids add hands out
+#[derive(Debug, Clone)]
+pub struct MaterialTable {
+    materials: Vec<Material>,
+}
+
+impl Default for MaterialTable {
+    fn default() -> Self {
+        MaterialTable::new()
+    }
+}
+
+impl MaterialTable {
+    pub fn new() -> Self {
+        MaterialTable { materials: vec![Material::Empty] }
+    }
+
+    pub fn add(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        MaterialId((self.materials.len() - 1) as u32)
+    }
+}
+
+impl Index<MaterialId> for MaterialTable {
+    type Output = Material;
+
+    // panics for ids that belong to a different table
+    fn index(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0 as usize]
+    }
+}
+
 impl Material {
 
-    pub fn emit(&self) -> Color {
+    pub fn opacity(&self, curr_record: &Record) -> f64 {
+        match self {
+            Material::Masked { base, opacity } => opacity.scalar_at(curr_record) * base.opacity(curr_record),
+            Material::NormalMapped { base, .. } => base.opacity(curr_record),
+            _ => 1.0
+        }
+    }
+
+    // stochastic transparency: whether a ray stops at this hit or passes through it
+    pub fn alpha_test(&self, curr_record: &Record) -> bool {
+        let opacity = self.opacity(curr_record);
+        opacity >= 1.0 || (opacity > 0.0 && gen_random() < opacity)
+    }
+
+    pub fn emit(&self, curr_record: &Record) -> Color {
         match self {
             Material::Emission { color, strength } => {
-                // println!("Emit");
-                *color * *strength
+                color.at(curr_record) * *strength
             }
+            Material::NormalMapped { base, .. } | Material::Masked { base, .. } => base.emit(curr_record),
             _ => Color::default()
         }
 
@@ -46,7 +153,7 @@ impl Material {
             Material::Diffuse { color } => {
                 let scatter_dir = Vec3::vec_in_unit_hemisphere(curr_record.normal) + curr_record.normal;
                 let ray_out = Ray::new(curr_record.point, scatter_dir);
-                let color_out = *color;
+                let color_out = color.at(curr_record);
                 Some((color_out, ray_out))
             }
             Material::Metal { color, roughness } => {
@@ -55,12 +162,12 @@ impl Material {
                 let cos = f64::min(Vec3::dot(-ray_in.direction.unit(), curr_record.normal.unit()), 1.0);
                 let mut r0 = (1.0 - refractive_ratio)/(1.0 + refractive_ratio);
                 r0 *= r0;
-                let R = r0 + (1.0 - r0)*f64::powi(1.0 - cos, 5);
+                let r = r0 + (1.0 - r0)*f64::powi(1.0 - cos, 5);
 
                 let mut scatter_dir = Vec3::reflect(ray_in.direction, curr_record.normal);
-                scatter_dir = scatter_dir + (*roughness * Vec3::random_unit_vec());
+                scatter_dir = scatter_dir + (roughness.scalar_at(curr_record) * Vec3::random_unit_vec());
                 let ray_out = Ray::new(curr_record.point, scatter_dir);
-                let color_out = (1.0 - R) * *color + R * WHITE;
+                let color_out = (1.0 - r) * color.at(curr_record) + r * WHITE;
 
                 Some((color_out, ray_out))
             },
@@ -75,16 +182,18 @@ impl Material {
             Material::Stripes => {
                 let black = Color::default();
                 let white = Color::new(1.0,1.0,1.0);
-                let f: Color;
-
-               
-                
                 let a = (f64::sin(curr_record.point.x()) + 1.0) / 2.0 + (f64::sin(curr_record.point.y()) + 1.0) / 2.0 ;
 
-                f = (1.0 - a) * black + a* white;
+                let f = (1.0 - a) * black + a* white;
 
                 Some((f, Ray::new(curr_record.point, curr_record.normal)))
             }
+            Material::NormalMapped { base, map } => {
+                let mut record = curr_record.clone();
+                record.normal = map.perturb(curr_record);
+                base.scatter(ray_in, &record)
+            }
+            Material::Masked { base, .. } => base.scatter(ray_in, curr_record),
             Material::Empty => None,
             Material::Dielectric { ior, color } => {
                 let refractive_ratio: f64 = if curr_record.outside_face {1.0/ior} else {*ior};
@@ -92,24 +201,24 @@ impl Material {
                 let cos = f64::min(Vec3::dot(-ray_in.direction.unit(), curr_record.normal.unit()), 1.0);
                 let sin = f64::sqrt(1.0 - cos * cos);
 
-                let scatter_dir: Vec3;
-
                 let mut r0 = (1.0 - refractive_ratio)/(1.0 + refractive_ratio);
                 r0 *= r0;
                 let reflectance = r0 + (1.0 - r0)*f64::powi(1.0 - cos, 5);
 
-                if refractive_ratio * sin > 1.0 || reflectance > gen_random() {
-                    scatter_dir = Vec3::reflect(ray_in.direction.unit(), curr_record.normal);
+                let scatter_dir = if refractive_ratio * sin > 1.0 || reflectance > gen_random() {
+                    Vec3::reflect(ray_in.direction.unit(), curr_record.normal)
                 } else {
-                    scatter_dir = Vec3::refract(ray_in.direction.unit(), curr_record.normal, refractive_ratio);
-                }
+                    Vec3::refract(ray_in.direction.unit(), curr_record.normal, refractive_ratio)
+                };
 
-                let out = (1.0 - reflectance) * (*color) + reflectance * WHITE;
+                let out = (1.0 - reflectance) * color.at(curr_record) + reflectance * WHITE;
 
                 let ray_out : Ray = Ray::new(curr_record.point, scatter_dir);
                 Some((out, ray_out))
             },
             Material::Glossy { specularity, roughness, color } => {
+                let specularity = specularity.scalar_at(curr_record);
+                let roughness = roughness.scalar_at(curr_record);
 
                 let cos = f64::min(Vec3::dot(-ray_in.direction.unit(), curr_record.normal.unit()), 1.0);
                 let reflectance = specularity + (1.0 - specularity)*f64::powi(1.0 - cos, 5);
@@ -120,12 +229,12 @@ impl Material {
                 let diff_dir = (Vec3::vec_in_unit_hemisphere(curr_record.normal) + curr_record.normal).unit();
                 let reflection_dir = Vec3::reflect(ray_in.direction, curr_record.normal).unit();
 
-                let mut scatter_dir = reflection_dir * ((is_specular * (1.0 - roughness))) + (1.0 - (is_specular * (1.0 - roughness))) * diff_dir;
+                let mut scatter_dir = reflection_dir * (is_specular * (1.0 - roughness)) + (1.0 - (is_specular * (1.0 - roughness))) * diff_dir;
                 scatter_dir = scatter_dir.unit();
 
 
                 let ray_out = Ray::new(curr_record.point, scatter_dir);
-                let color_out = (1.0 - is_specular) * *color + is_specular * WHITE;
+                let color_out = (1.0 - is_specular) * color.at(curr_record) + is_specular * WHITE;
 
                 Some((color_out, ray_out))
 
@@ -135,4 +244,53 @@ impl Material {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a hit on a surface facing +z, u along +x and v along +y
+    fn record() -> Record {
+        let mut record = Record::new();
+        record.normal = Vec3::new(0.0, 0.0, 1.0);
+        record.tangent = Vec3::new(1.0, 0.0, 0.0);
+        record.bitangent = Vec3::new(0.0, 1.0, 0.0);
+        record
+    }
+
+    fn tangent_map(r: f64, g: f64, b: f64, strength: f64) -> NormalMap {
+        NormalMap::Tangent { map: Color::new(r, g, b).into(), strength }
+    }
+
+    fn assert_vec(v: Vec3, expected: Vec3) {
+        assert!((v - expected).length() < 1e-9, "{:?}, expected {:?}", v, expected);
+    }
+
+    #[test]
+    fn flat_texel_keeps_the_normal() {
+        assert_vec(tangent_map(0.5, 0.5, 1.0, 1.0).perturb(&record()), Vec3::new(0.0, 0.0, 1.0));
+        // a constant height has no slope
+        let bump = NormalMap::Bump { height: 0.3.into(), strength: 2.0 };
+        assert_vec(bump.perturb(&record()), Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn tilted_texels_lean_along_the_tangent_frame() {
+        // +x in the map is the direction of increasing u, +y of increasing v
+        assert_vec(tangent_map(1.0, 0.5, 1.0, 1.0).perturb(&record()), Vec3::new(1.0, 0.0, 1.0).unit());
+        assert_vec(tangent_map(0.5, 0.0, 1.0, 1.0).perturb(&record()), Vec3::new(0.0, -1.0, 1.0).unit());
+        // strength scales the tilt, not the normal
+        assert_vec(tangent_map(1.0, 0.5, 1.0, 0.5).perturb(&record()), Vec3::new(0.5, 0.0, 1.0).unit());
+        // the back face mirrors the frame with the normal
+        let mut back = record();
+        back.normal = -back.normal;
+        back.outside_face = false;
+        assert_vec(tangent_map(1.0, 0.5, 1.0, 1.0).perturb(&back), Vec3::new(-1.0, 0.0, -1.0).unit());
+    }
+
+    #[test]
+    fn texels_pointing_into_the_surface_are_ignored() {
+        assert_vec(tangent_map(1.0, 0.5, 0.5, 1.0).perturb(&record()), Vec3::new(0.0, 0.0, 1.0));
+        assert_vec(tangent_map(0.5, 0.5, 0.0, 1.0).perturb(&record()), Vec3::new(0.0, 0.0, 1.0));
+    }
+}
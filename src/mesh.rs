@@ -1,117 +1,923 @@
-use tobj::GPU_LOAD_OPTIONS;
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}, sync::Arc};
 
-use crate::{aabb::{AABB, self}, color::Color, hittable::{Hittable, Record}, material::{self, Material}, triangle::Triangle, vec3::Vec3};
+use serde::Deserialize;
+use tobj::{LoadError, LoadOptions, GPU_LOAD_OPTIONS};
 
+use crate::{color::Color, indexed_mesh::IndexedMesh, material::{Material, MaterialId, MaterialTable, NormalMap}, ply::Ply, stl::Stl, subdivision::PolyMesh, texture::{ColorSpace, ImageTexture, Texture}, vec3::{Point3, Vec3}};
+
+#[derive(Debug)]
+pub enum MeshError {
+    MissingFile(PathBuf),
+    Parse(PathBuf, String),
+    // the OBJ references an .mtl file that could not be opened
+    MissingMtl(PathBuf),
+    IndexOutOfRange { file: PathBuf, index: usize, len: usize },
+    // more vertices than the u32 indices of an IndexedMesh can address
+    TooManyVertices { file: PathBuf, len: usize },
+    // a PLY file without faces, which is a point cloud rather than a mesh
+    PointCloud(PathBuf),
+    // an IndexedMesh built in code with a face past its vertices, or with a vertex attribute
+    // that does not have one value per vertex
+    VertexIndex { index: usize, len: usize },
+    AttributeLength { attribute: &'static str, len: usize, vertices: usize },
+    // an image map referenced by the .mtl file could not be loaded
+    Texture(PathBuf, String),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::MissingFile(path) => write!(f, "mesh file {} not found", path.display()),
+            MeshError::Parse(path, msg) => write!(f, "could not parse {}: {}", path.display(), msg),
+            MeshError::MissingMtl(path) => write!(f, "material library referenced by {} not found", path.display()),
+            MeshError::IndexOutOfRange { file, index, len } => {
+                write!(f, "{}: vertex index {} out of range, the mesh has {} vertices", file.display(), index, len)
+            }
+            MeshError::TooManyVertices { file, len } => {
+                write!(f, "{}: {} vertices are more than a mesh can index, the limit is {}", file.display(), len, u32::MAX)
+            }
+            MeshError::PointCloud(path) => {
+                write!(f, "{} has no faces, load point clouds with [[points]] instead of [[meshes]]", path.display())
+            }
+            MeshError::VertexIndex { index, len } => write!(f, "vertex index {} out of range, the mesh has {} vertices", index, len),
+            MeshError::AttributeLength { attribute, len, vertices } => {
+                write!(f, "{} {} for a mesh with {} vertices", len, attribute, vertices)
+            }
+            MeshError::Texture(path, msg) => write!(f, "could not load texture {}: {}", path.display(), msg),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// How to shade meshes that come without vertex normals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalMode {
+    // one normal per face, facets stay visible
+    Flat,
+    // per vertex normals averaged from the adjacent faces, weighted by the angle of each face at the vertex
+    #[default]
+    Smooth,
+}
+
+// degrees between the faces of an STL past which its edges stay hard, when no crease angle is given
+pub const STL_CREASE_ANGLE: f64 = 30.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct MeshOptions {
+    pub normals: NormalMode,
+    // levels of subdivision applied before the mesh is split into triangles: Loop for triangle
+    // meshes, Catmull-Clark when there are quads or bigger polygons. Normals are recomputed
+    pub subdivision: u32,
+    // faces meeting at a sharper angle (in degrees) than this keep a hard edge, both when
+    // subdividing and in generated smooth normals
+    pub crease_angle: Option<f64>,
+    // applied after subdivision, which gives the detail vertices to move
+    pub displacement: Option<Displacement>,
+}
+
+// Moves every vertex along its normal by (height - mid_level) * scale, reading the height
+// from map at the vertex's uv and object space position. Unlike a bump map this changes the
+// silhouette and the shadows, but detail finer than the mesh's edges is lost.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    pub map: Texture,
+    pub scale: f64,
+    // the height that stays in place, lower heights push the surface in
+    pub mid_level: f64,
+}
+
+// vertex data of one model, shared between the faces that index it
+pub(crate) struct MeshData {
+    pub(crate) positions: Vec<Point3>,
+    pub(crate) normals: Option<Vec<Vec3>>,
+    pub(crate) uvs: Option<Vec<(f64, f64)>>,
+    pub(crate) colors: Option<Vec<Color>>,
+    pub(crate) faces: Vec<[usize; 3]>,
+}
+
+// A mesh file, one IndexedMesh for each of its models, e.g. the groups of an OBJ
 pub struct TriMesh {
-    pub triangles: Vec<Triangle>,
-    bounds: AABB
+    pub meshes: Vec<IndexedMesh>,
 }
 
 impl TriMesh {
-    pub fn new(file_name: &str, material: Material) -> TriMesh{
-        let obj = tobj::load_obj(file_name, &GPU_LOAD_OPTIONS);
-        let (models, mats) = obj.unwrap();
-        let materials = mats.unwrap();
+    pub fn new(file_name: impl AsRef<Path>, material: MaterialId, materials: &mut MaterialTable) -> Result<TriMesh, MeshError> {
+        TriMesh::load(file_name, material, &MeshOptions::default(), materials)
+    }
 
-        let mut triangles: Vec<Triangle> = Vec::new();
-        for model in models {
-            let mat = match model.mesh.material_id {
-                Some(id) => match (materials[id].diffuse, &materials[id].name) {
-                    (Some([r,g,b]), name) => {
-                        println!("{name}");
-                        if name.starts_with("d") {
-                            Material::Diffuse { color: Color::newf32(r, g, b) }
-                        } else if name.starts_with("g") {
-                            Material::Glossy { color: Color::newf32(r, g, b), specularity: 0.02, roughness: 0.0 }
-                        } else if name.starts_with("e") {
-                            Material::Emission { color: Color::newf32(r, g, b), strength: 2.0 }
-                        } else if name.starts_with("m") {
-                            Material::Metal { color: Color::newf32(r, g, b), roughness: 0.3 }
-                        } else if name.starts_with("t") {
-                            Material::Dielectric { color: Color::newf32(r, g, b), ior: 1.5 }
-                        } else {
-                            Material::Empty
-                        }
-                    },
-                    _ => Material::Empty
+    // OBJ, PLY or STL files, by extension. The .mtl materials are added to materials, material
+    // is used for faces without one that can be mapped; meshes with vertex colours and no
+    // material (MaterialId::EMPTY) get a diffuse vertex colour one
+    pub fn load(file_name: impl AsRef<Path>, material: MaterialId, options: &MeshOptions, materials: &mut MaterialTable) -> Result<TriMesh, MeshError> {
+        let path = file_name.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+        // CAD models are mostly flat faces meeting at hard edges, so STLs keep their edges
+        // unless the options give a crease angle of their own
+        let stl_options;
+        let options = if extension.as_deref() == Some("stl") {
+            stl_options = MeshOptions { crease_angle: options.crease_angle.or(Some(STL_CREASE_ANGLE)), ..options.clone() };
+            &stl_options
+        } else {
+            options
+        };
+        let (models, file_materials) = match extension.as_deref() {
+            Some("ply") => {
+                let ply = Ply::load(path)?;
+                if ply.is_point_cloud() {
+                    return Err(MeshError::PointCloud(path.to_path_buf()));
                 }
-                None => Material::Empty
+                (vec![ply_model(ply, options)], Vec::new())
+            }
+            Some("stl") => {
+                let stl = Stl::load(path)?;
+                let ply = Ply { positions: stl.positions, faces: stl.faces.iter().map(|f| f.to_vec()).collect(), ..Ply::default() };
+                (vec![ply_model(ply, options)], Vec::new())
+            }
+            _ => load_obj(path, options)?,
+        };
+        let ids: Vec<MaterialId> = file_materials.into_iter().map(|m| materials.add(m)).collect();
+
+        let mut meshes = Vec::new();
+        for model in models {
+            let id = model.mesh.material_id.and_then(|id| ids.get(id)).copied().unwrap_or(material);
+            let data = if options.subdivision > 0 {
+                subdivided(&model.mesh, path, options)?
+            } else {
+                indexed(&model.mesh, path)?
             };
+            if !data.faces.is_empty() {
+                meshes.push(data.into_mesh(path, id, options, materials)?);
+            }
+        }
+        Ok(TriMesh { meshes })
+    }
+}
+
+impl MeshData {
+    // Displaces the vertices, works out the normals and tangents the options ask for and packs
+    // the lot into an IndexedMesh. path is the file the data came from, for errors.
+    pub(crate) fn into_mesh(mut self, path: &Path, mut material: MaterialId, options: &MeshOptions, materials: &mut MaterialTable) -> Result<IndexedMesh, MeshError> {
+        if let Some(displacement) = &options.displacement {
+            displace(&mut self, displacement);
+        }
+        let MeshData { mut positions, normals: file_normals, mut uvs, mut colors, mut faces } = self;
+        if colors.is_some() && matches!(materials[material], Material::Empty) {
+            material = materials.add(Material::Diffuse { color: Texture::VertexColor });
+        }
 
-            for i in (0..model.mesh.indices.len()).step_by(3) {
-                // println!("{} {} {}", model.mesh.indices[i], model.mesh.indices[i + 1], model.mesh.indices[i + 2])
-                let f1 = model.mesh.indices[i] as usize;
-                let f2 = model.mesh.indices[i + 1] as usize;
-                let f3 = model.mesh.indices[i + 2] as usize;
-
-                let p1 = &model.mesh.positions[3 * f1..3 * f1+3];
-                let p2 = &model.mesh.positions[3 * f2..3 * f2+3];
-                let p3 = &model.mesh.positions[3 * f3..3 * f3+3];
-
-                let n1 = &model.mesh.normals[3 * f1..3 * f1+3];
-                let n2 = &model.mesh.normals[3 * f2..3 * f2+3];
-                let n3 = &model.mesh.normals[3 * f3..3 * f3+3];
-
-                let mut t = Triangle::new(
-                    Vec3::newf32(p1[0], p1[1], p1[2]), 
-                    Vec3::newf32(p2[0], p2[1], p2[2]), 
-                    Vec3::newf32(p3[0], p3[1], p3[2]),
-                    // Material::Diffuse { color: color }
-                    // Material::Metal { color: color, roughness: 0.0 }
-                    // Material::Glossy { color: color, specularity: 0.15, roughness: 0.0 }
-                    mat
-                );
-                t.n1 = Some(Vec3::newf32(n1[0], n1[1], n1[2]));
-                t.n2 = Some(Vec3::newf32(n2[0], n2[1], n2[2]));
-                t.n3 = Some(Vec3::newf32(n3[0], n3[1], n3[2]));
-                // println!("{:?}", t);
-                triangles.push(t);
+        // files without normals get generated ones
+        let generated = file_normals.is_none();
+        let mut normals = match (file_normals, options.normals) {
+            (Some(normals), _) => Some(normals),
+            (None, NormalMode::Flat) => None,
+            (None, NormalMode::Smooth) => Some(smooth_normals(&positions, &faces)),
+        };
+
+        // tangent frames for normal and bump maps follow the uvs. They only need to be roughly
+        // right, each hit makes its tangent perpendicular to the shading normal again
+        let mut tangents = match (&normals, &uvs) {
+            (Some(normals), Some(uvs)) => Some(vertex_tangents(&positions, normals, uvs, &faces)),
+            _ => None,
+        };
+
+        // creased normals belong to the corners of the faces, vertices on a hard edge are split
+        // into one for each side
+        if let (true, Some(_), Some(angle)) = (generated, &normals, options.crease_angle) {
+            let corners = crease_normals(&positions, &faces, angle);
+            let (source, split_normals) = split_corners(&mut faces, &corners);
+            positions = remap(&positions, &source);
+            uvs = uvs.map(|uvs| remap(&uvs, &source));
+            colors = colors.map(|colors| remap(&colors, &source));
+            tangents = tangents.map(|tangents| remap(&tangents, &source));
+            normals = Some(split_normals);
+        }
+
+        // split corners can push a mesh past the limit, so this is checked last
+        if positions.len() > u32::MAX as usize {
+            return Err(MeshError::TooManyVertices { file: path.to_path_buf(), len: positions.len() });
+        }
+        let packed = |vectors: Vec<Vec3>| vectors.iter().map(|v| v.v.map(|x| x as f32)).collect();
+        let indices = faces.iter().map(|f| f.map(|i| i as u32)).collect();
+        let mut mesh = IndexedMesh::new(packed(positions), indices, material)?;
+        mesh.set_normals(normals.map(packed))?;
+        mesh.set_uvs(uvs.map(|uvs| uvs.iter().map(|(u, v)| [*u as f32, *v as f32]).collect()))?;
+        mesh.set_tangents(tangents.map(packed))?;
+        mesh.set_colors(colors.map(packed))?;
+        Ok(mesh)
+    }
+}
+
+// Gives every distinct (vertex, normal) pair among the corners of the faces a vertex of its own
+// and points the faces at those. Returns the vertex each new one was copied from, and its normal.
+fn split_corners(faces: &mut [[usize; 3]], corners: &[[Vec3; 3]]) -> (Vec<usize>, Vec<Vec3>) {
+    let mut split: HashMap<(usize, [u64; 3]), usize> = HashMap::new();
+    let mut source = Vec::new();
+    let mut normals = Vec::new();
+    for (face, corner_normals) in faces.iter_mut().zip(corners) {
+        for (v, n) in face.iter_mut().zip(corner_normals) {
+            *v = *split.entry((*v, n.v.map(f64::to_bits))).or_insert_with(|| {
+                source.push(*v);
+                normals.push(*n);
+                source.len() - 1
+            });
+        }
+    }
+    (source, normals)
+}
+
+fn remap<T: Copy>(values: &[T], source: &[usize]) -> Vec<T> {
+    source.iter().map(|i| values[*i]).collect()
+}
+
+// the models in an OBJ file and the materials of its .mtl library
+fn load_obj(path: &Path, options: &MeshOptions) -> Result<(Vec<tobj::Model>, Vec<Material>), MeshError> {
+    let load_options = if options.subdivision > 0 { &SUBDIVISION_LOAD_OPTIONS } else { &GPU_LOAD_OPTIONS };
+    let (models, mats) = tobj::load_obj(path, load_options).map_err(|e| match e {
+        LoadError::OpenFileFailed => MeshError::MissingFile(path.to_path_buf()),
+        e => MeshError::Parse(path.to_path_buf(), e.to_string()),
+    })?;
+    let materials = mats.map_err(|e| match e {
+        LoadError::OpenFileFailed => MeshError::MissingMtl(path.to_path_buf()),
+        e => MeshError::Parse(path.to_path_buf(), format!("material library: {e}")),
+    })?;
+
+    let mut maps = MapLoader { dir: path.parent().unwrap_or(Path::new("")), cache: HashMap::new() };
+    let materials = materials.iter().map(|m| mtl_material(m, &mut maps)).collect::<Result<Vec<_>, _>>()?;
+    Ok((models, materials))
+}
+
+// A PLY mesh (or an STL read into one) in the layout tobj gives OBJs, so all of them go through
+// the same triangulation and subdivision: polygons whole for subdivision, cut into fans otherwise. Normals, uvs and colours
+// are indexed like the positions, as with GPU_LOAD_OPTIONS.
+fn ply_model(ply: Ply, options: &MeshOptions) -> tobj::Model {
+    let flat = |vectors: &[Vec3]| vectors.iter().flat_map(|v| v.v.map(|x| x as f32)).collect();
+    let mut mesh = tobj::Mesh {
+        positions: flat(&ply.positions),
+        normals: ply.normals.as_deref().map_or(Vec::new(), flat),
+        vertex_color: ply.colors.as_deref().map_or(Vec::new(), flat),
+        texcoords: ply.uvs.iter().flatten().flat_map(|(u, v)| [*u as f32, *v as f32]).collect(),
+        ..tobj::Mesh::default()
+    };
+    for face in &ply.faces {
+        if options.subdivision > 0 {
+            mesh.indices.extend(face.iter().map(|i| *i as u32));
+            mesh.face_arities.push(face.len() as u32);
+        } else {
+            for k in 1..face.len() - 1 {
+                mesh.indices.extend([face[0], face[k], face[k + 1]].map(|i| i as u32));
             }
         }
+    }
+    if ply.uvs.is_some() {
+        mesh.texcoord_indices = mesh.indices.clone();
+    }
+    tobj::Model::new(mesh, String::new())
+}
+
+// a model as loaded with GPU_LOAD_OPTIONS: triangulated, with one index for all vertex data
+fn indexed(mesh: &tobj::Mesh, path: &Path) -> Result<MeshData, MeshError> {
+    let vertex_count = mesh.positions.len() / 3;
+    let positions: Vec<Point3> = mesh.positions.chunks_exact(3)
+        .map(|p| Vec3::newf32(p[0], p[1], p[2]))
+        .collect();
+
+    let mut faces: Vec<[usize; 3]> = Vec::with_capacity(mesh.indices.len() / 3);
+    for f in mesh.indices.chunks_exact(3) {
+        let face = [f[0] as usize, f[1] as usize, f[2] as usize];
+        if let Some(&index) = face.iter().find(|i| **i >= vertex_count) {
+            return Err(MeshError::IndexOutOfRange { file: path.to_path_buf(), index, len: vertex_count });
+        }
+        faces.push(face);
+    }
+
+    let normals = if mesh.normals.len() == mesh.positions.len() {
+        Some(mesh.normals.chunks_exact(3).map(|n| Vec3::newf32(n[0], n[1], n[2])).collect())
+    } else {
+        None
+    };
+    let uvs = if mesh.texcoords.len() / 2 == vertex_count {
+        Some(mesh.texcoords.chunks_exact(2).map(|t| (t[0] as f64, t[1] as f64)).collect())
+    } else {
+        None
+    };
+    let colors = if !mesh.vertex_color.is_empty() && mesh.vertex_color.len() == mesh.positions.len() {
+        Some(mesh.vertex_color.chunks_exact(3).map(|c| Vec3::newf32(c[0], c[1], c[2])).collect())
+    } else {
+        None
+    };
+    Ok(MeshData { positions, normals, uvs, colors, faces })
+}
+
+// The options subdivided models are loaded with, the polygons are kept whole
+const SUBDIVISION_LOAD_OPTIONS: LoadOptions = LoadOptions {
+    single_index: false,
+    triangulate: false,
+    ignore_points: true,
+    ignore_lines: true,
+};
+
+// A model as loaded with SUBDIVISION_LOAD_OPTIONS, subdivided and cut into triangles. Normals
+// from the file no longer fit the new surface and are dropped, and so are vertex colours.
+fn subdivided(mesh: &tobj::Mesh, path: &Path, options: &MeshOptions) -> Result<MeshData, MeshError> {
+    let vertex_count = mesh.positions.len() / 3;
+    let positions: Vec<Point3> = mesh.positions.chunks_exact(3).map(|p| Vec3::newf32(p[0], p[1], p[2])).collect();
+    // vertices at the same position are merged, the faces have to be connected for the surface to stay closed
+    let (weld, count) = weld(&positions);
+    let mut poly = PolyMesh { positions: vec![Vec3::default(); count], ..PolyMesh::default() };
+    for (p, w) in positions.iter().zip(&weld) {
+        poly.positions[*w] = *p;
+    }
+    let has_uvs = !mesh.texcoords.is_empty() && mesh.texcoord_indices.len() == mesh.indices.len();
+    let mut uvs = Vec::new();
+
+    // face_arities is left empty when every face is a triangle
+    let arities: Vec<usize> = if mesh.face_arities.is_empty() {
+        vec![3; mesh.indices.len() / 3]
+    } else {
+        mesh.face_arities.iter().map(|a| *a as usize).collect()
+    };
+    let mut start = 0;
+    for arity in arities {
+        let corners = start..start + arity;
+        start += arity;
+        let face: Vec<usize> = mesh.indices[corners.clone()].iter().map(|i| *i as usize).collect();
+        if let Some(&index) = face.iter().find(|i| **i >= vertex_count) {
+            return Err(MeshError::IndexOutOfRange { file: path.to_path_buf(), index, len: vertex_count });
+        }
+        let face: Vec<usize> = face.iter().map(|i| weld[*i]).collect();
+        // faces that use a vertex twice have no proper edges to split
+        if (1..face.len()).any(|k| face[k..].contains(&face[k - 1])) {
+            continue;
+        }
+        poly.faces.push(face);
+        if has_uvs {
+            uvs.push(mesh.texcoord_indices[corners].iter().map(|t| {
+                let t = *t as usize * 2;
+                (mesh.texcoords[t] as f64, mesh.texcoords[t + 1] as f64)
+            }).collect());
+        }
+    }
+    poly.uvs = has_uvs.then_some(uvs);
+    if let Some(angle) = options.crease_angle {
+        poly.mark_creases(angle);
+    }
+    let poly = poly.subdivide(options.subdivision);
+
+    // corners with the same position and uv become one vertex, a uv seam splits it
+    let mut welded: HashMap<(usize, [u64; 2]), usize> = HashMap::new();
+    let mut positions = Vec::new();
+    let mut vertex_uvs = Vec::new();
+    let mut faces = Vec::new();
+    for (i, face) in poly.faces.iter().enumerate() {
+        let corners: Vec<usize> = face.iter().enumerate().map(|(k, v)| {
+            let uv = poly.uvs.as_ref().map_or((0.0, 0.0), |uvs| uvs[i][k]);
+            *welded.entry((*v, [uv.0.to_bits(), uv.1.to_bits()])).or_insert_with(|| {
+                positions.push(poly.positions[*v]);
+                vertex_uvs.push(uv);
+                positions.len() - 1
+            })
+        }).collect();
+        // a fan around the first corner
+        for k in 1..corners.len() - 1 {
+            faces.push([corners[0], corners[k], corners[k + 1]]);
+        }
+    }
+    let uvs = poly.uvs.is_some().then_some(vertex_uvs);
+    Ok(MeshData { positions, normals: None, uvs, colors: None, faces })
+}
+
+// Moves the vertices for a displacement map, along the file's normals when it has them and
+// smooth ones otherwise. Vertices split apart at uv seams move by their average height so the
+// seam does not crack open, and the normals are recomputed afterwards.
+fn displace(data: &mut MeshData, displacement: &Displacement) {
+    let normals = match &data.normals {
+        Some(normals) => normals.iter().map(|n| if n.length() > 0.0 { n.unit() } else { *n }).collect(),
+        None => smooth_normals(&data.positions, &data.faces),
+    };
+    let (weld, count) = weld(&data.positions);
+    let mut heights = vec![(0.0, 0); count];
+    for (i, p) in data.positions.iter().enumerate() {
+        let (u, v) = data.uvs.as_ref().map_or((0.0, 0.0), |uvs| uvs[i]);
+        let h = &mut heights[weld[i]];
+        *h = (h.0 + displacement.map.scalar(u, v, *p), h.1 + 1);
+    }
+    for (i, p) in data.positions.iter_mut().enumerate() {
+        let (sum, n) = heights[weld[i]];
+        *p = *p + normals[i] * ((sum / n as f64 - displacement.mid_level) * displacement.scale);
+    }
+    data.normals = None;
+}
+
+// Loads the image maps referenced by an .mtl file, each image is decoded once per mesh
+struct MapLoader<'a> {
+    dir: &'a Path,
+    cache: HashMap<(String, ColorSpace), Arc<ImageTexture>>,
+}
 
-        TriMesh{
-            triangles,
-            bounds: AABB::default()
+impl MapLoader<'_> {
+    // map statements can carry options (map_Kd -s 2 2 1 wood.png), the file name is the last word
+    fn load(&mut self, map: &str, color_space: ColorSpace) -> Result<Texture, MeshError> {
+        let file = map.split_whitespace().last().unwrap_or(map).to_string();
+        if let Some(image) = self.cache.get(&(file.clone(), color_space)) {
+            return Ok(Texture::Image(image.clone()));
         }
+
+        let path = self.dir.join(&file);
+        let image = ImageTexture::load(&path, color_space).map_err(|e| MeshError::Texture(path, e.to_string()))?;
+        let image = Arc::new(image);
+        self.cache.insert((file, color_space), image.clone());
+        Ok(Texture::Image(image))
     }
 
+    fn load_opt(&mut self, map: Option<&String>, color_space: ColorSpace) -> Result<Option<Texture>, MeshError> {
+        map.map(|m| self.load(m, color_space)).transpose()
+    }
 }
 
-impl Hittable for TriMesh {
-    fn ray_hit(&self, ray: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::Record> {
-        // if self.bounds.hit(ray) {
-            let mut curr_record: Record = Record::new();
-            let mut curr_hit: bool = false;
-            let mut curr_closest: f64 = t_max;
+// Translates the standard MTL fields into the closest Material:
+// Ke -> Emission, d < 1 or Tr > 0 -> Dielectric (ior from Ni), otherwise the illum model picks
+// between Diffuse, Glossy and Metal. Kd is the base colour, Ks the specular reflectance and
+// Ns the Phong exponent, converted to a roughness.
+// map_Kd, map_Ks and map_Ke replace their colours. map_Ns is read as a roughness map, which
+// is what Blender writes there. norm, map_Bump and bump wrap the result in a NormalMapped
+// material and map_d cuts it out with a Masked one.
+fn mtl_material(m: &tobj::Material, maps: &mut MapLoader) -> Result<Material, MeshError> {
+    let mut material = mtl_base_material(m, maps)?;
+    if let Some(map) = mtl_normal_map(m, maps)? {
+        material = Material::NormalMapped { base: Box::new(material), map };
+    }
+    if let Some(map_d) = &m.dissolve_texture {
+        let opacity = maps.load(map_d, ColorSpace::Linear)?.into_opacity();
+        material = Material::Masked { base: Box::new(material), opacity };
+    }
+    Ok(material)
+}
+
+fn mtl_base_material(m: &tobj::Material, maps: &mut MapLoader) -> Result<Material, MeshError> {
+    let to_color = |[r, g, b]: [f32; 3]| Color::newf32(r, g, b);
+    let diffuse = m.diffuse.map(to_color).unwrap_or(Color::new(0.8, 0.8, 0.8));
+    let specular = m.specular.map(to_color).unwrap_or_default();
+    let specularity = ((specular.x() + specular.y() + specular.z()) / 3.0).clamp(0.0, 1.0);
+    // Beckmann roughness matching a Phong lobe with exponent Ns
+    let roughness = m.shininess.map(|ns| f64::sqrt(2.0 / (ns.max(0.0) as f64 + 2.0))).unwrap_or(1.0);
+    let ior = m.optical_density.map(|ni| ni as f64).filter(|ni| *ni >= 1.0).unwrap_or(1.5);
+
+    let diffuse_map = maps.load_opt(m.diffuse_texture.as_ref(), ColorSpace::Srgb)?;
+    let specular_map = maps.load_opt(m.specular_texture.as_ref(), ColorSpace::Linear)?;
+    let roughness_map = maps.load_opt(m.shininess_texture.as_ref(), ColorSpace::Linear)?;
+    let emission_map = maps.load_opt(m.unknown_param.get("map_Ke"), ColorSpace::Srgb)?;
 
-            for triangle in &self.triangles {
-                let res = triangle.ray_hit(ray, t_min, curr_closest);
-                match res {
-                    Some(x) => {
-                        curr_record = x;
-                        curr_hit = true;
-                        curr_closest = curr_record.t;
-                    },
-                    None => {}
+    let emission = m.unknown_param.get("Ke").and_then(|ke| parse_color(ke)).unwrap_or_default();
+    let strength = emission.x().max(emission.y()).max(emission.z());
+    if strength > 0.0 {
+        let color = emission_map.unwrap_or((emission / strength).into());
+        return Ok(Material::Emission { color, strength });
+    }
+    if let Some(color) = emission_map {
+        return Ok(Material::Emission { color, strength: 1.0 });
+    }
+
+    let color = diffuse_map.unwrap_or(diffuse.into());
+    let roughness = roughness_map.unwrap_or(roughness.into());
+
+    // d is opacity, Tr the (less common) transparency. Tf tints the transmitted light.
+    let transparency = match (m.dissolve, m.unknown_param.get("Tr").and_then(|tr| tr.trim().parse::<f64>().ok())) {
+        (Some(d), _) => 1.0 - d as f64,
+        (None, Some(tr)) => tr,
+        (None, None) => 0.0,
+    };
+    let transmission = m.unknown_param.get("Tf").and_then(|tf| parse_color(tf)).unwrap_or(diffuse);
+
+    let material = match m.illumination_model {
+        Some(4 | 6 | 7 | 9) => Material::Dielectric { color: transmission.into(), ior },
+        _ if transparency > 0.0 => Material::Dielectric { color: transmission.into(), ior },
+        Some(0 | 1 | 10) => Material::Diffuse { color },
+        Some(3 | 5 | 8) => {
+            let color = match specular_map {
+                Some(map) => map,
+                None if specularity > 0.0 => specular.into(),
+                None => color,
+            };
+            Material::Metal { color, roughness }
+        }
+        _ if specularity > 0.0 || specular_map.is_some() => {
+            let specularity = specular_map.unwrap_or(specularity.into());
+            Material::Glossy { color, specularity, roughness }
+        }
+        _ => Material::Diffuse { color },
+    };
+    Ok(material)
+}
+
+// norm is always a tangent space normal map. map_Bump and bump hold normal maps as often as
+// height maps in the wild, so grayscale images are read as heights and colour images as normals.
+fn mtl_normal_map(m: &tobj::Material, maps: &mut MapLoader) -> Result<Option<NormalMap>, MeshError> {
+    if let Some(norm) = m.unknown_param.get("norm") {
+        let map = maps.load(norm, ColorSpace::Linear)?;
+        return Ok(Some(NormalMap::Tangent { map, strength: bump_multiplier(norm) }));
+    }
+    let Some(bump) = &m.normal_texture else {
+        return Ok(None);
+    };
+    let strength = bump_multiplier(bump);
+    let map = maps.load(bump, ColorSpace::Linear)?;
+    let normal_map = match &map {
+        Texture::Image(image) if image.is_grayscale() => NormalMap::Bump { height: map, strength },
+        _ => NormalMap::Tangent { map, strength },
+    };
+    Ok(Some(normal_map))
+}
+
+// the -bm option of a bump map statement
+fn bump_multiplier(map: &str) -> f64 {
+    let words: Vec<&str> = map.split_whitespace().collect();
+    words.windows(2).find(|w| w[0] == "-bm").and_then(|w| w[1].parse().ok()).unwrap_or(1.0)
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    let v: Vec<f64> = s.split_whitespace().map(|x| x.parse::<f64>()).collect::<Result<_, _>>().ok()?;
+    match v[..] {
+        [r, g, b] => Some(Color::new(r, g, b)),
+        [x] => Some(Color::new(x, x, x)),
+        _ => None,
+    }
+}
+
+// for each vertex the first vertex at the same position, and how many distinct positions there are
+fn weld(positions: &[Point3]) -> (Vec<usize>, usize) {
+    let mut welded: HashMap<[u64; 3], usize> = HashMap::new();
+    let weld = positions.iter().map(|p| {
+        let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+        let next = welded.len();
+        *welded.entry(key).or_insert(next)
+    }).collect();
+    (weld, welded.len())
+}
+
+// Angle weighted vertex normals. Vertices that share a position are treated as one, so faces
+// that were split apart for texture seams still shade smoothly across the seam.
+pub fn smooth_normals(positions: &[Point3], faces: &[[usize; 3]]) -> Vec<Vec3> {
+    let (weld, count) = weld(positions);
+
+    let mut sums = vec![Vec3::default(); count];
+    for face in faces {
+        let normal = Vec3::cross(positions[face[1]] - positions[face[0]], positions[face[2]] - positions[face[0]]);
+        if normal.length() == 0.0 {
+            continue;
+        }
+        let normal = normal.unit();
+        for k in 0..3 {
+            let p = positions[face[k]];
+            let e1 = positions[face[(k + 1) % 3]] - p;
+            let e2 = positions[face[(k + 2) % 3]] - p;
+            let angle = corner_angle(e1, e2);
+            sums[weld[face[k]]] = sums[weld[face[k]]] + angle * normal;
+        }
+    }
+
+    weld.iter().map(|w| {
+        let n = sums[*w];
+        if n.length() > 0.0 { n.unit() } else { n }
+    }).collect()
+}
+
+// Normals for each corner of each face, smooth except where faces meet at more than crease_angle
+// degrees: a corner only averages the faces around its vertex that are close enough to its own.
+pub fn crease_normals(positions: &[Point3], faces: &[[usize; 3]], crease_angle: f64) -> Vec<[Vec3; 3]> {
+    let (weld, count) = weld(positions);
+    let limit = f64::to_radians(crease_angle).cos();
+
+    let face_normals: Vec<Vec3> = faces.iter().map(|f| {
+        let n = Vec3::cross(positions[f[1]] - positions[f[0]], positions[f[2]] - positions[f[0]]);
+        if n.length() > 0.0 { n.unit() } else { n }
+    }).collect();
+    // (face, angle of the face at the vertex) around every welded vertex
+    let mut around: Vec<Vec<(usize, f64)>> = vec![vec![]; count];
+    for (i, f) in faces.iter().enumerate() {
+        for k in 0..3 {
+            let p = positions[f[k]];
+            let angle = corner_angle(positions[f[(k + 1) % 3]] - p, positions[f[(k + 2) % 3]] - p);
+            around[weld[f[k]]].push((i, angle));
+        }
+    }
+
+    faces.iter().enumerate().map(|(i, f)| {
+        let own = face_normals[i];
+        f.map(|v| {
+            let n = around[weld[v]].iter()
+                .filter(|(j, _)| Vec3::dot(face_normals[*j], own) >= limit)
+                .fold(Vec3::default(), |sum, (j, angle)| sum + *angle * face_normals[*j]);
+            if n.length() > 0.0 { n.unit() } else { own }
+        })
+    }).collect()
+}
+
+// Per vertex tangents in the style of MikkTSpace: each face's tangent (the direction of increasing u)
+// is added to its corners weighted by the corner angle, then the sum is made orthogonal to the
+// vertex normal. The bitangent is rebuilt at hit time from the normal, the tangent and the face's
+// handedness, so mirrored uvs work.
+pub fn vertex_tangents(positions: &[Point3], normals: &[Vec3], uvs: &[(f64, f64)], faces: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut sums = vec![Vec3::default(); positions.len()];
+    for face in faces {
+        let [i1, i2, i3] = *face;
+        let (e1, e2) = (positions[i2] - positions[i1], positions[i3] - positions[i1]);
+        let (du1, dv1) = (uvs[i2].0 - uvs[i1].0, uvs[i2].1 - uvs[i1].1);
+        let (du2, dv2) = (uvs[i3].0 - uvs[i1].0, uvs[i3].1 - uvs[i1].1);
+        let det = du1 * dv2 - du2 * dv1;
+        let tangent = (e1 * dv2 - e2 * dv1) / det;
+        // faces with degenerate uvs have no tangent
+        if det == 0.0 || tangent.length() == 0.0 {
+            continue;
+        }
+        let tangent = tangent.unit();
+        for k in 0..3 {
+            let p = positions[face[k]];
+            let angle = corner_angle(positions[face[(k + 1) % 3]] - p, positions[face[(k + 2) % 3]] - p);
+            sums[face[k]] = sums[face[k]] + angle * tangent;
+        }
+    }
+
+    sums.iter().zip(normals).map(|(t, n)| {
+        let t = *t - Vec3::dot(*t, *n) * *n;
+        // leave it to the triangle to pick a tangent where none could be found
+        if t.length() > 0.0 { t.unit() } else { Vec3::default() }
+    }).collect()
+}
+
+// angle between two edges leaving the same corner
+fn corner_angle(e1: Vec3, e2: Vec3) -> f64 {
+    let len = e1.length() * e2.length();
+    if len == 0.0 {
+        return 0.0;
+    }
+    f64::acos((Vec3::dot(e1, e2) / len).clamp(-1.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    // a unit square in the xy plane, facing +z
+    fn quad(normals: Option<Vec<Vec3>>) -> MeshData {
+        MeshData {
+            positions: vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)],
+            normals,
+            uvs: Some(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+            colors: None,
+            faces: vec![[0, 1, 2], [0, 2, 3]],
+        }
+    }
+
+    fn displacement(height: f64, scale: f64) -> Displacement {
+        Displacement { map: height.into(), scale, mid_level: 0.5 }
+    }
+
+    #[test]
+    fn flat_quad_moves_by_scale_times_height_along_its_normal() {
+        let mut data = quad(None);
+        let before = data.positions.clone();
+        displace(&mut data, &displacement(0.9, 0.5));
+        for (p, q) in before.iter().zip(&data.positions) {
+            assert!((*q - (*p + Vec3::new(0.0, 0.0, 0.2))).length() < 1e-12, "{:?} moved to {:?}", p, q);
+        }
+        // below mid_level pushes the surface in
+        let mut data = quad(None);
+        displace(&mut data, &displacement(0.0, 2.0));
+        assert!(data.positions.iter().all(|p| (p.z() + 1.0).abs() < 1e-12), "{:?}", data.positions);
+    }
+
+    #[test]
+    fn file_normals_decide_the_direction() {
+        let tilted = Vec3::new(1.0, 0.0, 1.0);
+        let mut data = quad(Some(vec![tilted; 4]));
+        let before = data.positions.clone();
+        displace(&mut data, &displacement(1.0, 1.0));
+        let offset = tilted.unit() * 0.5;
+        for (p, q) in before.iter().zip(&data.positions) {
+            assert!((*q - (*p + offset)).length() < 1e-12, "{:?} moved to {:?}", p, q);
+        }
+        // the old normals no longer fit the moved surface
+        assert!(data.normals.is_none());
+    }
+
+    // Writes files (name, contents) to a fresh directory and loads the first one
+    fn load_files(name: &str, files: &[(&str, &str)], options: &MeshOptions) -> Result<(TriMesh, MaterialTable), MeshError> {
+        let dir = std::env::temp_dir().join(format!("glint-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        let mut materials = MaterialTable::new();
+        let mesh = TriMesh::load(dir.join(files[0].0), MaterialId::EMPTY, options, &mut materials);
+        std::fs::remove_dir_all(&dir).unwrap();
+        mesh.map(|mesh| (mesh, materials))
+    }
+
+    // two triangles folded at a right angle along the x axis, one facing +z and one facing +y
+    const FOLD: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 2 1 4\n";
+
+    #[test]
+    fn missing_files_are_reported() {
+        let path = std::env::temp_dir().join("glint-no-such-mesh.obj");
+        assert!(matches!(TriMesh::new(&path, MaterialId::EMPTY, &mut MaterialTable::new()), Err(MeshError::MissingFile(p)) if p == path));
+        let obj = format!("mtllib missing.mtl\nusemtl red\n{}", FOLD);
+        let result = load_files("missing-mtl", &[("fold.obj", &obj)], &MeshOptions::default());
+        assert!(matches!(result, Err(MeshError::MissingMtl(_))), "{:?}", result.err());
+    }
+
+    #[test]
+    fn faces_past_the_vertices_are_an_error() {
+        let mesh = tobj::Mesh { positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], indices: vec![0, 1, 3], ..tobj::Mesh::default() };
+        let result = indexed(&mesh, Path::new("bad.obj"));
+        assert!(matches!(result, Err(MeshError::IndexOutOfRange { index: 3, len: 3, .. })), "{:?}", result.err());
+        // tobj already rejects such faces in OBJ files
+        let result = load_files("bad-index", &[("bad.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n")], &MeshOptions::default());
+        assert!(matches!(result, Err(MeshError::Parse(..))), "{:?}", result.err());
+    }
+
+    #[test]
+    fn smooth_normals_average_the_faces_by_angle() {
+        let positions = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 0.0, 1.0)];
+        let normals = smooth_normals(&positions, &[[0, 1, 2], [1, 0, 3]]);
+        let fold = Vec3::new(0.0, 1.0, 1.0).unit();
+        assert!((normals[0] - fold).length() < 1e-12 && (normals[1] - fold).length() < 1e-12, "{:?}", normals);
+        assert!((normals[2] - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+        assert!((normals[3] - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+
+        // a cube corner has three right angles, so the normal points along the diagonal. The
+        // wide face at the corner counts as much as the two thin ones that make up the third side
+        let positions = [
+            Point3::new(0.0, 0.0, 0.0), Point3::new(-1.0, 0.0, 0.0), Point3::new(0.0, -1.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0), Point3::new(-1.0, -1.0, 0.0),
+        ];
+        let normals = smooth_normals(&positions, &[[0, 3, 1], [0, 2, 3], [0, 1, 4], [0, 4, 2]]);
+        assert!((normals[0] - Vec3::new(1.0, 1.0, 1.0).unit()).length() < 1e-12, "{:?}", normals[0]);
+    }
+
+    #[test]
+    fn obj_without_normals_gets_flat_or_smooth_ones() {
+        let ray = Ray::new(Point3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let options = MeshOptions { normals: NormalMode::Flat, ..MeshOptions::default() };
+        let (mesh, materials) = load_files("flat", &[("fold.obj", FOLD)], &options).unwrap();
+        let mesh = &mesh.meshes[0];
+        assert!(mesh.normals().is_none());
+        let hit = mesh.ray_hit(&materials, &ray, 0.001, f64::INFINITY).expect("ray should hit the +z face");
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-6, "{:?}", hit.normal);
+
+        let (mesh, materials) = load_files("smooth", &[("fold.obj", FOLD)], &MeshOptions::default()).unwrap();
+        let mesh = &mesh.meshes[0];
+        let normals = mesh.normals().expect("smooth normals are generated");
+        assert_eq!(normals.len(), 4);
+        // the fold's edge leans halfway between the faces, the far corners keep their face's normal
+        let hit = mesh.ray_hit(&materials, &ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.normal.y() > 0.1 && hit.normal.z() > hit.normal.y(), "{:?}", hit.normal);
+    }
+
+    fn mtl(fields: &[(&str, &str)]) -> tobj::Material {
+        let mut m = tobj::Material { diffuse: Some([0.5, 0.25, 0.125]), ..tobj::Material::default() };
+        for (key, value) in fields {
+            let color = || {
+                let v: Vec<f32> = value.split_whitespace().map(|x| x.parse().unwrap()).collect();
+                Some([v[0], v[1], v[2]])
+            };
+            match *key {
+                "Kd" => m.diffuse = color(),
+                "Ks" => m.specular = color(),
+                "Ns" => m.shininess = value.parse().ok(),
+                "d" => m.dissolve = value.parse().ok(),
+                "Ni" => m.optical_density = value.parse().ok(),
+                "illum" => m.illumination_model = value.parse().ok(),
+                _ => {
+                    m.unknown_param.insert(key.to_string(), value.to_string());
                 }
             }
-            return if curr_hit {Some(curr_record)} else {None};
+        }
+        m
+    }
 
+    fn base_material(fields: &[(&str, &str)]) -> Material {
+        let mut maps = MapLoader { dir: Path::new(""), cache: HashMap::new() };
+        mtl_base_material(&mtl(fields), &mut maps).unwrap()
+    }
 
-            // for triangle in &self.triangles {
-            //     if triangle.bounds.hit(ray) {
-            //         return triangle.ray_hit(ray, t_min, t_max)
-            //     }
-            // }
-        // }
-        None
+    fn constant(texture: &Texture) -> Color {
+        match texture {
+            Texture::Constant(c) => *c,
+            t => panic!("{:?} is not a constant", t),
+        }
+    }
+
+    fn assert_color(texture: &Texture, expected: Color) {
+        let c = constant(texture);
+        assert!((c - expected).length() < 1e-6, "{:?}, expected {:?}", c, expected);
+    }
+
+    #[test]
+    fn mtl_illum_models_pick_the_material() {
+        let kd = Color::new(0.5, 0.25, 0.125);
+        let Material::Diffuse { color } = base_material(&[]) else { panic!("plain Kd should be diffuse") };
+        assert_color(&color, kd);
+        // illum 1 has no highlights even with a Ks
+        assert!(matches!(base_material(&[("illum", "1"), ("Ks", "1 1 1")]), Material::Diffuse { .. }));
+        // illum 2 is glossy when there is a specular colour, diffuse without one
+        assert!(matches!(base_material(&[("illum", "2")]), Material::Diffuse { .. }));
+        let Material::Glossy { color, specularity, roughness } = base_material(&[("illum", "2"), ("Ks", "0.2 0.4 0.6"), ("Ns", "98")]) else {
+            panic!("illum 2 with Ks should be glossy")
+        };
+        assert_color(&color, kd);
+        assert_color(&specularity, Color::new(0.4, 0.4, 0.4));
+        assert_color(&roughness, Color::new(0.1, 0.1, 0.1) * f64::sqrt(2.0));
+        // illum 3 is a mirror tinted by Ks, or by Kd without one
+        let Material::Metal { color, roughness } = base_material(&[("illum", "3"), ("Ks", "0.9 0.8 0.7"), ("Ns", "0")]) else {
+            panic!("illum 3 should be a metal")
+        };
+        assert_color(&color, Color::new(0.9, 0.8, 0.7));
+        assert_color(&roughness, Color::new(1.0, 1.0, 1.0));
+        let Material::Metal { color, .. } = base_material(&[("illum", "5")]) else { panic!("illum 5 should be a metal") };
+        assert_color(&color, kd);
+    }
+
+    #[test]
+    fn mtl_roughness_falls_with_the_phong_exponent() {
+        let roughness = |ns: &str| match base_material(&[("Ks", "1 1 1"), ("Ns", ns)]) {
+            Material::Glossy { roughness, .. } => constant(&roughness).x(),
+            m => panic!("{:?} is not glossy", m),
+        };
+        assert!((roughness("0") - 1.0).abs() < 1e-6);
+        assert!((roughness("-5") - 1.0).abs() < 1e-6);
+        assert!(roughness("10") > roughness("100") && roughness("100") > roughness("1000"));
+        assert!((roughness("1000") - f64::sqrt(2.0 / 1002.0)).abs() < 1e-6);
     }
 
-    fn bounds(&self) -> &AABB {
-        &self.bounds
+    #[test]
+    fn mtl_transparency_makes_a_dielectric() {
+        let Material::Dielectric { color, ior } = base_material(&[("d", "0.5"), ("Ni", "1.33")]) else {
+            panic!("d < 1 should be a dielectric")
+        };
+        assert_color(&color, Color::new(0.5, 0.25, 0.125));
+        assert!((ior - 1.33).abs() < 1e-6);
+        // Tr is the inverse of d, Tf tints the transmitted light and an ior below 1 falls back to glass
+        let Material::Dielectric { color, ior } = base_material(&[("Tr", "0.3"), ("Tf", "0.1 0.9 0.1"), ("Ni", "0.5")]) else {
+            panic!("Tr > 0 should be a dielectric")
+        };
+        assert_color(&color, Color::new(0.1, 0.9, 0.1));
+        assert_eq!(ior, 1.5);
+        assert!(matches!(base_material(&[("illum", "7")]), Material::Dielectric { .. }));
+        // opaque settings stay opaque, and d wins over Tr
+        assert!(matches!(base_material(&[("d", "1"), ("Tr", "0.5")]), Material::Diffuse { .. }));
+        assert!(matches!(base_material(&[("Tr", "0")]), Material::Diffuse { .. }));
     }
 
-    fn centroid(&self) -> Vec3 {
-        Vec3::default()
+    #[test]
+    fn mtl_ke_makes_an_emitter() {
+        let Material::Emission { color, strength } = base_material(&[("Ke", "4 2 1"), ("illum", "2"), ("Ks", "1 1 1")]) else {
+            panic!("Ke should make an emitter")
+        };
+        assert_eq!(strength, 4.0);
+        assert_color(&color, Color::new(1.0, 0.5, 0.25));
+        // a grey Ke can be a single number, a black one is no light at all
+        let Material::Emission { strength, .. } = base_material(&[("Ke", "3")]) else { panic!("Ke 3 should emit") };
+        assert_eq!(strength, 3.0);
+        assert!(matches!(base_material(&[("Ke", "0 0 0")]), Material::Diffuse { .. }));
+    }
+
+    #[test]
+    fn vertex_tangents_follow_u_and_stay_perpendicular() {
+        let data = quad(None);
+        // normals leaning different ways, as on a curved surface
+        let normals: Vec<Vec3> = [(0.3, 0.0, 1.0), (-0.2, 0.4, 1.0), (0.0, 0.0, 1.0), (0.5, -0.5, 1.0)]
+            .map(|(x, y, z)| Vec3::new(x, y, z).unit())
+            .to_vec();
+        let tangents = vertex_tangents(&data.positions, &normals, data.uvs.as_ref().unwrap(), &data.faces);
+        for (t, n) in tangents.iter().zip(&normals) {
+            assert!((t.length() - 1.0).abs() < 1e-9, "{:?}", t);
+            assert!(Vec3::dot(*t, *n).abs() < 1e-9, "tangent {:?} is not perpendicular to {:?}", t, n);
+            assert!(t.x() > 0.8, "tangent {:?} should point along +u", t);
+        }
+        // mirroring u turns the tangents around
+        let mirrored: Vec<(f64, f64)> = data.uvs.as_ref().unwrap().iter().map(|(u, v)| (1.0 - u, *v)).collect();
+        let tangents = vertex_tangents(&data.positions, &normals, &mirrored, &data.faces);
+        assert!(tangents.iter().all(|t| t.x() < -0.8), "{:?}", tangents);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn degenerate_uvs_leave_the_tangent_to_the_hit() {
+        let mut data = quad(None);
+        data.uvs = Some(vec![(0.5, 0.5); 4]);
+        let normals = smooth_normals(&data.positions, &data.faces);
+        let tangents = vertex_tangents(&data.positions, &normals, data.uvs.as_ref().unwrap(), &data.faces);
+        assert!(tangents.iter().all(|t| t.length() == 0.0), "{:?}", tangents);
+
+        // the hit falls back to some frame around the normal
+        let mut materials = MaterialTable::new();
+        let mesh = data.into_mesh(Path::new("quad.obj"), MaterialId::EMPTY, &MeshOptions::default(), &mut materials).unwrap();
+        let ray = Ray::new(Point3::new(0.3, 0.6, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh.ray_hit(&materials, &ray, 0.001, f64::INFINITY).expect("ray should hit the quad");
+        for v in [hit.tangent, hit.bitangent] {
+            assert!((v.length() - 1.0).abs() < 1e-6 && Vec3::dot(v, hit.normal).abs() < 1e-6, "{:?}", v);
+        }
+    }
+}
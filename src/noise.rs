@@ -0,0 +1,192 @@
+// 3D noise functions for procedural textures. All of them are deterministic,
+// the same point always gives the same value.
+
+use std::sync::OnceLock;
+
+use crate::vec3::Point3;
+
+const PERM_SIZE: usize = 256;
+
+// Ken Perlin's "improved noise" permutation, shuffled once with a fixed seed
+fn permutation() -> &'static [u8; 2 * PERM_SIZE] {
+    static PERM: OnceLock<[u8; 2 * PERM_SIZE]> = OnceLock::new();
+    PERM.get_or_init(|| {
+        let mut p = [0u8; PERM_SIZE];
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for i in (1..PERM_SIZE).rev() {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let j = (state % (i as u64 + 1)) as usize;
+            p.swap(i, j);
+        }
+        let mut perm = [0u8; 2 * PERM_SIZE];
+        for i in 0..2 * PERM_SIZE {
+            perm[i] = p[i % PERM_SIZE];
+        }
+        perm
+    })
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// dot product of the offset with one of 12 cube edge gradients picked by the hash
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// gradient noise in roughly [-1, 1]
+pub fn perlin(p: Point3) -> f64 {
+    let perm = permutation();
+    let (xf, yf, zf) = (p.x().floor(), p.y().floor(), p.z().floor());
+    let xi = (xf as i64).rem_euclid(PERM_SIZE as i64) as usize;
+    let yi = (yf as i64).rem_euclid(PERM_SIZE as i64) as usize;
+    let zi = (zf as i64).rem_euclid(PERM_SIZE as i64) as usize;
+    let (x, y, z) = (p.x() - xf, p.y() - yf, p.z() - zf);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = perm[xi] as usize + yi;
+    let aa = perm[a] as usize + zi;
+    let ab = perm[a + 1] as usize + zi;
+    let b = perm[xi + 1] as usize + yi;
+    let ba = perm[b] as usize + zi;
+    let bb = perm[b + 1] as usize + zi;
+
+    lerp(w,
+        lerp(v,
+            lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1.0, y, z)),
+            lerp(u, grad(perm[ab], x, y - 1.0, z), grad(perm[bb], x - 1.0, y - 1.0, z))),
+        lerp(v,
+            lerp(u, grad(perm[aa + 1], x, y, z - 1.0), grad(perm[ba + 1], x - 1.0, y, z - 1.0)),
+            lerp(u, grad(perm[ab + 1], x, y - 1.0, z - 1.0), grad(perm[bb + 1], x - 1.0, y - 1.0, z - 1.0))))
+}
+
+// fractal Brownian motion: octaves of noise, each at twice the frequency and half the amplitude of the last
+pub fn fbm(p: Point3, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut p = p;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p);
+        amplitude *= 0.5;
+        p = p * 2.0;
+    }
+    sum
+}
+
+// fbm of the absolute noise value, creased like turbulent flow. Roughly in [0, 1]
+pub fn turbulence(p: Point3, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut p = p;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p).abs();
+        amplitude *= 0.5;
+        p = p * 2.0;
+    }
+    sum
+}
+
+// Cellular noise: distance to the nearest of one random feature point per unit cell
+pub fn worley(p: Point3) -> f64 {
+    let perm = permutation();
+    let cell = [p.x().floor() as i64, p.y().floor() as i64, p.z().floor() as i64];
+    let mut nearest = f64::INFINITY;
+
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let c = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                let hash = |salt: usize| {
+                    let h = perm[(c[0].rem_euclid(PERM_SIZE as i64) as usize + salt) % PERM_SIZE] as usize;
+                    let h = perm[(h + c[1].rem_euclid(PERM_SIZE as i64) as usize) % PERM_SIZE] as usize;
+                    perm[(h + c[2].rem_euclid(PERM_SIZE as i64) as usize) % PERM_SIZE] as f64 / 255.0
+                };
+                let feature = Point3::new(c[0] as f64 + hash(0), c[1] as f64 + hash(101), c[2] as f64 + hash(211));
+                nearest = nearest.min((feature - p).length());
+            }
+        }
+    }
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fixed spread of points, including negative coordinates and ones far from the origin
+    fn points() -> impl Iterator<Item = Point3> {
+        (0..2000).map(|i| {
+            let i = i as f64;
+            Point3::new(f64::sin(i * 12.9898) * 40.0, f64::sin(i * 78.233) * 40.0, f64::sin(i * 37.719) * 300.0)
+        })
+    }
+
+    #[test]
+    fn perlin_is_in_range_and_zero_on_the_lattice() {
+        for p in points() {
+            let n = perlin(p);
+            assert!((-1.0..=1.0).contains(&n), "perlin({}) = {}", p, n);
+        }
+        for p in [Point3::new(0.0, 0.0, 0.0), Point3::new(3.0, -7.0, 12.0), Point3::new(-300.0, 255.0, 256.0)] {
+            assert_eq!(perlin(p), 0.0, "perlin({})", p);
+        }
+        // not a constant
+        assert!(points().any(|p| perlin(p).abs() > 0.2));
+    }
+
+    #[test]
+    fn noise_is_deterministic() {
+        for p in points().take(100) {
+            assert_eq!(perlin(p), perlin(p));
+            assert_eq!(fbm(p, 5), fbm(p, 5));
+            assert_eq!(worley(p), worley(p));
+        }
+        // the lattice repeats every PERM_SIZE cells
+        let p = Point3::new(0.3, 1.7, -2.2);
+        let shifted = p + Point3::new(PERM_SIZE as f64, 0.0, -(PERM_SIZE as f64));
+        assert!((perlin(p) - perlin(shifted)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn octaves_add_halving_layers() {
+        for p in points().take(200) {
+            assert_eq!(fbm(p, 0), 0.0);
+            assert_eq!(turbulence(p, 0), 0.0);
+            assert_eq!(fbm(p, 1), perlin(p));
+            assert_eq!(turbulence(p, 1), perlin(p).abs());
+            assert!((fbm(p, 3) - (perlin(p) + 0.5 * perlin(p * 2.0) + 0.25 * perlin(p * 4.0))).abs() < 1e-12);
+            // each layer adds a non-negative amount, the amplitudes sum to less than 2
+            for octaves in 1..8 {
+                assert!(turbulence(p, octaves + 1) >= turbulence(p, octaves));
+            }
+            assert!(fbm(p, 8).abs() < 2.0 && turbulence(p, 8) < 2.0);
+        }
+    }
+
+    #[test]
+    fn worley_is_a_distance_to_a_nearby_point() {
+        for p in points() {
+            let d = worley(p);
+            // the point in p's own cell is at most a cell diagonal away
+            assert!((0.0..=f64::sqrt(3.0)).contains(&d), "worley({}) = {}", p, d);
+        }
+        // moving a small step changes the distance by at most the step
+        let p = Point3::new(0.4, 2.6, -1.3);
+        let step = Point3::new(0.01, 0.0, 0.0);
+        assert!((worley(p + step) - worley(p)).abs() <= 0.01 + 1e-12);
+    }
+}
@@ -1,20 +1,43 @@
-use crate::{vec3::{Point3, Vec3}, material::Material, hittable::{Hittable, Record}, ray::Ray, aabb::AABB};
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB};
 
+// An infinite plane, or a rectangular patch of one. Infinite planes have infinite bounds, the BVH
+// keeps them out of the tree and tests them alongside it.
+#[derive(Debug, Clone)]
 pub struct Plane {
     point: Point3,
     normal: Vec3,
-    material: Material,
-    bounds: AABB
+    tangent: Vec3,
+    bitangent: Vec3,
+    // full width and height of a patch, along the tangent and bitangent
+    size: Option<(f64, f64)>,
+    material: MaterialId,
+    pub bounds: AABB
 }
 
 impl Plane {
-    pub fn new(point: Point3, normal: Vec3, material: Material) -> Self {
-        Plane {point, normal, material, bounds: AABB::default()}
+    // normal has to be finite and non-zero, scene files check this when they load
+    pub fn new(point: Point3, normal: Vec3, material: MaterialId) -> Self {
+        let (tangent, bitangent) = Vec3::basis(normal);
+        Plane {point, normal: normal.unit(), tangent, bitangent, size: None, material, bounds: AABB::infinite()}
     }
-}
 
-impl Hittable for Plane {
-    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+    // a width x height rectangle centred on center, width runs along Vec3::basis(normal).0
+    pub fn patch(center: Point3, normal: Vec3, width: f64, height: f64, material: MaterialId) -> Self {
+        let mut plane = Plane::new(center, normal, material);
+        plane.size = Some((width, height));
+
+        let (w, h) = (plane.tangent * (width / 2.0), plane.bitangent * (height / 2.0));
+        let mut bounds = AABB::default();
+        for corner in [center - w - h, center + w - h, center + w + h, center - w + h] {
+            bounds.add(corner);
+        }
+        // give axis aligned patches some thickness so the box does not collapse
+        let pad = Vec3::new(1e-6, 1e-6, 1e-6);
+        plane.bounds = AABB::new(bounds.min - pad, bounds.max + pad);
+        plane
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
         let qp: Vec3 = self.point - ray.origin();
         let v_dot_n = Vec3::dot(ray.direction(), self.normal);
 
@@ -29,14 +52,44 @@ impl Hittable for Plane {
             return None;
         }
 
+        let point = ray.ray_at(t);
+        let d = point - self.point;
+        let (x, y) = (Vec3::dot(d, self.tangent), Vec3::dot(d, self.bitangent));
+
+        let (u, v) = match self.size {
+            // patches get uvs from 0 to 1 across
+            Some((width, height)) => {
+                let (u, v) = (x / width + 0.5, y / height + 0.5);
+                if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+                    return None;
+                }
+                (u, v)
+            }
+            // world space distances along the plane, one texture tile per unit
+            None => (x, y),
+        };
+
         let mut return_record = Record::new();
 
         return_record.t = t;
-        return_record.point = ray.ray_at(t);
+        return_record.point = point;
+        return_record.object_point = d;
         return_record.material = self.material;
-        return_record.calculate_normal(ray, self.normal.unit());
-        // eprintln!("{:?}", return_record);/
-        return Some(return_record)
+        return_record.calculate_normal(ray, self.normal);
+        return_record.tangent = self.tangent;
+        return_record.bitangent = self.bitangent;
+        (return_record.u, return_record.v) = (u, v);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        self.point
+    }
+}
+
+impl Hittable for Plane {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Plane::ray_hit(self, ray, t_min, t_max)
     }
 
     fn bounds(&self) -> &crate::aabb::AABB {
@@ -44,6 +97,81 @@ impl Hittable for Plane {
     }
 
     fn centroid(&self) -> Vec3 {
-        Vec3::default()
+        self.point
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bvh::BVH, hittable2::Primitive, material::{Material, MaterialTable}, sphere::Sphere};
+
+    fn ground() -> Plane {
+        Plane::new(Point3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), MaterialId::EMPTY)
+    }
+
+    #[test]
+    fn front_and_back_faces() {
+        let down = Ray::new(Point3::new(3.0, 4.0, -7.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = ground().ray_hit(&down, 0.001, f64::INFINITY).expect("ray should hit the plane from above");
+        assert!((hit.t - 5.0).abs() < 1e-9, "t = {}", hit.t);
+        assert!((hit.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9 && hit.outside_face);
+        assert!((hit.point - Point3::new(3.0, -1.0, -7.0)).length() < 1e-9);
+
+        let up = Ray::new(Point3::new(0.0, -3.0, 0.0), Vec3::new(0.0, 1.0, 1.0));
+        let hit = ground().ray_hit(&up, 0.001, f64::INFINITY).expect("ray should hit the plane from below");
+        assert!((hit.t - 2.0).abs() < 1e-9, "t = {}", hit.t);
+        assert!((hit.normal - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9 && !hit.outside_face);
+        // the frame stays with the outward normal
+        assert!((Vec3::cross(hit.tangent, hit.bitangent) - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn parallel_and_receding_rays_miss() {
+        let along = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0));
+        assert!(ground().ray_hit(&along, 0.001, f64::INFINITY).is_none());
+        let in_plane = Ray::new(Point3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(ground().ray_hit(&in_plane, 0.001, f64::INFINITY).is_none());
+        let away = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(ground().ray_hit(&away, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn patch_ends_at_its_size() {
+        let patch = Plane::patch(Point3::default(), Vec3::new(0.0, 0.0, 1.0), 2.0, 4.0, MaterialId::EMPTY);
+        let (w, h) = (patch.tangent, patch.bitangent);
+        let toward = |p: Point3| Ray::new(p + Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = patch.ray_hit(&toward(Point3::default()), 0.001, f64::INFINITY).expect("ray should hit the centre");
+        assert!((hit.u - 0.5).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9);
+        let hit = patch.ray_hit(&toward(0.9 * w + 1.9 * h), 0.001, f64::INFINITY).expect("ray should hit near the corner");
+        assert!((hit.u - 0.95).abs() < 1e-9 && (hit.v - 0.975).abs() < 1e-9, "uv ({}, {})", hit.u, hit.v);
+        assert!(patch.ray_hit(&toward(1.1 * w), 0.001, f64::INFINITY).is_none());
+        assert!(patch.ray_hit(&toward(2.1 * h), 0.001, f64::INFINITY).is_none());
+        assert!(patch.bounds.is_finite() && !ground().bounds.is_finite());
+    }
+
+    #[test]
+    fn infinite_plane_is_tested_alongside_the_tree() {
+        let primitives = vec![
+            Primitive::Sphere(Sphere::new(Point3::new(-2.0, 0.0, 0.0), 1.0, MaterialId(1))),
+            Primitive::Plane(ground()),
+            Primitive::Sphere(Sphere::new(Point3::new(2.0, 0.0, 0.0), 1.0, MaterialId(1))),
+        ];
+        let bvh = BVH::new(&primitives);
+        // the plane does not blow up the tree's box
+        assert!(bvh.bounds().is_finite());
+        assert!((bvh.bounds().min - Point3::new(-3.0, -1.0, -1.0)).length() < 1e-6, "{:?}", bvh.bounds());
+
+        let mut materials = MaterialTable::new();
+        materials.add(Material::UV);
+        let down = |x: f64| Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        // onto a sphere, which is closer than the plane
+        let hit = bvh.ray_hit(&primitives, &materials, &down(2.0), 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9 && hit.material == MaterialId(1), "t = {}", hit.t);
+        // between the spheres, and far outside the tree's box, the plane is still there
+        for x in [0.0, 1e6] {
+            let hit = bvh.ray_hit(&primitives, &materials, &down(x), 0.001, f64::INFINITY).expect("ray should hit the plane");
+            assert!((hit.t - 6.0).abs() < 1e-9 && hit.material == MaterialId::EMPTY, "t = {}", hit.t);
+        }
+    }
+}
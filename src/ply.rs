@@ -0,0 +1,470 @@
+// Reader for PLY files, the format most scanned models (the Stanford bunny and friends) come in.
+// ASCII and binary files of either byte order are read. Of the elements only vertices and faces
+// are kept, and of the vertex properties the position, normal, uv and colour, anything else the
+// header declares is skipped over.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{color::Color, material::{Material, MaterialId, MaterialTable}, mesh::MeshError, sphere::Sphere, texture::srgb_to_linear, vec3::{Point3, Vec3}};
+
+// The vertices and polygons of a PLY file. Files without faces are point clouds.
+#[derive(Debug, Clone, Default)]
+pub struct Ply {
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    // linear, decoded from sRGB
+    pub colors: Option<Vec<Color>>,
+    pub faces: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // what a colour channel of this type is divided by to land in [0, 1]
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    kind: Scalar,
+    // the type of the length in front of list properties
+    list: Option<Scalar>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    // the fewest bytes a row can take up: one digit per value in ascii files, and in binary ones
+    // the plain values plus the length of each list, as lists may be empty
+    fn min_row_size(&self, format: Format) -> usize {
+        self.properties.iter().map(|p| match (format, p.list) {
+            (Format::Ascii, _) => 1,
+            (_, Some(length)) => length.size(),
+            (_, None) => p.kind.size(),
+        }).sum()
+    }
+}
+
+// the data after the header, read one value at a time
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, kind: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or("file ends in the middle of the data")?;
+                token.parse::<f64>().map_err(|_| format!("'{token}' is not a number"))
+            }
+            Body::Binary { data, big_endian } => {
+                let size = kind.size();
+                if data.len() < size {
+                    return Err("file ends in the middle of the data".to_string());
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(&data[..size]);
+                *data = &data[size..];
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+                Ok(match kind {
+                    Scalar::I8 => bytes[0] as i8 as f64,
+                    Scalar::U8 => bytes[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(bytes),
+                })
+            }
+        }
+    }
+}
+
+impl Ply {
+    pub fn load(path: impl AsRef<Path>) -> Result<Ply, MeshError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => MeshError::MissingFile(path.to_path_buf()),
+            _ => MeshError::Parse(path.to_path_buf(), e.to_string()),
+        })?;
+        Ply::parse(&data).map_err(|msg| MeshError::Parse(path.to_path_buf(), msg))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Ply, String> {
+        let (format, elements, body) = header(data)?;
+        // counts come from the header and are not to be trusted, a file can't hold more rows than fit in it
+        let mut needed: usize = 0;
+        for element in &elements {
+            needed = element.count.checked_mul(element.min_row_size(format))
+                .and_then(|size| needed.checked_add(size))
+                .filter(|needed| *needed <= body.len())
+                .ok_or(format!("{} {} elements do not fit in the file", element.count, element.name))?;
+        }
+        let mut body = match format {
+            Format::Ascii => {
+                let text = std::str::from_utf8(body).map_err(|_| "ascii data is not valid text")?;
+                Body::Ascii(text.split_ascii_whitespace())
+            }
+            Format::LittleEndian => Body::Binary { data: body, big_endian: false },
+            Format::BigEndian => Body::Binary { data: body, big_endian: true },
+        };
+
+        let mut ply = Ply::default();
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => ply.read_vertices(element, &mut body)?,
+                "face" => ply.read_faces(element, &mut body)?,
+                // rows without properties hold no data
+                _ if element.properties.is_empty() => {}
+                _ => {
+                    for _ in 0..element.count {
+                        read_row(element, &mut body)?;
+                    }
+                }
+            }
+        }
+
+        let count = ply.positions.len();
+        for face in &ply.faces {
+            if let Some(index) = face.iter().find(|i| **i >= count) {
+                return Err(format!("face uses vertex {index} but there are only {count} vertices"));
+            }
+        }
+        Ok(ply)
+    }
+
+    fn read_vertices(&mut self, element: &Element, body: &mut Body) -> Result<(), String> {
+        let find = |names: &[&str]| element.properties.iter().position(|p| p.list.is_none() && names.contains(&p.name.as_str()));
+        let all = |names: [&[&str]; 3]| -> Option<[usize; 3]> { Some([find(names[0])?, find(names[1])?, find(names[2])?]) };
+
+        let position = all([&["x"], &["y"], &["z"]]).ok_or("vertices have no x, y and z")?;
+        let normal = all([&["nx"], &["ny"], &["nz"]]);
+        let uv = find(&["u", "s", "texture_u", "texture_s"]).zip(find(&["v", "t", "texture_v", "texture_t"]));
+        let color = all([&["red", "diffuse_red", "r"], &["green", "diffuse_green", "g"], &["blue", "diffuse_blue", "b"]]);
+
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut colors = Vec::new();
+        for _ in 0..element.count {
+            let row = read_row(element, body)?;
+            let vector = |[x, y, z]: [usize; 3]| Vec3::new(row[x][0], row[y][0], row[z][0]);
+            self.positions.push(vector(position));
+            if let Some(n) = normal {
+                normals.push(vector(n));
+            }
+            if let Some((u, v)) = uv {
+                uvs.push((row[u][0], row[v][0]));
+            }
+            if let Some(c) = color {
+                let channel = |i: usize| srgb_to_linear((row[i][0] / element.properties[i].kind.full_scale()).clamp(0.0, 1.0));
+                colors.push(Color::new(channel(c[0]), channel(c[1]), channel(c[2])));
+            }
+        }
+        self.normals = normal.map(|_| normals);
+        self.uvs = uv.map(|_| uvs);
+        self.colors = color.map(|_| colors);
+        Ok(())
+    }
+
+    fn read_faces(&mut self, element: &Element, body: &mut Body) -> Result<(), String> {
+        let indices = element.properties.iter()
+            .position(|p| p.list.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"))
+            .ok_or("faces have no vertex_indices list")?;
+        for _ in 0..element.count {
+            let row = read_row(element, body)?;
+            let face = &row[indices];
+            if face.iter().any(|i| *i < 0.0) {
+                return Err("negative vertex index".to_string());
+            }
+            // points and lines have no surface
+            if face.len() >= 3 {
+                self.faces.push(face.iter().map(|i| *i as usize).collect());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_point_cloud(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    // A sphere of the given radius on every vertex, for point clouds. Without a material
+    // (MaterialId::EMPTY) each point is diffuse in its vertex colour, or grey if there are none.
+    // Points of the same colour share one material in the table.
+    pub fn spheres(&self, radius: f64, material: MaterialId, materials: &mut MaterialTable) -> Vec<Sphere> {
+        let mut by_color: HashMap<[u64; 3], MaterialId> = HashMap::new();
+        self.positions.iter().enumerate().map(|(i, p)| {
+            let material = match (&materials[material], &self.colors) {
+                (Material::Empty, colors) => {
+                    let color = colors.as_ref().map_or(Color::new(0.8, 0.8, 0.8), |colors| colors[i]);
+                    *by_color.entry(color.v.map(f64::to_bits))
+                        .or_insert_with(|| materials.add(Material::Diffuse { color: color.into() }))
+                }
+                _ => material,
+            };
+            Sphere::new(*p, radius, material)
+        }).collect()
+    }
+}
+
+// the values of every property of one element, a single value for plain properties
+fn read_row(element: &Element, body: &mut Body) -> Result<Vec<Vec<f64>>, String> {
+    element.properties.iter().map(|p| match p.list {
+        None => Ok(vec![body.read(p.kind)?]),
+        Some(length) => {
+            let n = body.read(length)?;
+            if n < 0.0 {
+                return Err(format!("negative list length in {}", element.name));
+            }
+            (0..n as usize).map(|_| body.read(p.kind)).collect()
+        }
+    }).collect()
+}
+
+// the format and elements the header declares, and the data after it
+fn header(data: &[u8]) -> Result<(Format, Vec<Element>, &[u8]), String> {
+    let mut rest = data;
+    let mut next_line = || -> Result<String, String> {
+        let end = rest.iter().position(|b| *b == b'\n').ok_or("header has no end_header line")?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        rest = &rest[end + 1..];
+        Ok(line)
+    };
+
+    if next_line()? != "ply" {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line()?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", kind, _version] => {
+                format = Some(match kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(format!("unknown format {kind}")),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| format!("bad count for element {name}"))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            }
+            ["property", "list", length, kind, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let length = Scalar::parse(length).ok_or(format!("unknown type {length}"))?;
+                let kind = Scalar::parse(kind).ok_or(format!("unknown type {kind}"))?;
+                element.properties.push(Property { name: name.to_string(), kind, list: Some(length) });
+            }
+            ["property", kind, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let kind = Scalar::parse(kind).ok_or(format!("unknown type {kind}"))?;
+                element.properties.push(Property { name: name.to_string(), kind, list: None });
+            }
+            _ => return Err(format!("unexpected header line '{line}'")),
+        }
+    }
+    let format = format.ok_or("header has no format line")?;
+    Ok((format, elements, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(points: &[Point3]) -> Vec<[f64; 3]> {
+        points.iter().map(|p| p.v).collect()
+    }
+
+    const BINARY_HEADER: &str = "element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    // the triangle (0,0,0), (1,0,0), (0,1,0) with red, green and blue corners
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut data = format!("ply\nformat {format} 1.0\n{BINARY_HEADER}").into_bytes();
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let colors = [[255u8, 0, 0], [0, 255, 0], [0, 0, 255]];
+        for (p, c) in positions.iter().zip(colors) {
+            for x in p {
+                data.extend(if big_endian { x.to_be_bytes() } else { x.to_le_bytes() });
+            }
+            data.extend(c);
+        }
+        data.push(3);
+        for i in [0i32, 1, 2] {
+            data.extend(if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        data
+    }
+
+    fn check_triangle(ply: &Ply) {
+        assert_eq!(coordinates(&ply.positions), [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        assert_eq!(ply.faces, vec![vec![0, 1, 2]]);
+        let colors = ply.colors.as_ref().expect("vertex colours");
+        assert_eq!(colors[0].v, [1.0, 0.0, 0.0]);
+        assert_eq!(colors[2].v, [0.0, 0.0, 1.0]);
+        assert!(ply.normals.is_none() && ply.uvs.is_none());
+    }
+
+    #[test]
+    fn ascii() {
+        let data = "ply\nformat ascii 1.0\ncomment made by hand\n\
+            element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\nproperty float u\nproperty float v\n\
+            element face 2\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0 0 0 1 0 0\n1 0 0 0 0 1 1 0\n1 1 0 0 0 1 1 1\n0 1 0 0 0 1 0 1\n\
+            4 0 1 2 3\n2 0 2\n";
+        let ply = Ply::parse(data.as_bytes()).unwrap();
+        assert_eq!(ply.positions.len(), 4);
+        assert_eq!(ply.positions[2].v, [1.0, 1.0, 0.0]);
+        assert_eq!(ply.normals.unwrap()[3].v, [0.0, 0.0, 1.0]);
+        assert_eq!(ply.uvs.unwrap()[2], (1.0, 1.0));
+        // the quad is kept whole for the mesh loader to split, the two index line is dropped
+        assert_eq!(ply.faces, vec![vec![0, 1, 2, 3]]);
+        assert!(ply.colors.is_none());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        check_triangle(&Ply::parse(&binary("binary_little_endian", false)).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        check_triangle(&Ply::parse(&binary("binary_big_endian", true)).unwrap());
+    }
+
+    #[test]
+    fn skips_unknown_properties_and_elements() {
+        let data = "ply\nformat ascii 1.0\n\
+            element vertex 3\nproperty double confidence\nproperty float x\nproperty float y\nproperty float z\n\
+            property list uchar float samples\n\
+            element edge 1\nproperty int vertex1\nproperty int vertex2\n\
+            element face 1\nproperty list uint int vertex_index\nend_header\n\
+            0.5 0 0 0 2 7 8\n0.5 1 0 0 0\n0.5 0 1 0 1 9\n\
+            0 1\n\
+            3 0 1 2\n";
+        let ply = Ply::parse(data.as_bytes()).unwrap();
+        assert_eq!(coordinates(&ply.positions), [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        assert_eq!(ply.faces, vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn point_cloud() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n\
+            0 0 0\n1 2 3\n";
+        let ply = Ply::parse(data.as_bytes()).unwrap();
+        assert!(ply.is_point_cloud());
+        assert_eq!(ply.positions.len(), 2);
+    }
+
+    #[test]
+    fn truncated_body() {
+        let mut data = binary("binary_little_endian", false);
+        data.truncate(data.len() - 2);
+        assert_eq!(Ply::parse(&data).unwrap_err(), "file ends in the middle of the data");
+
+        let data = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n1 2\n";
+        assert_eq!(Ply::parse(data.as_bytes()).unwrap_err(), "file ends in the middle of the data");
+    }
+
+    #[test]
+    fn huge_count_is_truncated_not_allocated() {
+        let data = "ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000\n\
+            property float x\nproperty float y\nproperty float z\nend_header\n\0\0\0\0";
+        assert_eq!(Ply::parse(data.as_bytes()).unwrap_err(), "4000000000000 vertex elements do not fit in the file");
+    }
+
+    #[test]
+    fn huge_count_of_empty_rows_is_skipped() {
+        // rows without properties take no bytes, so any count fits and nothing is read for them
+        let data = format!("ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar int vertex_indices\nelement junk {}\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n", usize::MAX);
+        let ply = Ply::parse(data.as_bytes()).unwrap();
+        assert_eq!(ply.faces, vec![vec![0, 1, 2]]);
+        // binary rows with properties still have to fit
+        let mut data = binary("binary_little_endian", false);
+        let end = data.windows(10).position(|w| w == b"end_header").unwrap();
+        data.splice(end..end, b"element junk 4000000000000\nproperty uchar x\n".iter().copied());
+        assert_eq!(Ply::parse(&data).unwrap_err(), "4000000000000 junk elements do not fit in the file");
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+        assert_eq!(Ply::parse(data.as_bytes()).unwrap_err(), "face uses vertex 3 but there are only 3 vertices");
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert_eq!(Ply::parse(b"obj\n").unwrap_err(), "not a PLY file");
+        assert_eq!(Ply::parse(b"ply\nformat ascii 1.0\n").unwrap_err(), "header has no end_header line");
+        assert!(Ply::parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB};
+
+// A parallelogram with one corner at q and edges u and v. Rectangles are quads with
+// perpendicular edges. The normal is u x v and (u, v) run from 0 to 1 along the edges.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // plane offset, normal . p = d for every point on the quad
+    d: f64,
+    // n / (n . n) with n = u x v, turns a point in the plane into its edge coordinates
+    w: Vec3,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: MaterialId) -> Self {
+        let n = Vec3::cross(u, v);
+        let normal = n.unit();
+        let d = Vec3::dot(normal, q);
+        let w = n / Vec3::dot(n, n);
+
+        let mut bounds = AABB::default();
+        for corner in [q, q + u, q + v, q + u + v] {
+            bounds.add(corner);
+        }
+        // axis aligned quads have no thickness, pad them so the box does not collapse
+        let pad = Vec3::new(1e-6, 1e-6, 1e-6);
+        let bounds = AABB::new(bounds.min - pad, bounds.max + pad);
+
+        Quad { q, u, v, normal, d, w, material, bounds }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        let v_dot_n = Vec3::dot(ray.direction(), self.normal);
+        if v_dot_n == 0.0 {
+            return None;
+        }
+
+        let t = (self.d - Vec3::dot(self.normal, ray.origin())) / v_dot_n;
+        if t_min > t || t > t_max {
+            return None;
+        }
+
+        let point = ray.ray_at(t);
+        let planar = point - self.q;
+        let alpha = Vec3::dot(self.w, Vec3::cross(planar, self.v));
+        let beta = Vec3::dot(self.w, Vec3::cross(self.u, planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.point = point;
+        return_record.object_point = planar;
+        return_record.material = self.material;
+        return_record.calculate_normal(ray, self.normal);
+        (return_record.u, return_record.v) = (alpha, beta);
+        return_record.tangent = self.u.unit();
+        return_record.bitangent = Vec3::cross(self.normal, return_record.tangent);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.q + 0.5 * self.u + 0.5 * self.v
+    }
+}
+
+impl Hittable for Quad {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Quad::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Quad::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a parallelogram in the z = 1 plane, slanted along x, facing +z
+    fn quad() -> Quad {
+        Quad::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), MaterialId::EMPTY)
+    }
+
+    fn toward(x: f64, y: f64) -> Ray {
+        Ray::new(Point3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn uvs_run_along_the_edges() {
+        let cases = [((0.0, 0.0), (0.0, 0.0)), ((2.0, 0.0), (1.0, 0.0)), ((1.0, 1.0), (0.0, 1.0)), ((3.0, 1.0), (1.0, 1.0)), ((1.5, 0.5), (0.5, 0.5))];
+        for ((x, y), (u, v)) in cases {
+            let hit = quad().ray_hit(&toward(x, y), 0.001, f64::INFINITY).expect("ray should hit the quad");
+            assert!((hit.t - 4.0).abs() < 1e-9, "t = {}", hit.t);
+            assert!((hit.u - u).abs() < 1e-9 && (hit.v - v).abs() < 1e-9, "({}, {}) has uv ({}, {})", x, y, hit.u, hit.v);
+        }
+        let hit = quad().ray_hit(&toward(1.5, 0.5), 0.001, f64::INFINITY).unwrap();
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9 && hit.outside_face);
+        assert!((hit.tangent - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((hit.bitangent - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn misses_outside_the_edges() {
+        // inside the bounding rectangle but past the slanted edges
+        for (x, y) in [(0.2, 0.8), (2.8, 0.2), (1.0, -0.1), (1.5, 1.1)] {
+            assert!(quad().ray_hit(&toward(x, y), 0.001, f64::INFINITY).is_none(), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn back_face_and_parallel_rays() {
+        let from_behind = Ray::new(Point3::new(1.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = quad().ray_hit(&from_behind, 0.001, f64::INFINITY).expect("back faces are hit too");
+        assert!((hit.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9 && !hit.outside_face);
+        let parallel = Ray::new(Point3::new(-5.0, 0.5, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(quad().ray_hit(&parallel, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn bounds_cover_the_corners() {
+        let bounds = quad().bounds;
+        assert!((bounds.min - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5, "{:?}", bounds);
+        assert!((bounds.max - Vec3::new(3.0, 1.0, 1.0)).length() < 1e-5, "{:?}", bounds);
+        // flat in z, but still with some thickness
+        assert!(bounds.max.z() > bounds.min.z());
+        assert!((quad().centroid() - Point3::new(1.5, 0.5, 1.0)).length() < 1e-12);
+    }
+}
@@ -0,0 +1,66 @@
+use rayon::ThreadPoolBuilder;
+
+use crate::{bvh::BVH, camera::CameraError, image::Image, scene::Scene};
+
+// Renders scenes to in-memory images. The resolution, sample count and ray depth come from the scene's camera.
+#[derive(Debug, Clone, Default)]
+pub struct Renderer {
+    threads: Option<usize>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer::default()
+    }
+
+    // number of render threads, defaults to one per core
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    // fails without rendering anything if the camera's resolution, samples or ray depth are not positive
+    pub fn render(&self, scene: &Scene) -> Result<Image, CameraError> {
+        scene.camera.validate()?;
+        let primitives = scene.primitives();
+
+        // only the top level is built here, meshes carry their own BVH
+        let bvh = BVH::new(primitives);
+
+        let mut camera = scene.camera.clone();
+        let pool = self.threads.map(|threads| ThreadPoolBuilder::new().num_threads(threads).build());
+        let image = match pool {
+            Some(Ok(pool)) => pool.install(|| camera.render(&bvh, primitives, scene.materials())),
+            // no pool of our own, or it could not be started: render on rayon's global pool
+            _ => camera.render(&bvh, primitives, scene.materials()),
+        };
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Camera, vec3::Point3};
+
+    #[test]
+    fn bad_cameras_are_rejected_before_rendering() {
+        let mut scene = Scene::new(Camera::new());
+        scene.camera.image_width = 0;
+        assert!(matches!(Renderer::new().render(&scene), Err(CameraError::Resolution(0, 4))));
+        scene.camera.image_width = -4;
+        assert!(matches!(Renderer::new().render(&scene), Err(CameraError::Resolution(-4, 4))));
+        scene.camera.image_width = 4;
+        scene.camera.samples = 0;
+        assert!(matches!(Renderer::new().render(&scene), Err(CameraError::Samples(0))));
+        scene.camera.samples = 1;
+        scene.camera.fov = 180.0;
+        assert!(matches!(Renderer::new().render(&scene), Err(CameraError::Fov(_))));
+        scene.camera.fov = 60.0;
+        scene.camera.look_at = scene.camera.look_from;
+        assert!(matches!(Renderer::new().render(&scene), Err(CameraError::LookAt(_))));
+        scene.camera.look_at = Point3::new(0.0, 0.0, -1.0);
+        let image = Renderer::new().threads(1).render(&scene).expect("a 4x4 camera renders");
+        assert_eq!((image.width(), image.height()), (4, 4));
+    }
+}
@@ -0,0 +1,1254 @@
+// Scene description files. A scene is a TOML file that declares the camera,
+// a table of named materials and the objects to render, e.g.
+//
+// [camera]
+// look_from = [0.0, 1.0, 10.0]
+// look_at = [0.0, 1.0, 0.0]
+// resolution = [1280, 720]
+//
+// [materials.red]
+// type = "diffuse"
+// color = [1.0, 0.2, 0.2]
+//
+// [[spheres]]
+// center = [0.0, 1.0, 0.0]
+// radius = 1.0
+// material = "red"
+
+use std::{collections::HashMap, fmt, fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{aabb::AABB, camera::{Camera, CameraError}, color::Color, hittable2::Primitive, instance::{Geometry, Instance}, material::{Material, MaterialId, MaterialTable, NormalMap}, texture::{ColorSpace, Filter, ImageTexture, Pattern, Procedural, Texture, TextureSpace, WrapMode}, mesh::{Displacement, MeshError, MeshOptions, NormalMode, TriMesh}, plane::Plane, ply::Ply, quad::Quad, disk::Disk, gltf_import::GltfScene, cylinder::Cylinder, cone::Cone, torus::Torus, cuboid::Cuboid, curve::{load_strands, CurveMode}, csg::{Csg, CsgOp}, heightfield::{Heightfield, HeightfieldError}, sdf::{Sdf, SdfShape}, sphere::Sphere, transform::Transform, vec3::Vec3};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownMaterial(String),
+    // a material declares both a normal map and a bump map
+    ConflictingMaps(String),
+    Mesh(MeshError),
+    Texture(PathBuf, image::ImageError),
+    Heightfield(PathBuf, HeightfieldError),
+    // camera = true on a glTF file without a perspective camera
+    NoCamera(PathBuf),
+    // a transform scales an axis by zero or by a non-finite factor
+    BadScale([f64; 3]),
+    // rotation angles that are not finite
+    BadRotation([f64; 3]),
+    // an object with a negative, zero or non-finite size, e.g. a sphere of radius -1
+    BadShape(String),
+    // a texture or material parameter out of range, e.g. a checker of scale 0
+    BadMaterial(String),
+    Camera(CameraError),
+    // an ObjectId that was not handed out by this scene
+    UnknownObject(ObjectId),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            SceneError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            SceneError::ConflictingMaps(name) => write!(f, "material '{}' has both a normal_map and a bump_map", name),
+            SceneError::Mesh(e) => write!(f, "{}", e),
+            SceneError::Texture(path, e) => write!(f, "could not load texture {}: {}", path.display(), e),
+            SceneError::Heightfield(path, e) => write!(f, "could not load heightfield {}: {}", path.display(), e),
+            SceneError::NoCamera(path) => write!(f, "{} has no perspective camera to render from", path.display()),
+            SceneError::BadScale(v) => write!(f, "scale {:?} must be finite and non-zero on every axis", v),
+            SceneError::BadRotation(v) => write!(f, "rotation {:?} must be finite", v),
+            SceneError::BadShape(reason) => write!(f, "{}", reason),
+            SceneError::BadMaterial(reason) => write!(f, "{}", reason),
+            SceneError::Camera(e) => write!(f, "camera {}", e),
+            SceneError::UnknownObject(id) => write!(f, "object {} is not in the scene", id),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<MeshError> for SceneError {
+    fn from(e: MeshError) -> Self {
+        SceneError::Mesh(e)
+    }
+}
+
+// On-disk layout of a scene file
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default)]
+    materials: HashMap<String, MaterialEntry>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    planes: Vec<PlaneDesc>,
+    #[serde(default)]
+    quads: Vec<QuadDesc>,
+    #[serde(default)]
+    disks: Vec<DiskDesc>,
+    #[serde(default)]
+    cylinders: Vec<CylinderDesc>,
+    #[serde(default)]
+    cones: Vec<ConeDesc>,
+    #[serde(default)]
+    tori: Vec<TorusDesc>,
+    #[serde(default)]
+    boxes: Vec<BoxDesc>,
+    #[serde(default)]
+    csg: Vec<CsgDesc>,
+    #[serde(default)]
+    heightfields: Vec<HeightfieldDesc>,
+    #[serde(default)]
+    sdfs: Vec<SdfDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    strands: Vec<StrandsDesc>,
+    #[serde(default)]
+    points: Vec<PointsDesc>,
+    #[serde(default)]
+    gltf: Vec<GltfDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    fov: f64,
+    defocus_angle: f64,
+    focus_dist: f64,
+    resolution: [i32; 2],
+    samples: i32,
+    ray_depth: i32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        let camera = Camera::new();
+        CameraDesc {
+            look_from: camera.look_from.v,
+            look_at: camera.look_at.v,
+            fov: camera.fov,
+            defocus_angle: camera.defocus_angle,
+            focus_dist: camera.focus_dist,
+            resolution: [camera.image_width, camera.image_height],
+            samples: camera.samples,
+            ray_depth: camera.ray_depth,
+        }
+    }
+}
+
+// Material parameters are textures, written as a number, an [r, g, b] colour or a table:
+// color = { image = "wood.png", wrap = "mirror", filter = "nearest" }
+// color = { checker = [[0.9, 0.9, 0.9], [0.1, 0.1, 0.1]], scale = 8.0 }
+// color = { pattern = "marble", scale = 4.0, colors = [[0.2, 0.2, 0.3], [0.9, 0.9, 0.9]], space = "object" }
+// Noise patterns sum octaves = 1 to 16 layers of noise, 6 by default.
+// color = "vertex" takes the vertex colours of PLY and OBJ meshes.
+// Any material can also take a tangent space normal_map or a grayscale bump_map, scaled by normal_strength,
+// and an opacity map to cut it out. Opacity images use their alpha channel when they have one.
+#[derive(Deserialize, Debug)]
+struct MaterialEntry {
+    #[serde(flatten)]
+    material: MaterialDesc,
+    normal_map: Option<TextureDesc>,
+    bump_map: Option<TextureDesc>,
+    #[serde(default = "one")]
+    normal_strength: f64,
+    opacity: Option<TextureDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDesc {
+    Diffuse { color: TextureDesc },
+    Metal { color: TextureDesc, roughness: TextureDesc },
+    Dielectric {
+        #[serde(default = "white")]
+        color: TextureDesc,
+        ior: f64,
+    },
+    Glossy { color: TextureDesc, specularity: TextureDesc, roughness: TextureDesc },
+    Emission { color: TextureDesc, strength: f64 },
+    UV,
+    Stripes,
+}
+
+fn white() -> TextureDesc {
+    TextureDesc::Color([1.0; 3])
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged, deny_unknown_fields)]
+enum TextureDesc {
+    Value(f64),
+    Color([f64; 3]),
+    Keyword(TextureKeyword),
+    Image {
+        image: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+        #[serde(default)]
+        filter: Filter,
+        // defaults to srgb for colours and linear for everything else
+        color_space: Option<ColorSpace>,
+    },
+    Checker {
+        checker: [[f64; 3]; 2],
+        #[serde(default = "one")]
+        scale: f64,
+    },
+    Procedural {
+        pattern: Pattern,
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default = "black_white")]
+        colors: [[f64; 3]; 2],
+        #[serde(default)]
+        space: TextureSpace,
+        #[serde(default = "octaves")]
+        octaves: u32,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum TextureKeyword {
+    Vertex,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn black_white() -> [[f64; 3]; 2] {
+    [[0.0; 3], [1.0; 3]]
+}
+
+fn octaves() -> u32 {
+    6
+}
+
+// past this each octave is finer than a texel and the doubled coordinates lose precision
+const MAX_OCTAVES: u32 = 16;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f64; 3],
+    radius: f64,
+    material: String,
+}
+
+// an infinite plane, or a patch of one when size = [width, height] is given
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    point: [f64; 3],
+    normal: [f64; 3],
+    size: Option<[f64; 2]>,
+    material: String,
+}
+
+// a parallelogram from corner along the edges u and v
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct QuadDesc {
+    corner: [f64; 3],
+    u: [f64; 3],
+    v: [f64; 3],
+    material: String,
+}
+
+// a circle facing normal, with a hole in the middle when inner_radius is given
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DiskDesc {
+    center: [f64; 3],
+    normal: [f64; 3],
+    radius: f64,
+    #[serde(default)]
+    inner_radius: f64,
+    material: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CylinderDesc {
+    base: [f64; 3],
+    top: [f64; 3],
+    radius: f64,
+    material: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConeDesc {
+    base: [f64; 3],
+    apex: [f64; 3],
+    radius: f64,
+    material: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TorusDesc {
+    center: [f64; 3],
+    // the torus lies in the plane perpendicular to axis
+    #[serde(default = "up")]
+    axis: [f64; 3],
+    major_radius: f64,
+    minor_radius: f64,
+    material: String,
+}
+
+fn up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+// an axis aligned box, turned around its centre by rotate = [x, y, z] degrees if given
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BoxDesc {
+    min: [f64; 3],
+    max: [f64; 3],
+    #[serde(default)]
+    rotate: [f64; 3],
+    material: String,
+}
+
+// terrain from a grayscale image, black at corner and white size[1] above it
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct HeightfieldDesc {
+    image: PathBuf,
+    corner: [f64; 3],
+    size: [f64; 3],
+    material: String,
+}
+
+// A boolean combination of two closed shapes, each written inline with its kind, e.g.
+//
+// [[csg]]
+// operation = "difference"
+// left = { shape = "sphere", center = [0.0, 1.0, 0.0], radius = 1.0, material = "red" }
+// right = { shape = "box", min = [-0.5, 0.5, 0.5], max = [0.5, 1.5, 1.5], material = "steel" }
+//
+// shape = "csg" nests another combination.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CsgDesc {
+    operation: CsgOpDesc,
+    left: Box<SolidDesc>,
+    right: Box<SolidDesc>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CsgOpDesc {
+    Union,
+    Intersection,
+    Difference,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "shape", rename_all = "lowercase")]
+enum SolidDesc {
+    Sphere(SphereDesc),
+    Cylinder(CylinderDesc),
+    Cone(ConeDesc),
+    Torus(TorusDesc),
+    Box(BoxDesc),
+    Csg(CsgDesc),
+}
+
+// A distance field shape, built around the origin and optionally moved with a transform, e.g.
+//
+// [[sdfs]]
+// material = "clay"
+// transform = { translate = [0.0, 0.0, -2.0] }
+// shape = { type = "smooth_union", k = 0.3,
+//     left = { type = "sphere", radius = 0.6 },
+//     right = { type = "capsule", a = [0.0, 0.0, 0.0], b = [0.0, 1.2, 0.0], radius = 0.25 } }
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SdfDesc {
+    shape: SdfShapeDesc,
+    material: String,
+    transform: Option<TransformDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SdfShapeDesc {
+    Sphere {
+        #[serde(default)]
+        center: [f64; 3],
+        radius: f64,
+    },
+    Box {
+        #[serde(default)]
+        center: [f64; 3],
+        size: [f64; 3],
+    },
+    RoundBox {
+        #[serde(default)]
+        center: [f64; 3],
+        size: [f64; 3],
+        radius: f64,
+    },
+    Capsule { a: [f64; 3], b: [f64; 3], radius: f64 },
+    Torus {
+        #[serde(default)]
+        center: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    SmoothUnion {
+        left: Box<SdfShapeDesc>,
+        right: Box<SdfShapeDesc>,
+        #[serde(default)]
+        k: f64,
+    },
+    SmoothSubtract {
+        left: Box<SdfShapeDesc>,
+        right: Box<SdfShapeDesc>,
+        #[serde(default)]
+        k: f64,
+    },
+    SmoothIntersect {
+        left: Box<SdfShapeDesc>,
+        right: Box<SdfShapeDesc>,
+        #[serde(default)]
+        k: f64,
+    },
+    // count copies along x, y and z, period apart
+    Repeat { shape: Box<SdfShapeDesc>, period: [f64; 3], count: [u32; 3] },
+    // degrees per unit of height
+    Twist { shape: Box<SdfShapeDesc>, rate: f64 },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    // .obj, .ply for scans or .stl from CAD programs
+    file: PathBuf,
+    // used for faces whose .mtl material cannot be mapped
+    #[serde(default)]
+    material: Option<String>,
+    // how to shade meshes without vertex normals
+    #[serde(default)]
+    normals: NormalMode,
+    // levels of Loop or Catmull-Clark subdivision
+    #[serde(default)]
+    subdivide: u32,
+    // edges sharper than this many degrees stay hard
+    crease_angle: Option<f64>,
+    // displacement = { map = { image = "height.png" }, scale = 0.1 }
+    displacement: Option<DisplacementDesc>,
+    // place the mesh once with a transform, or any number of times sharing one copy of the triangles:
+    // instances = [{ translate = [2.0, 0.0, 0.0] }, { translate = [-2.0, 0.0, 0.0], rotate = [0.0, 90.0, 0.0] }]
+    transform: Option<TransformDesc>,
+    #[serde(default)]
+    instances: Vec<TransformDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DisplacementDesc {
+    map: TextureDesc,
+    #[serde(default = "one")]
+    scale: f64,
+    #[serde(default = "half")]
+    mid_level: f64,
+}
+
+fn half() -> f64 {
+    0.5
+}
+
+// hair or grass from a strand file (see curve::load_strands), placed like a mesh
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StrandsDesc {
+    file: PathBuf,
+    material: String,
+    #[serde(default)]
+    mode: CurveMode,
+    transform: Option<TransformDesc>,
+    #[serde(default)]
+    instances: Vec<TransformDesc>,
+}
+
+// a point cloud from a PLY file, a small sphere on every vertex, placed like a mesh
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PointsDesc {
+    file: PathBuf,
+    #[serde(default = "point_radius")]
+    radius: f64,
+    // without one the points take their colours from the file
+    material: Option<String>,
+    transform: Option<TransformDesc>,
+    #[serde(default)]
+    instances: Vec<TransformDesc>,
+}
+
+fn point_radius() -> f64 {
+    0.01
+}
+
+// a glTF 2.0 scene (.gltf or .glb) with its node hierarchy and materials, placed as a whole
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct GltfDesc {
+    file: PathBuf,
+    transform: Option<TransformDesc>,
+    // look through the file's first perspective camera instead of the [camera] position and fov
+    #[serde(default)]
+    camera: bool,
+}
+
+// scale, then rotate around x, y and z (in degrees), then translate
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct TransformDesc {
+    translate: [f64; 3],
+    rotate: [f64; 3],
+    scale: ScaleDesc,
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc { translate: [0.0; 3], rotate: [0.0; 3], scale: ScaleDesc::Uniform(1.0) }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+// finds a material by name for the object descriptions below
+type Lookup<'a> = dyn Fn(&str) -> Result<MaterialId, SceneError> + 'a;
+
+// sizes and radii have to be finite and larger than zero
+fn positive(shape: &str, what: &str, x: f64) -> Result<f64, SceneError> {
+    if x > 0.0 && x.is_finite() {
+        Ok(x)
+    } else {
+        Err(SceneError::BadShape(format!("{} {} {} must be positive", shape, what, x)))
+    }
+}
+
+fn positive_axes(shape: &str, what: &str, v: [f64; 3]) -> Result<Vec3, SceneError> {
+    if v.iter().all(|x| *x > 0.0 && x.is_finite()) {
+        Ok(Vec3 { v })
+    } else {
+        Err(SceneError::BadShape(format!("{} {} {:?} must be positive on every axis", shape, what, v)))
+    }
+}
+
+// normals and axes need a direction, so they have to be finite and not zero
+fn direction(shape: &str, what: &str, v: [f64; 3]) -> Result<Vec3, SceneError> {
+    let length = Vec3 { v }.length();
+    if length > 0.0 && length.is_finite() {
+        Ok(Vec3 { v })
+    } else {
+        Err(SceneError::BadShape(format!("{} {} {:?} must be finite and not zero", shape, what, v)))
+    }
+}
+
+impl SphereDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let radius = positive("sphere", "radius", self.radius)?;
+        Ok(Primitive::Sphere(Sphere::new(Vec3 { v: self.center }, radius, lookup(&self.material)?)))
+    }
+}
+
+impl CylinderDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let base = Vec3 { v: self.base };
+        let axis = direction("cylinder", "axis from base to top", (Vec3 { v: self.top } - base).v)?;
+        let radius = positive("cylinder", "radius", self.radius)?;
+        Ok(Primitive::Cylinder(Cylinder::new(base, axis, radius, lookup(&self.material)?)))
+    }
+}
+
+impl ConeDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let base = Vec3 { v: self.base };
+        let axis = direction("cone", "axis from base to apex", (Vec3 { v: self.apex } - base).v)?;
+        let radius = positive("cone", "radius", self.radius)?;
+        Ok(Primitive::Cone(Cone::new(base, axis, radius, lookup(&self.material)?)))
+    }
+}
+
+impl TorusDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let (center, axis) = (Vec3 { v: self.center }, direction("torus", "axis", self.axis)?);
+        let major = positive("torus", "major_radius", self.major_radius)?;
+        let minor = positive("torus", "minor_radius", self.minor_radius)?;
+        Ok(Primitive::Torus(Torus::new(center, axis, major, minor, lookup(&self.material)?)))
+    }
+}
+
+impl BoxDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let (min, max) = (Vec3 { v: self.min }, Vec3 { v: self.max });
+        positive_axes("box", "size", (max - min).v)?;
+        let rotation = rotation(self.rotate)?;
+        Ok(Primitive::Cuboid(Cuboid::oriented((min + max) * 0.5, max - min, &rotation, lookup(&self.material)?)))
+    }
+}
+
+impl CsgDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        let op = match self.operation {
+            CsgOpDesc::Union => CsgOp::Union,
+            CsgOpDesc::Intersection => CsgOp::Intersection,
+            CsgOpDesc::Difference => CsgOp::Difference,
+        };
+        Ok(Primitive::Csg(Csg::new(op, self.left.build(lookup)?, self.right.build(lookup)?)))
+    }
+}
+
+impl SolidDesc {
+    fn build(&self, lookup: &Lookup) -> Result<Primitive, SceneError> {
+        match self {
+            SolidDesc::Sphere(s) => s.build(lookup),
+            SolidDesc::Cylinder(c) => c.build(lookup),
+            SolidDesc::Cone(c) => c.build(lookup),
+            SolidDesc::Torus(t) => t.build(lookup),
+            SolidDesc::Box(b) => b.build(lookup),
+            SolidDesc::Csg(c) => c.build(lookup),
+        }
+    }
+}
+
+impl SdfShapeDesc {
+    fn build(&self) -> Result<SdfShape, SceneError> {
+        let half = |size: [f64; 3]| positive_axes("distance field box", "size", size).map(|size| size * 0.5);
+        let pair = |left: &SdfShapeDesc, right: &SdfShapeDesc| Ok::<_, SceneError>((Box::new(left.build()?), Box::new(right.build()?)));
+        let shape = match self {
+            SdfShapeDesc::Sphere { center, radius } => {
+                SdfShape::Sphere { center: Vec3 { v: *center }, radius: positive("distance field sphere", "radius", *radius)? }
+            }
+            SdfShapeDesc::Box { center, size } => SdfShape::Box { center: Vec3 { v: *center }, half: half(*size)? },
+            SdfShapeDesc::RoundBox { center, size, radius } => {
+                SdfShape::RoundBox { center: Vec3 { v: *center }, half: half(*size)?, radius: positive("distance field round box", "radius", *radius)? }
+            }
+            SdfShapeDesc::Capsule { a, b, radius } => {
+                SdfShape::Capsule { a: Vec3 { v: *a }, b: Vec3 { v: *b }, radius: positive("distance field capsule", "radius", *radius)? }
+            }
+            SdfShapeDesc::Torus { center, major_radius, minor_radius } => SdfShape::Torus {
+                center: Vec3 { v: *center },
+                major: positive("distance field torus", "major_radius", *major_radius)?,
+                minor: positive("distance field torus", "minor_radius", *minor_radius)?,
+            },
+            SdfShapeDesc::SmoothUnion { left, right, k } => {
+                let (left, right) = pair(left, right)?;
+                SdfShape::SmoothUnion { left, right, k: *k }
+            }
+            SdfShapeDesc::SmoothSubtract { left, right, k } => {
+                let (left, right) = pair(left, right)?;
+                SdfShape::SmoothSubtract { left, right, k: *k }
+            }
+            SdfShapeDesc::SmoothIntersect { left, right, k } => {
+                let (left, right) = pair(left, right)?;
+                SdfShape::SmoothIntersect { left, right, k: *k }
+            }
+            SdfShapeDesc::Repeat { shape, period, count } => {
+                // the period only matters along axes with more than one copy
+                for (n, period) in count.iter().zip(period) {
+                    if *n > 1 {
+                        positive("distance field repeat", "period", *period)?;
+                    }
+                }
+                SdfShape::Repeat { shape: Box::new(shape.build()?), period: Vec3 { v: *period }, count: *count }
+            }
+            SdfShapeDesc::Twist { shape, rate } => SdfShape::Twist { shape: Box::new(shape.build()?), rate: *rate },
+        };
+        Ok(shape)
+    }
+}
+
+impl TransformDesc {
+    fn build(&self) -> Result<Transform, SceneError> {
+        let scale = match self.scale {
+            ScaleDesc::Uniform(s) => Vec3::new(s, s, s),
+            ScaleDesc::Axes(v) => Vec3 { v },
+        };
+        let scale = Transform::try_scale(scale).ok_or(SceneError::BadScale(scale.v))?;
+        Ok(scale.then(&rotation(self.rotate)?).then(&Transform::translate(Vec3 { v: self.translate })))
+    }
+}
+
+// turns around x, then y, then z, by degrees
+fn rotation(degrees: [f64; 3]) -> Result<Transform, SceneError> {
+    if !degrees.iter().all(|d| d.is_finite()) {
+        return Err(SceneError::BadRotation(degrees));
+    }
+    let [rx, ry, rz] = degrees;
+    Ok(Transform::rotate_x(rx).then(&Transform::rotate_y(ry)).then(&Transform::rotate_z(rz)))
+}
+
+impl MaterialEntry {
+    fn build(&self, name: &str, base_dir: &Path) -> Result<Material, SceneError> {
+        let mut material = self.material.build(base_dir)?;
+        let strength = self.normal_strength;
+        if !strength.is_finite() {
+            return Err(SceneError::BadMaterial(format!("material '{}' normal_strength {} must be finite", name, strength)));
+        }
+        // normal, bump and opacity maps hold data, not colours
+        let map = match (&self.normal_map, &self.bump_map) {
+            (Some(_), Some(_)) => return Err(SceneError::ConflictingMaps(name.to_string())),
+            (Some(map), None) => Some(NormalMap::Tangent { map: map.build(base_dir, ColorSpace::Linear)?, strength }),
+            (None, Some(height)) => Some(NormalMap::Bump { height: height.build(base_dir, ColorSpace::Linear)?, strength }),
+            (None, None) => None,
+        };
+        if let Some(map) = map {
+            material = Material::NormalMapped { base: Box::new(material), map };
+        }
+        if let Some(opacity) = &self.opacity {
+            let opacity = opacity.build(base_dir, ColorSpace::Linear)?.into_opacity();
+            material = Material::Masked { base: Box::new(material), opacity };
+        }
+        Ok(material)
+    }
+}
+
+impl MaterialDesc {
+    fn build(&self, base_dir: &Path) -> Result<Material, SceneError> {
+        let color = |t: &TextureDesc| t.build(base_dir, ColorSpace::Srgb);
+        let value = |t: &TextureDesc| t.build(base_dir, ColorSpace::Linear);
+        let material = match self {
+            MaterialDesc::Diffuse { color: c } => Material::Diffuse { color: color(c)? },
+            MaterialDesc::Metal { color: c, roughness } => Material::Metal { color: color(c)?, roughness: value(roughness)? },
+            MaterialDesc::Dielectric { color: c, ior } => Material::Dielectric { color: color(c)?, ior: *ior },
+            MaterialDesc::Glossy { color: c, specularity, roughness } => {
+                Material::Glossy { color: color(c)?, specularity: value(specularity)?, roughness: value(roughness)? }
+            }
+            MaterialDesc::Emission { color: c, strength } => {
+                if !(*strength >= 0.0 && strength.is_finite()) {
+                    return Err(SceneError::BadMaterial(format!("emission strength {} must be finite and not negative", strength)));
+                }
+                Material::Emission { color: color(c)?, strength: *strength }
+            }
+            MaterialDesc::UV => Material::UV,
+            MaterialDesc::Stripes => Material::Stripes,
+        };
+        Ok(material)
+    }
+}
+
+impl TextureDesc {
+    fn build(&self, base_dir: &Path, default_space: ColorSpace) -> Result<Texture, SceneError> {
+        let color = |[r, g, b]: [f64; 3]| Color::new(r, g, b);
+        let texture = match self {
+            TextureDesc::Value(x) => (*x).into(),
+            TextureDesc::Color(c) => color(*c).into(),
+            TextureDesc::Keyword(TextureKeyword::Vertex) => Texture::VertexColor,
+            TextureDesc::Image { image, wrap, filter, color_space } => {
+                let path = base_dir.join(image);
+                let mut texture = ImageTexture::load(&path, color_space.unwrap_or(default_space))
+                    .map_err(|e| SceneError::Texture(path, e))?;
+                texture.wrap = *wrap;
+                texture.filter = *filter;
+                texture.into()
+            }
+            TextureDesc::Checker { checker: [even, odd], scale } => {
+                Texture::Checker { even: color(*even), odd: color(*odd), scale: texture_scale("checker", *scale)? }
+            }
+            TextureDesc::Procedural { pattern, scale, colors: [low, high], space, octaves } => {
+                let scale = texture_scale("procedural texture", *scale)?;
+                if !(1..=MAX_OCTAVES).contains(octaves) {
+                    return Err(SceneError::BadMaterial(format!("octaves {} must be between 1 and {}", octaves, MAX_OCTAVES)));
+                }
+                let mut procedural = Procedural::new(*pattern, scale, [color(*low), color(*high)]);
+                procedural.space = *space;
+                procedural.octaves = *octaves;
+                Texture::Procedural(Arc::new(procedural))
+            }
+        };
+        Ok(texture)
+    }
+}
+
+// patterns are sampled at 1 / (8 * scale) for bump maps, so the scale must be usable as a divisor
+fn texture_scale(texture: &str, scale: f64) -> Result<f64, SceneError> {
+    if scale > 0.0 && scale.is_finite() {
+        Ok(scale)
+    } else {
+        Err(SceneError::BadMaterial(format!("{} scale {} must be positive", texture, scale)))
+    }
+}
+
+// A camera and the objects to render. Scenes are either loaded from a scene file or built up in code:
+//
+// let mut scene = Scene::new(Camera::new());
+// let red = scene.add_material(Material::Diffuse { color: Color::new(0.8, 0.2, 0.1).into() });
+// scene.add(Primitive::Sphere(Sphere::new(Point3::new(0.0, 0.0, -2.0), 1.0, red)));
+// let car = scene.add_mesh(TriMesh::new("car.obj", MaterialId::EMPTY, scene.materials_mut())?);
+// let image = Renderer::new().render(&scene)?;
+//
+// Acceleration is two level: every mesh has its own BVH, built once when it is added, and the
+// renderer builds a small top level BVH over the scene's objects. Moving an object with
+// set_transform only invalidates the top level:
+//
+// scene.set_transform(car, Transform::translate(Vec3::new(0.0, 0.0, -1.0)))?;
+// let next_frame = Renderer::new().render(&scene)?;
+//
+// Objects refer to their materials by id, materials are added to the scene's table once and
+// shared by everything that uses them.
+pub struct Scene {
+    pub camera: Camera,
+    primitives: Vec<Primitive>,
+    materials: MaterialTable,
+}
+
+// Index of an object in the scene, handed out when the object is added
+pub type ObjectId = usize;
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Scene { camera, primitives: vec![], materials: MaterialTable::new() }
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.add(material)
+    }
+
+    pub fn materials(&self) -> &MaterialTable {
+        &self.materials
+    }
+
+    // for loaders that add the materials of a file, e.g. TriMesh::load
+    pub fn materials_mut(&mut self) -> &mut MaterialTable {
+        &mut self.materials
+    }
+
+    pub fn add(&mut self, primitive: Primitive) -> ObjectId {
+        self.primitives.push(primitive);
+        self.primitives.len() - 1
+    }
+
+    pub fn add_mesh(&mut self, mesh: TriMesh) -> ObjectId {
+        self.add_instance(Arc::new(Geometry::from(mesh)), Transform::identity())
+    }
+
+    pub fn add_instance(&mut self, geometry: Arc<Geometry>, transform: Transform) -> ObjectId {
+        self.add(Primitive::Instance(Instance::new(geometry, transform)))
+    }
+
+    // Replaces the transform of an object. Instances are placed relative to their geometry's own
+    // space, anything else relative to where it was added, by becoming an instance of itself.
+    pub fn set_transform(&mut self, id: ObjectId, transform: Transform) -> Result<(), SceneError> {
+        let primitive = self.primitives.get_mut(id).ok_or(SceneError::UnknownObject(id))?;
+        let geometry = match &*primitive {
+            Primitive::Instance(instance) => instance.geometry().clone(),
+            other => Arc::new(Geometry::new(vec![other.clone()])),
+        };
+        *primitive = Primitive::Instance(Instance::new(geometry, transform));
+        Ok(())
+    }
+
+    pub fn primitives(&self) -> &[Primitive] {
+        &self.primitives
+    }
+
+    // bounds of the finite objects, infinite planes are left out
+    pub fn bounds(&self) -> AABB {
+        let mut bounds = AABB::default();
+        for p in self.primitives.iter().filter(|p| p.is_bounded()) {
+            bounds.join(p.bounds());
+        }
+        bounds
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+        let file: SceneFile = toml::from_str(&text).map_err(|e| SceneError::Parse(path.to_path_buf(), e))?;
+
+        // mesh paths are relative to the scene file
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        Scene::from_desc(file, base_dir)
+    }
+
+    fn from_desc(file: SceneFile, base_dir: &Path) -> Result<Scene, SceneError> {
+        let mut scene = Scene::new(Camera::new());
+
+        let materials: HashMap<String, MaterialId> = file.materials.iter()
+            .map(|(name, entry)| Ok((name.clone(), scene.add_material(entry.build(name, base_dir)?))))
+            .collect::<Result<_, SceneError>>()?;
+        let lookup = |name: &str| {
+            materials.get(name).copied().ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+        };
+
+        for s in &file.spheres {
+            scene.add(s.build(&lookup)?);
+        }
+
+        for p in &file.planes {
+            let (point, normal) = (Vec3 { v: p.point }, direction("plane", "normal", p.normal)?);
+            let plane = match p.size {
+                Some([width, height]) => {
+                    let (width, height) = (positive("plane", "width", width)?, positive("plane", "height", height)?);
+                    Plane::patch(point, normal, width, height, lookup(&p.material)?)
+                }
+                None => Plane::new(point, normal, lookup(&p.material)?),
+            };
+            scene.add(Primitive::Plane(plane));
+        }
+
+        for q in &file.quads {
+            let (corner, u, v) = (Vec3 { v: q.corner }, Vec3 { v: q.u }, Vec3 { v: q.v });
+            // edges of zero length, or along the same line, leave no area and no normal
+            let area = Vec3::cross(u, v).length();
+            if area <= 0.0 || !area.is_finite() {
+                return Err(SceneError::BadShape(format!("quad edges {:?} and {:?} must span a parallelogram", q.u, q.v)));
+            }
+            scene.add(Primitive::Quad(Quad::new(corner, u, v, lookup(&q.material)?)));
+        }
+
+        for d in &file.disks {
+            let (center, normal) = (Vec3 { v: d.center }, direction("disk", "normal", d.normal)?);
+            let radius = positive("disk", "radius", d.radius)?;
+            if !(d.inner_radius >= 0.0 && d.inner_radius < radius) {
+                return Err(SceneError::BadShape(format!("disk inner_radius {} must be at least 0 and less than the radius", d.inner_radius)));
+            }
+            scene.add(Primitive::Disk(Disk::ring(center, normal, d.inner_radius, radius, lookup(&d.material)?)));
+        }
+
+        for c in &file.cylinders {
+            scene.add(c.build(&lookup)?);
+        }
+
+        for c in &file.cones {
+            scene.add(c.build(&lookup)?);
+        }
+
+        for t in &file.tori {
+            scene.add(t.build(&lookup)?);
+        }
+
+        for b in &file.boxes {
+            scene.add(b.build(&lookup)?);
+        }
+
+        for c in &file.csg {
+            scene.add(c.build(&lookup)?);
+        }
+
+        for h in &file.heightfields {
+            let path = base_dir.join(&h.image);
+            let heightfield = Heightfield::load(&path, Vec3 { v: h.corner }, Vec3 { v: h.size }, lookup(&h.material)?)
+                .map_err(|e| SceneError::Heightfield(path, e))?;
+            scene.add(Primitive::Heightfield(heightfield));
+        }
+
+        for d in &file.sdfs {
+            let id = scene.add(Primitive::Sdf(Sdf::new(d.shape.build()?, lookup(&d.material)?)));
+            if let Some(transform) = &d.transform {
+                scene.set_transform(id, transform.build()?)?;
+            }
+        }
+
+        for m in &file.meshes {
+            let material = match &m.material {
+                Some(name) => lookup(name)?,
+                None => MaterialId::EMPTY,
+            };
+            let displacement = match &m.displacement {
+                Some(d) => Some(Displacement { map: d.map.build(base_dir, ColorSpace::Linear)?, scale: d.scale, mid_level: d.mid_level }),
+                None => None,
+            };
+            let options = MeshOptions { normals: m.normals, subdivision: m.subdivide, crease_angle: m.crease_angle, displacement };
+            let mesh = TriMesh::load(base_dir.join(&m.file), material, &options, &mut scene.materials)?;
+            let transforms = m.transform.iter().chain(&m.instances).map(TransformDesc::build).collect::<Result<Vec<_>, _>>()?;
+            if transforms.is_empty() {
+                scene.add_mesh(mesh);
+                continue;
+            }
+            let geometry = Arc::new(Geometry::from(mesh));
+            for transform in transforms {
+                scene.add_instance(geometry.clone(), transform);
+            }
+        }
+
+        for s in &file.strands {
+            let curves = load_strands(base_dir.join(&s.file), s.mode, lookup(&s.material)?)?;
+            let geometry = Arc::new(Geometry::new(curves.into_iter().map(Primitive::Curve).collect()));
+            let mut transforms = s.transform.iter().chain(&s.instances).map(TransformDesc::build).collect::<Result<Vec<_>, _>>()?;
+            if transforms.is_empty() {
+                transforms.push(Transform::identity());
+            }
+            for transform in transforms {
+                scene.add_instance(geometry.clone(), transform);
+            }
+        }
+
+        for p in &file.points {
+            let material = match &p.material {
+                Some(name) => lookup(name)?,
+                None => MaterialId::EMPTY,
+            };
+            let radius = positive("point", "radius", p.radius)?;
+            let spheres = Ply::load(base_dir.join(&p.file))?.spheres(radius, material, &mut scene.materials);
+            let geometry = Arc::new(Geometry::new(spheres.into_iter().map(Primitive::Sphere).collect()));
+            let mut transforms = p.transform.iter().chain(&p.instances).map(TransformDesc::build).collect::<Result<Vec<_>, _>>()?;
+            if transforms.is_empty() {
+                transforms.push(Transform::identity());
+            }
+            for transform in transforms {
+                scene.add_instance(geometry.clone(), transform);
+            }
+        }
+
+        let mut gltf_camera = None;
+        for g in &file.gltf {
+            let gltf = GltfScene::load(base_dir.join(&g.file), &mut scene.materials)?;
+            let placement = g.transform.as_ref().map_or(Ok(Transform::identity()), TransformDesc::build)?;
+            for (geometry, transform) in gltf.instances {
+                scene.add_instance(geometry, transform.then(&placement));
+            }
+            if g.camera {
+                let mut camera = gltf.cameras.into_iter().next()
+                    .ok_or_else(|| SceneError::NoCamera(base_dir.join(&g.file)))?;
+                camera.transform = camera.transform.then(&placement);
+                gltf_camera = Some(camera);
+            }
+        }
+
+        let c = &file.camera;
+        let camera = &mut scene.camera;
+        camera.look_from = Vec3 { v: c.look_from };
+        camera.look_at = Vec3 { v: c.look_at };
+        camera.fov = c.fov;
+        camera.defocus_angle = c.defocus_angle;
+        camera.focus_dist = c.focus_dist;
+        camera.image_width = c.resolution[0];
+        camera.image_height = c.resolution[1];
+        camera.samples = c.samples;
+        camera.ray_depth = c.ray_depth;
+        if let Some(gltf_camera) = gltf_camera {
+            gltf_camera.apply(camera);
+        }
+        camera.validate().map_err(SceneError::Camera)?;
+
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bvh::BVH, ray::Ray, vec3::Point3};
+
+    fn parse(text: &str) -> Result<Scene, SceneError> {
+        let file = toml::from_str(text).map_err(|e| SceneError::Parse(PathBuf::from("test.toml"), e))?;
+        Scene::from_desc(file, Path::new(""))
+    }
+
+    const SPHERE: &str = r#"
+        [camera]
+        look_from = [0.0, 1.0, 5.0]
+        look_at = [0.0, 1.0, 0.0]
+        fov = 45.0
+        resolution = [320, 180]
+        samples = 16
+        ray_depth = 4
+
+        [materials.red]
+        type = "diffuse"
+        color = [1.0, 0.2, 0.2]
+
+        [[spheres]]
+        center = [0.0, 1.0, 0.0]
+        radius = 1.0
+        material = "red"
+
+        [[planes]]
+        point = [0.0, 0.0, 0.0]
+        normal = [0.0, 1.0, 0.0]
+        material = "red"
+    "#;
+
+    #[test]
+    fn loads_camera_materials_and_objects() {
+        let scene = parse(SPHERE).expect("scene should load");
+        let camera = &scene.camera;
+        assert_eq!((camera.image_width, camera.image_height), (320, 180));
+        assert_eq!((camera.samples, camera.ray_depth), (16, 4));
+        assert_eq!(camera.fov, 45.0);
+        assert_eq!(camera.look_from.v, [0.0, 1.0, 5.0]);
+        assert!(matches!(scene.primitives(), [Primitive::Sphere(_), Primitive::Plane(_)]));
+        let bounds = scene.bounds();
+        assert_eq!((bounds.min.v, bounds.max.v), ([-1.0, 0.0, -1.0], [1.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        for name in ["cornell", "csg", "sdf", "spheres"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes").join(format!("{}.toml", name));
+            if let Err(e) = Scene::load(&path) {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_io_error() {
+        let result = Scene::load("scenes/does_not_exist.toml");
+        assert!(matches!(result, Err(SceneError::Io(..))), "{:?}", result.err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let text = SPHERE.replace("radius = 1.0", "radius = 1.0\ncolour = [1.0, 0.0, 0.0]");
+        assert!(matches!(parse(&text), Err(SceneError::Parse(..))));
+        assert!(matches!(parse("[[cubes]]\nsize = 1.0"), Err(SceneError::Parse(..))));
+    }
+
+    #[test]
+    fn unknown_material_is_an_error() {
+        let text = SPHERE.replace("material = \"red\"\n\n        [[planes]]", "material = \"blue\"\n\n        [[planes]]");
+        assert!(matches!(parse(&text), Err(SceneError::UnknownMaterial(name)) if name == "blue"));
+    }
+
+    #[test]
+    fn degenerate_shapes_are_rejected() {
+        for radius in ["-1.0", "0.0", "nan"] {
+            let text = SPHERE.replace("radius = 1.0", &format!("radius = {}", radius));
+            assert!(matches!(parse(&text), Err(SceneError::BadShape(_))), "radius {}", radius);
+        }
+        let boxes = "[materials.m]\ntype = \"uv\"\n[[boxes]]\nmin = [0.0, 0.0, 0.0]\nmax = [1.0, 0.0, 1.0]\nmaterial = \"m\"";
+        assert!(matches!(parse(boxes), Err(SceneError::BadShape(_))));
+        let torus = "[materials.m]\ntype = \"uv\"\n[[tori]]\ncenter = [0.0, 0.0, 0.0]\nmajor_radius = 1.0\nminor_radius = -0.1\nmaterial = \"m\"";
+        assert!(matches!(parse(torus), Err(SceneError::BadShape(_))));
+        let sdf = "[materials.m]\ntype = \"uv\"\n[[sdfs]]\nmaterial = \"m\"\nshape = { type = \"sphere\", radius = 0.0 }";
+        assert!(matches!(parse(sdf), Err(SceneError::BadShape(_))));
+    }
+
+    #[test]
+    fn shapes_without_a_direction_are_rejected() {
+        let objects = [
+            "[[planes]]\npoint = [0.0, 0.0, 0.0]\nnormal = [0.0, 0.0, 0.0]",
+            "[[quads]]\ncorner = [0.0, 0.0, 0.0]\nu = [1.0, 0.0, 0.0]\nv = [2.0, 0.0, 0.0]",
+            "[[quads]]\ncorner = [0.0, 0.0, 0.0]\nu = [0.0, 0.0, 0.0]\nv = [0.0, 1.0, 0.0]",
+            "[[disks]]\ncenter = [0.0, 0.0, 0.0]\nnormal = [0.0, 0.0, 0.0]\nradius = 1.0",
+            "[[disks]]\ncenter = [0.0, 0.0, 0.0]\nnormal = [0.0, 1.0, 0.0]\nradius = 0.0",
+            "[[disks]]\ncenter = [0.0, 0.0, 0.0]\nnormal = [0.0, 1.0, 0.0]\nradius = 1.0\ninner_radius = 1.0",
+            "[[disks]]\ncenter = [0.0, 0.0, 0.0]\nnormal = [0.0, 1.0, 0.0]\nradius = 1.0\ninner_radius = -0.5",
+            "[[cylinders]]\nbase = [0.0, 1.0, 0.0]\ntop = [0.0, 1.0, 0.0]\nradius = 1.0",
+            "[[cones]]\nbase = [0.0, 0.0, 0.0]\napex = [0.0, 0.0, 0.0]\nradius = 1.0",
+            "[[tori]]\ncenter = [0.0, 0.0, 0.0]\naxis = [0.0, 0.0, 0.0]\nmajor_radius = 1.0\nminor_radius = 0.1",
+        ];
+        for object in objects {
+            let text = format!("[materials.m]\ntype = \"uv\"\n{}\nmaterial = \"m\"", object);
+            assert!(matches!(parse(&text), Err(SceneError::BadShape(_))), "{}", object);
+        }
+    }
+
+    #[test]
+    fn texture_scales_must_be_positive() {
+        for scale in ["0.0", "-2.0", "inf"] {
+            for texture in ["checker = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]", "pattern = \"marble\""] {
+                let text = SPHERE.replace("color = [1.0, 0.2, 0.2]", &format!("color = {{ {}, scale = {} }}", texture, scale));
+                assert!(matches!(parse(&text), Err(SceneError::BadMaterial(_))), "{} scale {}", texture, scale);
+            }
+        }
+        let text = SPHERE.replace("color = [1.0, 0.2, 0.2]", "color = { checker = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]], scale = 4.0 }");
+        assert!(parse(&text).is_ok());
+    }
+
+    #[test]
+    fn octaves_and_strengths_are_checked() {
+        for octaves in [0, 17] {
+            let text = SPHERE.replace("color = [1.0, 0.2, 0.2]", &format!("color = {{ pattern = \"wood\", octaves = {} }}", octaves));
+            assert!(matches!(parse(&text), Err(SceneError::BadMaterial(_))), "octaves {}", octaves);
+        }
+        let text = SPHERE.replace("color = [1.0, 0.2, 0.2]", "color = { pattern = \"wood\", octaves = 16 }");
+        assert!(parse(&text).is_ok());
+        for strength in ["-1.0", "nan", "inf"] {
+            let text = SPHERE.replace("type = \"diffuse\"\n        color = [1.0, 0.2, 0.2]", &format!("type = \"emission\"\n        color = [1.0, 1.0, 1.0]\n        strength = {}", strength));
+            assert!(matches!(parse(&text), Err(SceneError::BadMaterial(_))), "emission strength {}", strength);
+        }
+        let text = SPHERE.replace("color = [1.0, 0.2, 0.2]", "color = [1.0, 0.2, 0.2]\n        bump_map = 0.5\n        normal_strength = nan");
+        assert!(matches!(parse(&text), Err(SceneError::BadMaterial(_))));
+    }
+
+    fn first_hit(scene: &Scene, origin: Point3) -> Option<f64> {
+        let bvh = BVH::new(scene.primitives());
+        let ray = Ray::new(origin, Vec3::new(0.0, 0.0, -1.0));
+        bvh.ray_hit(scene.primitives(), scene.materials(), &ray, 0.001, f64::INFINITY).map(|hit| hit.t)
+    }
+
+    fn geometry_of(scene: &Scene, id: ObjectId) -> &Arc<Geometry> {
+        match &scene.primitives()[id] {
+            Primitive::Instance(instance) => instance.geometry(),
+            _ => panic!("object {} is not an instance", id),
+        }
+    }
+
+    #[test]
+    fn instances_share_their_geometry_and_move_on_their_own() {
+        let mut scene = Scene::new(Camera::new());
+        let red = scene.add_material(Material::Diffuse { color: Color::new(1.0, 0.0, 0.0).into() });
+        let ball = Arc::new(Geometry::new(vec![Primitive::Sphere(Sphere::new(Point3::default(), 1.0, red))]));
+        let left = scene.add_instance(ball.clone(), Transform::translate(Vec3::new(-3.0, 0.0, 0.0)));
+        let right = scene.add_instance(ball.clone(), Transform::translate(Vec3::new(3.0, 0.0, 0.0)));
+        assert!(Arc::ptr_eq(geometry_of(&scene, left), &ball) && Arc::ptr_eq(geometry_of(&scene, right), &ball));
+        assert_eq!(Arc::strong_count(&ball), 3);
+
+        let (in_front_of_left, in_front_of_right) = (Point3::new(-3.0, 0.0, 5.0), Point3::new(3.0, 0.0, 5.0));
+        assert_eq!(first_hit(&scene, in_front_of_left), Some(4.0));
+        assert_eq!(first_hit(&scene, in_front_of_right), Some(4.0));
+        assert_eq!(first_hit(&scene, Point3::new(0.0, 0.0, 5.0)), None);
+
+        // moving one instance leaves the other, and the shared geometry, where they were
+        scene.set_transform(right, Transform::translate(Vec3::new(3.0, 0.0, -2.0))).unwrap();
+        assert_eq!(first_hit(&scene, in_front_of_right), Some(6.0));
+        assert_eq!(first_hit(&scene, in_front_of_left), Some(4.0));
+        assert!(Arc::ptr_eq(geometry_of(&scene, right), &ball));
+        assert_eq!(Arc::strong_count(&ball), 3);
+
+        // plain primitives become an instance of themselves
+        let sphere = scene.add(Primitive::Sphere(Sphere::new(Point3::new(0.0, 10.0, 0.0), 1.0, red)));
+        scene.set_transform(sphere, Transform::translate(Vec3::new(0.0, -10.0, 0.0))).unwrap();
+        assert_eq!(first_hit(&scene, Point3::new(0.0, 0.0, 5.0)), Some(4.0));
+    }
+
+    #[test]
+    fn moving_an_unknown_object_is_an_error() {
+        let mut scene = Scene::new(Camera::new());
+        let result = scene.set_transform(3, Transform::identity());
+        assert!(matches!(result, Err(SceneError::UnknownObject(3))));
+    }
+
+    #[test]
+    fn bad_camera_settings_are_rejected() {
+        let cases = [
+            ("resolution = [320, 180]", "resolution = [0, -5]"),
+            ("samples = 16", "samples = -1"),
+            ("ray_depth = 4", "ray_depth = 0"),
+            ("fov = 45.0", "fov = 0.0"),
+            ("fov = 45.0", "fov = 180.0"),
+            ("fov = 45.0", "fov = nan"),
+            ("look_at = [0.0, 1.0, 0.0]", "look_at = [0.0, 1.0, 5.0]"),
+        ];
+        for (from, to) in cases {
+            let result = parse(&SPHERE.replace(from, to));
+            assert!(matches!(result, Err(SceneError::Camera(_))), "{}: {:?}", to, result.err());
+        }
+    }
+}
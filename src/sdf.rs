@@ -0,0 +1,361 @@
+// Shapes given by signed distance functions: negative inside, positive outside and roughly the
+// distance to the surface. They are found by sphere tracing, stepping along the ray by the
+// distance to the nearest surface, so blends, repetitions and twists cost no more memory than
+// the shapes they are made of. Shapes are built around the origin and placed with a transform.
+//
+// let bolt = SdfShape::Twist {
+//     shape: Box::new(SdfShape::RoundBox { center: Point3::new(0.0, 1.0, 0.0), half: Vec3::new(0.3, 1.0, 0.3), radius: 0.05 }),
+//     rate: 90.0,
+// };
+// scene.add(Primitive::Sdf(Sdf::new(bolt, material)));
+
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, sphere::Sphere};
+
+// steps before a ray that keeps grazing a surface is given up on
+const MAX_STEPS: usize = 512;
+// smallest step along the ray, in world units
+const MIN_STEP: f64 = 1e-5;
+// offset for the central differences of the normal
+const GRADIENT_STEP: f64 = 1e-5;
+
+#[derive(Debug, Clone)]
+pub enum SdfShape {
+    Sphere { center: Point3, radius: f64 },
+    // half is half the size along each axis
+    Box { center: Point3, half: Vec3 },
+    // a box of the same outer size with its edges rounded off by radius
+    RoundBox { center: Point3, half: Vec3, radius: f64 },
+    // the points within radius of the segment from a to b
+    Capsule { a: Point3, b: Point3, radius: f64 },
+    // lying in the xz plane
+    Torus { center: Point3, major: f64, minor: f64 },
+    // blends that melt the two shapes together over a distance of about k, k = 0 is a sharp edge
+    SmoothUnion { left: Box<SdfShape>, right: Box<SdfShape>, k: f64 },
+    // left with right carved out of it
+    SmoothSubtract { left: Box<SdfShape>, right: Box<SdfShape>, k: f64 },
+    SmoothIntersect { left: Box<SdfShape>, right: Box<SdfShape>, k: f64 },
+    // count copies along each axis, period apart and centred on the origin. Each copy has to
+    // fit inside its own cell or the distances get too long
+    Repeat { shape: Box<SdfShape>, period: Vec3, count: [u32; 3] },
+    // turned around the y axis by rate degrees per unit of height
+    Twist { shape: Box<SdfShape>, rate: f64 },
+}
+
+impl SdfShape {
+    pub fn distance(&self, p: Point3) -> f64 {
+        match self {
+            SdfShape::Sphere { center, radius } => (p - *center).length() - radius,
+            SdfShape::Box { center, half } => box_distance(p - *center, *half),
+            SdfShape::RoundBox { center, half, radius } => {
+                box_distance(p - *center, *half - Vec3::new(*radius, *radius, *radius)) - radius
+            }
+            SdfShape::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = (Vec3::dot(pa, ba) / Vec3::dot(ba, ba)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            SdfShape::Torus { center, major, minor } => {
+                let q = p - *center;
+                let ring = f64::sqrt(q.x() * q.x() + q.z() * q.z()) - major;
+                f64::sqrt(ring * ring + q.y() * q.y()) - minor
+            }
+            SdfShape::SmoothUnion { left, right, k } => smooth_min(left.distance(p), right.distance(p), *k),
+            SdfShape::SmoothSubtract { left, right, k } => -smooth_min(-left.distance(p), right.distance(p), *k),
+            SdfShape::SmoothIntersect { left, right, k } => -smooth_min(-left.distance(p), -right.distance(p), *k),
+            SdfShape::Repeat { shape, period, count } => {
+                // move p into the cell of the nearest copy
+                let mut q = p;
+                for (i, n) in count.iter().enumerate() {
+                    if *n > 1 && period.v[i] > 0.0 {
+                        let middle = (n - 1) as f64 / 2.0;
+                        let cell = (p.v[i] / period.v[i] + middle).round().clamp(0.0, (n - 1) as f64);
+                        q.v[i] -= period.v[i] * (cell - middle);
+                    }
+                }
+                shape.distance(q)
+            }
+            SdfShape::Twist { shape, rate } => {
+                // undo the turn at this height
+                let (sin, cos) = f64::to_radians(-rate * p.y()).sin_cos();
+                let q = Point3::new(cos * p.x() + sin * p.z(), p.y(), -sin * p.x() + cos * p.z());
+                shape.distance(q)
+            }
+        }
+    }
+
+    // a box the surface never leaves
+    pub fn bounds(&self) -> AABB {
+        match self {
+            SdfShape::Sphere { center, radius } => around(*center, Vec3::new(*radius, *radius, *radius)),
+            SdfShape::Box { center, half } | SdfShape::RoundBox { center, half, .. } => around(*center, *half),
+            SdfShape::Capsule { a, b, radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                let mut bounds = AABB::new(*a - r, *a + r);
+                bounds.join(&AABB::new(*b - r, *b + r));
+                bounds
+            }
+            SdfShape::Torus { center, major, minor } => around(*center, Vec3::new(major + minor, *minor, major + minor)),
+            SdfShape::SmoothUnion { left, right, k } => {
+                // the blend bulges out by at most k / 4 between the two
+                let mut bounds = left.bounds();
+                bounds.join(&right.bounds());
+                let grow = Vec3::new(k / 4.0, k / 4.0, k / 4.0);
+                AABB::new(bounds.min - grow, bounds.max + grow)
+            }
+            // smooth subtraction and intersection only ever take away
+            SdfShape::SmoothSubtract { left, .. } => left.bounds(),
+            SdfShape::SmoothIntersect { left, right, .. } => {
+                let (l, r) = (left.bounds(), right.bounds());
+                AABB::new(
+                    Point3 { v: [0, 1, 2].map(|i| l.min.v[i].max(r.min.v[i])) },
+                    Point3 { v: [0, 1, 2].map(|i| l.max.v[i].min(r.max.v[i])) },
+                )
+            }
+            SdfShape::Repeat { shape, period, count } => {
+                let inner = shape.bounds();
+                let spread = Vec3 { v: [0, 1, 2].map(|i| if count[i] > 1 { period.v[i] * (count[i] - 1) as f64 / 2.0 } else { 0.0 }) };
+                AABB::new(inner.min - spread, inner.max + spread)
+            }
+            SdfShape::Twist { shape, .. } => {
+                // any turn around y stays inside the cylinder through the furthest corner
+                let inner = shape.bounds();
+                let r = twist_radius(&inner);
+                AABB::new(Point3::new(-r, inner.min.y(), -r), Point3::new(r, inner.max.y(), r))
+            }
+        }
+    }
+
+    // How much faster than the true distance the function can change. Sphere tracing divides its
+    // steps by this so that twisted shapes, which stretch space, are not stepped through.
+    pub fn lipschitz(&self) -> f64 {
+        match self {
+            SdfShape::SmoothUnion { left, right, .. }
+            | SdfShape::SmoothSubtract { left, right, .. }
+            | SdfShape::SmoothIntersect { left, right, .. } => left.lipschitz().max(right.lipschitz()),
+            SdfShape::Repeat { shape, .. } => shape.lipschitz(),
+            SdfShape::Twist { shape, rate } => {
+                let stretch = f64::to_radians(*rate) * twist_radius(&shape.bounds());
+                shape.lipschitz() * f64::sqrt(1.0 + stretch * stretch)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+fn around(center: Point3, half: Vec3) -> AABB {
+    AABB::new(center - half, center + half)
+}
+
+fn box_distance(p: Vec3, half: Vec3) -> f64 {
+    let q = Vec3 { v: [0, 1, 2].map(|i| p.v[i].abs() - half.v[i]) };
+    let outside = Vec3 { v: q.v.map(|x| x.max(0.0)) };
+    outside.length() + q.x().max(q.y()).max(q.z()).min(0.0)
+}
+
+// distance from the y axis to the furthest corner of bounds
+fn twist_radius(bounds: &AABB) -> f64 {
+    let x = bounds.min.x().abs().max(bounds.max.x().abs());
+    let z = bounds.min.z().abs().max(bounds.max.z().abs());
+    f64::sqrt(x * x + z * z)
+}
+
+// polynomial smooth minimum, never more than k / 4 below the real minimum
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+// An SDF shape as a primitive. u and v come from the direction of the normal, like a sphere
+// around the shape, since distance fields have no parameterisation of their own.
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    shape: SdfShape,
+    lipschitz: f64,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Sdf {
+    pub fn new(shape: SdfShape, material: MaterialId) -> Self {
+        let bounds = shape.bounds();
+        // a little slack so the march starts outside the surface
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        let bounds = AABB::new(bounds.min - pad, bounds.max + pad);
+        Sdf { lipschitz: shape.lipschitz(), shape, material, bounds }
+    }
+
+    pub fn shape(&self) -> &SdfShape {
+        &self.shape
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        let (enter, exit) = self.bounds.interval(ray)?;
+        let (start, end) = (enter.max(t_min), exit.min(t_max));
+        if start > end {
+            return None;
+        }
+
+        // Step by the distance until the sign flips, then bisect for the crossing. Rays that
+        // start inside (refraction) march with the same steps and look for the way out.
+        let speed = ray.direction().length() * self.lipschitz;
+        let min_step = MIN_STEP / ray.direction().length();
+        let mut t = start;
+        let mut d = self.shape.distance(ray.ray_at(t));
+        let inside = d < 0.0;
+        let mut steps = 0;
+        loop {
+            let prev = t;
+            t += (d.abs() / speed).max(min_step);
+            if t > end || steps == MAX_STEPS {
+                return None;
+            }
+            d = self.shape.distance(ray.ray_at(t));
+            steps += 1;
+            if (d < 0.0) != inside {
+                t = self.bisect(ray, prev, t, inside);
+                break;
+            }
+        }
+
+        let point = ray.ray_at(t);
+        let normal = self.normal(point);
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.point = point;
+        return_record.object_point = point;
+        return_record.material = self.material;
+        (return_record.u, return_record.v) = Sphere::uv(normal);
+        (return_record.tangent, return_record.bitangent) = Vec3::basis(normal);
+        return_record.calculate_normal(ray, normal);
+        Some(return_record)
+    }
+
+    // t where the distance changes sign between a and b
+    fn bisect(&self, ray: &Ray, mut a: f64, mut b: f64, inside: bool) -> f64 {
+        for _ in 0..32 {
+            let mid = 0.5 * (a + b);
+            if (self.shape.distance(ray.ray_at(mid)) < 0.0) == inside {
+                a = mid;
+            } else {
+                b = mid;
+            }
+        }
+        b
+    }
+
+    // the gradient of the distance, by central differences
+    pub fn normal(&self, p: Point3) -> Vec3 {
+        let h = GRADIENT_STEP;
+        let gradient = Vec3 {
+            v: [0, 1, 2].map(|i| {
+                let mut offset = Vec3::default();
+                offset.v[i] = h;
+                self.shape.distance(p + offset) - self.shape.distance(p - offset)
+            }),
+        };
+        if gradient.length() > 0.0 { gradient.unit() } else { Vec3::new(0.0, 1.0, 0.0) }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+}
+
+impl Hittable for Sdf {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Sdf::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Point3 {
+        Sdf::centroid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(shape: SdfShape, ray: &Ray) -> Option<Record> {
+        Sdf::new(shape, MaterialId::EMPTY).ray_hit(ray, 0.001, f64::INFINITY)
+    }
+
+    fn assert_near(a: Vec3, b: Vec3, tolerance: f64) {
+        assert!((a - b).length() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn sphere_matches_the_analytic_hit() {
+        let sphere = SdfShape::Sphere { center: Point3::new(0.0, 0.0, -3.0), radius: 1.0 };
+        let ray = Ray::new(Point3::default(), Vec3::new(0.15, 0.1, -1.0));
+        let analytic = Sphere::new(Point3::new(0.0, 0.0, -3.0), 1.0, MaterialId::EMPTY)
+            .ray_hit(&ray, 0.001, f64::INFINITY)
+            .expect("ray should hit the analytic sphere");
+        let traced = hit(sphere, &ray).expect("ray should hit the distance field sphere");
+        assert!((traced.t - analytic.t).abs() < 1e-6, "t = {}, analytic t = {}", traced.t, analytic.t);
+        assert_near(traced.normal, analytic.normal, 1e-4);
+    }
+
+    #[test]
+    fn box_face_is_hit_at_its_distance() {
+        let cube = SdfShape::Box { center: Point3::new(0.0, 0.0, -5.0), half: Vec3::new(1.0, 2.0, 0.5) };
+        assert!((cube.distance(Point3::new(0.0, 0.0, -2.0)) - 2.5).abs() < 1e-12);
+        assert!((cube.distance(Point3::new(2.0, 3.0, -5.0)) - f64::sqrt(2.0)).abs() < 1e-12);
+        assert!((cube.distance(Point3::new(0.0, 0.0, -5.0)) + 0.5).abs() < 1e-12);
+
+        let ray = Ray::new(Point3::new(0.5, 1.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+        let traced = hit(cube, &ray).expect("ray should hit the front face");
+        // t is measured along the unnormalised direction
+        assert!((traced.t - 2.25).abs() < 1e-6, "t = {}", traced.t);
+        assert_near(traced.normal, Vec3::new(0.0, 0.0, 1.0), 1e-4);
+    }
+
+    #[test]
+    fn ray_passing_close_by_misses() {
+        let sphere = SdfShape::Sphere { center: Point3::new(0.0, 0.0, -3.0), radius: 1.0 };
+        let ray = Ray::new(Point3::new(1.0 + 1e-3, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit(sphere.clone(), &ray).is_none());
+        // and one just inside the silhouette still hits
+        let ray = Ray::new(Point3::new(1.0 - 1e-3, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit(sphere, &ray).is_some());
+    }
+
+    #[test]
+    fn ray_from_inside_finds_the_way_out() {
+        let sphere = SdfShape::Sphere { center: Point3::default(), radius: 2.0 };
+        let ray = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0));
+        let traced = hit(sphere, &ray).expect("ray should leave the sphere");
+        assert!((traced.t - 2.0).abs() < 1e-6, "t = {}", traced.t);
+        assert!(!traced.outside_face);
+    }
+
+    #[test]
+    fn lipschitz_bound_grows_with_the_twist() {
+        let column = SdfShape::Box { center: Point3::default(), half: Vec3::new(1.0, 2.0, 1.0) };
+        assert_eq!(column.lipschitz(), 1.0);
+        let twisted = |rate: f64| SdfShape::Twist { shape: Box::new(column.clone()), rate };
+        // the corners are sqrt(2) from the axis, turning by rate degrees per unit of height
+        let stretch = f64::to_radians(90.0) * f64::sqrt(2.0);
+        assert!((twisted(90.0).lipschitz() - f64::sqrt(1.0 + stretch * stretch)).abs() < 1e-12);
+        assert!(twisted(180.0).lipschitz() > twisted(90.0).lipschitz());
+        let blend = SdfShape::SmoothUnion { left: Box::new(twisted(90.0)), right: Box::new(column.clone()), k: 0.1 };
+        assert_eq!(blend.lipschitz(), twisted(90.0).lipschitz());
+    }
+
+    #[test]
+    fn twisted_box_is_not_stepped_through() {
+        // a thin twisted slab, a ray along its width has to stop at the surface rather than skip it
+        let slab = SdfShape::Box { center: Point3::default(), half: Vec3::new(2.0, 2.0, 0.05) };
+        let twisted = SdfShape::Twist { shape: Box::new(slab), rate: 45.0 };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let traced = hit(twisted.clone(), &ray).expect("ray should hit the slab");
+        assert!(twisted.distance(traced.point).abs() < 1e-6, "distance {}", twisted.distance(traced.point));
+        assert!((traced.t - 4.95).abs() < 1e-6, "t = {}", traced.t);
+    }
+}
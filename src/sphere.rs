@@ -1,15 +1,17 @@
-use crate::{hittable::{ Record, Hittable}, vec3::{Vec3, Point3}, material::Material, aabb::AABB};
+use std::f64::consts::PI;
 
-#[derive(Debug, Clone, Copy)]
+use crate::{hittable::{ Record, Hittable}, vec3::{Vec3, Point3}, material::MaterialId, aabb::AABB};
+
+#[derive(Debug, Clone)]
 pub struct Sphere {
     center: Vec3,
     radius: f64,
-    material: Material,
+    material: MaterialId,
     pub bounds: AABB
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius:f64, material: Material) -> Sphere {
+    pub fn new(center: Vec3, radius:f64, material: MaterialId) -> Sphere {
         let min : Point3 = Point3::new(center.x() - radius, center.y() - radius, center.z() - radius);
         let max : Point3 = Point3::new(center.x() + radius, center.y() + radius, center.z() + radius);
 
@@ -47,58 +49,110 @@ impl Sphere {
             return_record.material = self.material;
             let normal = (return_record.point - self.center)/self.radius;
             return_record.calculate_normal(ray, normal);
-            return Some(return_record);
+            (return_record.u, return_record.v) = Sphere::uv(normal);
+            (return_record.tangent, return_record.bitangent) = Sphere::tangent_frame(normal);
+            return_record.object_point = return_record.point - self.center;
+            Some(return_record)
         }
     }
-    fn bounds(&self) -> &AABB {
-        &self.bounds
-    }
 
     pub fn centroid(&self) -> Vec3 {
         self.center
     }
+
+    // spherical coordinates of a point on the unit sphere, u goes around the y axis and v from the bottom (-y) to the top
+    pub fn uv(p: Vec3) -> (f64, f64) {
+        let theta = f64::acos((-p.y()).clamp(-1.0, 1.0));
+        let phi = f64::atan2(-p.z(), p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // directions of increasing u (around the y axis) and v (towards +y) at a point on the unit sphere
+    fn tangent_frame(n: Vec3) -> (Vec3, Vec3) {
+        let tangent = Vec3::new(n.z(), 0.0, -n.x());
+        // u is undefined at the poles
+        if tangent.length() < 1e-9 {
+            return Vec3::basis(n);
+        }
+        let tangent = tangent.unit();
+        (tangent, Vec3::cross(n, tangent))
+    }
 }
 
 impl Hittable for Sphere {
     fn ray_hit(&self, ray: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Sphere::ray_hit(self, ray, t_min, t_max)
+    }
 
-        if !self.bounds.hit(ray) {
-            return None;
-        }
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
 
-        let oc: Vec3 = ray.origin() - self.center;
-        let a = Vec3::dot(ray.direction(), ray.direction());
-        let b = Vec3::dot(2.0 * ray.direction(), oc);
-        let c = Vec3::dot(oc, oc) - (self.radius * self.radius);
-        let disc = (b*b)-(4.0*a*c);
+    fn centroid(&self) -> Vec3 {
+        self.center
+    }
+}
 
-        if disc < 0.0 {
-            None
-        } else {
-            let mut t: f64 = (-b - f64::sqrt(disc))/(2.0 * a);
-            if (t_min > t) || (t > t_max) {
-                t = (-b + f64::sqrt(disc))/(2.0 * a);
-                if (t_min > t) || (t > t_max) {
-                    return None
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
 
-            let mut return_record: Record = Record::new();
-            
-            return_record.t = t;
-            return_record.point= ray.ray_at(t);
-            return_record.material = self.material;
-            let normal = (return_record.point - self.center)/self.radius;
-            return_record.calculate_normal(ray, normal);
-            return Some(return_record);
+    fn assert_uv((u, v): (f64, f64), expected: (f64, f64)) {
+        assert!((u - expected.0).abs() < 1e-9 && (v - expected.1).abs() < 1e-9, "uv ({}, {}), expected {:?}", u, v, expected);
+    }
+
+    #[test]
+    fn uv_runs_around_y_and_up_from_the_bottom() {
+        assert_uv(Sphere::uv(Vec3::new(1.0, 0.0, 0.0)), (0.5, 0.5));
+        assert_uv(Sphere::uv(Vec3::new(0.0, 0.0, 1.0)), (0.25, 0.5));
+        assert_uv(Sphere::uv(Vec3::new(0.0, 0.0, -1.0)), (0.75, 0.5));
+        let p = Vec3::new(1.0, 1.0, 0.0).unit();
+        assert_uv(Sphere::uv(p), (0.5, 0.75));
+    }
+
+    #[test]
+    fn poles_are_the_top_and_bottom_rows() {
+        let (u, v) = Sphere::uv(Vec3::new(0.0, 1.0, 0.0));
+        assert!((v - 1.0).abs() < 1e-12 && (0.0..=1.0).contains(&u));
+        let (u, v) = Sphere::uv(Vec3::new(0.0, -1.0, 0.0));
+        assert!(v.abs() < 1e-12 && (0.0..=1.0).contains(&u));
+        // rounding can leave a pole point a little longer than 1
+        let (_, v) = Sphere::uv(Vec3::new(0.0, 1.0 + 1e-15, 0.0));
+        assert!(!v.is_nan());
+
+        // u has no direction at the pole, the hit still gets a usable frame
+        let sphere = Sphere::new(Point3::default(), 2.0, MaterialId::EMPTY);
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = sphere.ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the pole");
+        assert!((hit.v - 1.0).abs() < 1e-12);
+        for v in [hit.tangent, hit.bitangent] {
+            assert!((v.length() - 1.0).abs() < 1e-9 && Vec3::dot(v, hit.normal).abs() < 1e-9, "{:?}", v);
         }
     }
 
-    fn bounds(&self) -> &AABB {
-        &self.bounds
+    #[test]
+    fn seam_is_on_the_negative_x_side() {
+        // u wraps from 1 back to 0 across -x, and nowhere else
+        let (before, _) = Sphere::uv(Vec3::new(-1.0, 0.0, -1e-9).unit());
+        let (after, _) = Sphere::uv(Vec3::new(-1.0, 0.0, 1e-9).unit());
+        assert!(before > 0.999 && after < 0.001, "u goes from {} to {}", before, after);
+        let mut last = Sphere::uv(Vec3::new(-1.0, 0.0, 1e-9)).0;
+        for i in 1..360 {
+            let angle = f64::to_radians(i as f64);
+            // counter-clockwise seen from +y, starting at -x
+            let u = Sphere::uv(Vec3::new(-angle.cos(), 0.0, angle.sin())).0;
+            assert!(u > last, "u fell from {} to {} at {} degrees", last, u, i);
+            last = u;
+        }
     }
 
-    fn centroid(&self) -> Vec3 {
-        self.center
+    #[test]
+    fn hits_from_inside_use_the_outward_uv() {
+        let sphere = Sphere::new(Point3::new(1.0, 1.0, 1.0), 2.0, MaterialId::EMPTY);
+        let ray = Ray::new(Point3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = sphere.ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the far side");
+        assert!((hit.t - 2.0).abs() < 1e-9 && !hit.outside_face);
+        assert_uv((hit.u, hit.v), (0.5, 0.5));
     }
 }
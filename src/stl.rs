@@ -0,0 +1,203 @@
+// Reader for STL files as CAD programs export them, binary or ASCII. STL stores every triangle
+// with its own three corners, so corners at the same position are welded back into shared
+// vertices; the facet normals are ignored, TriMesh works out smooth ones with a crease angle.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{mesh::MeshError, vec3::{Point3, Vec3}};
+
+#[derive(Debug, Clone, Default)]
+pub struct Stl {
+    pub positions: Vec<Point3>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl Stl {
+    pub fn load(path: impl AsRef<Path>) -> Result<Stl, MeshError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => MeshError::MissingFile(path.to_path_buf()),
+            _ => MeshError::Parse(path.to_path_buf(), e.to_string()),
+        })?;
+        Stl::parse(&data).map_err(|msg| MeshError::Parse(path.to_path_buf(), msg))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Stl, String> {
+        let corners = if is_binary(data) { binary(data)? } else { ascii(data)? };
+        Ok(Stl::welded(&corners))
+    }
+
+    // shares corners with exactly the same position, and drops the triangles that collapse
+    fn welded(corners: &[Point3]) -> Stl {
+        let mut stl = Stl::default();
+        let mut index: HashMap<[u64; 3], usize> = HashMap::new();
+        for triangle in corners.chunks_exact(3) {
+            let face = [0, 1, 2].map(|k| {
+                let p = triangle[k];
+                *index.entry([p.x().to_bits(), p.y().to_bits(), p.z().to_bits()]).or_insert_with(|| {
+                    stl.positions.push(p);
+                    stl.positions.len() - 1
+                })
+            });
+            if face[0] != face[1] && face[1] != face[2] && face[2] != face[0] {
+                stl.faces.push(face);
+            }
+        }
+        stl
+    }
+}
+
+// Binary files are an 80 byte header, a triangle count and 50 bytes per triangle. ASCII files
+// start with "solid", but so do the headers of some binary ones, so the size decides.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    data.len() == 84 + 50 * count || !data.starts_with(b"solid")
+}
+
+// the corners of every triangle, three at a time
+fn binary(data: &[u8]) -> Result<Vec<Point3>, String> {
+    if data.len() < 84 {
+        return Err("file is too short for a binary STL header".to_string());
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    if data.len() < 84 + 50 * count {
+        return Err(format!("header promises {count} triangles but the file ends early"));
+    }
+    let float = |at: usize| f32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let mut corners = Vec::with_capacity(3 * count);
+    for i in 0..count {
+        // a normal, three corners and two bytes of attributes
+        let start = 84 + 50 * i + 12;
+        for k in 0..3 {
+            let at = start + 12 * k;
+            corners.push(Vec3::newf32(float(at), float(at + 4), float(at + 8)));
+        }
+    }
+    Ok(corners)
+}
+
+fn ascii(data: &[u8]) -> Result<Vec<Point3>, String> {
+    // not text after all, most likely a binary file that is cut short
+    let Ok(text) = std::str::from_utf8(data) else {
+        return binary(data);
+    };
+    let mut tokens = text.split_ascii_whitespace();
+    if tokens.next() != Some("solid") {
+        return Err("not an STL file".to_string());
+    }
+    let mut corners = Vec::new();
+    let mut in_loop = 0;
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut coordinate = || -> Result<f64, String> {
+                    let token = tokens.next().ok_or("file ends in the middle of a vertex")?;
+                    token.parse().map_err(|_| format!("'{token}' is not a number"))
+                };
+                corners.push(Point3::new(coordinate()?, coordinate()?, coordinate()?));
+                in_loop += 1;
+            }
+            "endloop" => {
+                if in_loop != 3 {
+                    return Err(format!("facet with {in_loop} vertices, STL facets are triangles"));
+                }
+                in_loop = 0;
+            }
+            // the facet normal and the names after solid and endsolid are skipped with the keywords
+            _ => {}
+        }
+    }
+    if in_loop != 0 {
+        return Err("file ends in the middle of a facet".to_string());
+    }
+    Ok(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two triangles of the unit square, sharing the diagonal from (1,0,0) to (0,1,0)
+    const SQUARE: [[f32; 9]; 2] = [
+        [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+    ];
+
+    fn binary_file(header: &[u8], triangles: &[[f32; 9]]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, b' ');
+        data.extend((triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            data.extend([0u8; 12]);
+            for x in triangle {
+                data.extend(x.to_le_bytes());
+            }
+            data.extend([0u8; 2]);
+        }
+        data
+    }
+
+    fn check_square(stl: &Stl) {
+        // the shared corners are welded
+        assert_eq!(stl.positions.len(), 4);
+        assert_eq!(stl.faces, vec![[0, 1, 2], [1, 3, 2]]);
+        assert_eq!(stl.positions[3].v, [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn ascii() {
+        let data = "solid square\n\
+            facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 0 1 0\n endloop\nendfacet\n\
+            facet normal 0 0 1\n outer loop\n  vertex 1 0 0\n  vertex 1 1 0\n  vertex 0 1 0\n endloop\nendfacet\n\
+            endsolid square\n";
+        check_square(&Stl::parse(data.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary() {
+        check_square(&Stl::parse(&binary_file(b"exported by some CAD program", &SQUARE)).unwrap());
+    }
+
+    #[test]
+    fn binary_with_solid_header() {
+        // plenty of exporters write "solid" into the binary header, the size gives them away
+        check_square(&Stl::parse(&binary_file(b"solid square", &SQUARE)).unwrap());
+    }
+
+    #[test]
+    fn drops_collapsed_triangles() {
+        let degenerate = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let stl = Stl::parse(&binary_file(b"", &[SQUARE[0], degenerate])).unwrap();
+        assert_eq!(stl.faces, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let mut data = binary_file(b"", &SQUARE);
+        data.truncate(data.len() - 10);
+        assert_eq!(Stl::parse(&data).unwrap_err(), "header promises 2 triangles but the file ends early");
+    }
+
+    #[test]
+    fn truncated_ascii() {
+        let data = "solid cut\nfacet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0";
+        assert_eq!(Stl::parse(data.as_bytes()).unwrap_err(), "file ends in the middle of a vertex");
+        let data = "solid cut\nfacet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n";
+        assert_eq!(Stl::parse(data.as_bytes()).unwrap_err(), "file ends in the middle of a facet");
+    }
+
+    #[test]
+    fn facet_with_four_vertices() {
+        let data = "solid quad\nfacet normal 0 0 1\n outer loop\n\
+            vertex 0 0 0\n vertex 1 0 0\n vertex 1 1 0\n vertex 0 1 0\n endloop\nendfacet\nendsolid quad\n";
+        assert_eq!(Stl::parse(data.as_bytes()).unwrap_err(), "facet with 4 vertices, STL facets are triangles");
+    }
+
+    #[test]
+    fn not_an_stl() {
+        assert_eq!(Stl::parse(b"ply\nformat ascii 1.0\n").unwrap_err(), "not an STL file");
+    }
+}
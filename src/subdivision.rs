@@ -0,0 +1,393 @@
+// Subdivision surfaces for loaded meshes. Each level splits every face and moves the vertices
+// towards a smooth limit surface: Loop's scheme for pure triangle meshes, Catmull-Clark for
+// quads and other polygons (one Catmull-Clark level turns any mesh into quads).
+//
+// Boundary edges, edges shared by more than two faces and crease edges are kept sharp: their
+// new points follow the curve along the edge only, and vertices where three or more sharp edges
+// meet stay where they are, as do the corners of open patches. UVs are face varying and
+// interpolated linearly, so seams survive.
+
+use std::collections::HashMap;
+
+use crate::vec3::{Point3, Vec3};
+
+// A polygon mesh with shared positions and optional uvs per face corner
+#[derive(Debug, Clone, Default)]
+pub struct PolyMesh {
+    pub positions: Vec<Point3>,
+    pub faces: Vec<Vec<usize>>,
+    // same layout as faces
+    pub uvs: Option<Vec<Vec<(f64, f64)>>>,
+    // edges (smaller index first) that stay sharp besides the boundaries
+    pub creases: Vec<(usize, usize)>,
+}
+
+struct Edge {
+    faces: Vec<usize>,
+    sharp: bool,
+    // index of the point that splits the edge in the next level
+    point: usize,
+}
+
+fn key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn lerp(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1))
+}
+
+impl PolyMesh {
+    // marks the edges between faces that meet at more than crease_angle degrees as creases
+    pub fn mark_creases(&mut self, crease_angle: f64) {
+        let normals: Vec<Vec3> = self.faces.iter().map(|f| self.face_normal(f)).collect();
+        let limit = f64::to_radians(crease_angle).cos();
+        let mut shared: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            for k in 0..face.len() {
+                shared.entry(key(face[k], face[(k + 1) % face.len()])).or_default().push(i);
+            }
+        }
+        for (edge, faces) in shared {
+            if let [a, b] = faces[..] {
+                if Vec3::dot(normals[a], normals[b]) < limit {
+                    self.creases.push(edge);
+                }
+            }
+        }
+    }
+
+    // Newell's normal, which also works for polygons that are not quite flat
+    fn face_normal(&self, face: &[usize]) -> Vec3 {
+        let mut n = Vec3::default();
+        for k in 0..face.len() {
+            let (a, b) = (self.positions[face[k]], self.positions[face[(k + 1) % face.len()]]);
+            n = n + Vec3::cross(a, b);
+        }
+        if n.length() > 0.0 { n.unit() } else { n }
+    }
+
+    pub fn is_triangles(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    // levels of Loop subdivision for triangle meshes, Catmull-Clark otherwise
+    pub fn subdivide(mut self, levels: u32) -> PolyMesh {
+        let triangles = self.is_triangles();
+        for _ in 0..levels {
+            self = if triangles { self.loop_level() } else { self.catmull_clark_level() };
+        }
+        self
+    }
+
+    // the edges with their faces; edge points are numbered from first_point on
+    fn edges(&self, first_point: usize) -> HashMap<(usize, usize), Edge> {
+        let mut edges: HashMap<(usize, usize), Edge> = HashMap::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            for k in 0..face.len() {
+                let next = first_point + edges.len();
+                edges.entry(key(face[k], face[(k + 1) % face.len()]))
+                    .or_insert(Edge { faces: vec![], sharp: false, point: next })
+                    .faces.push(i);
+            }
+        }
+        for edge in edges.values_mut() {
+            edge.sharp = edge.faces.len() != 2;
+        }
+        for crease in &self.creases {
+            if let Some(edge) = edges.get_mut(crease) {
+                edge.sharp = true;
+            }
+        }
+        edges
+    }
+
+    // New position of an original vertex from its neighbours, the smooth rule is left to the scheme.
+    // Vertices on exactly two sharp edges follow the crease curve, more than two make a corner, and
+    // so does a boundary vertex with a single face, the corner of an open patch.
+    fn vertex_point(&self, v: usize, sharp: &[usize], faces: usize, smooth: impl FnOnce() -> Point3) -> Point3 {
+        match sharp {
+            [a, b] if faces > 1 => 0.75 * self.positions[v] + 0.125 * (self.positions[*a] + self.positions[*b]),
+            [] | [_] => smooth(),
+            _ => self.positions[v],
+        }
+    }
+
+    // number of faces around every vertex
+    fn face_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.positions.len()];
+        for f in &self.faces {
+            for v in f {
+                counts[*v] += 1;
+            }
+        }
+        counts
+    }
+
+    // neighbours of every vertex, and the ones across sharp edges
+    fn neighbours(&self, edges: &HashMap<(usize, usize), Edge>) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let mut all = vec![vec![]; self.positions.len()];
+        let mut sharp = vec![vec![]; self.positions.len()];
+        for (&(a, b), edge) in edges {
+            all[a].push(b);
+            all[b].push(a);
+            if edge.sharp {
+                sharp[a].push(b);
+                sharp[b].push(a);
+            }
+        }
+        (all, sharp)
+    }
+
+    // the creases of the next level, each sharp edge splits into two
+    fn split_creases(edges: &HashMap<(usize, usize), Edge>) -> Vec<(usize, usize)> {
+        edges.iter()
+            .filter(|(_, e)| e.sharp && e.faces.len() == 2)
+            .flat_map(|(&(a, b), e)| [key(a, e.point), key(e.point, b)])
+            .collect()
+    }
+
+    fn loop_level(&self) -> PolyMesh {
+        let n = self.positions.len();
+        let edges = self.edges(n);
+        let (neighbours, sharp) = self.neighbours(&edges);
+        let face_counts = self.face_counts();
+        let mut positions = vec![Vec3::default(); n + edges.len()];
+
+        for v in 0..n {
+            if neighbours[v].is_empty() {
+                positions[v] = self.positions[v];
+                continue;
+            }
+            positions[v] = self.vertex_point(v, &sharp[v], face_counts[v], || {
+                let k = neighbours[v].len() as f64;
+                let beta = if neighbours[v].len() == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * k) };
+                let sum = neighbours[v].iter().fold(Vec3::default(), |s, w| s + self.positions[*w]);
+                (1.0 - k * beta) * self.positions[v] + beta * sum
+            });
+        }
+        for (&(a, b), edge) in &edges {
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            positions[edge.point] = if edge.sharp {
+                0.5 * (pa + pb)
+            } else {
+                // the corners opposite the edge in its two triangles
+                let opposite = edge.faces.iter().fold(Vec3::default(), |s, f| {
+                    let c = self.faces[*f].iter().find(|v| **v != a && **v != b).unwrap();
+                    s + self.positions[*c]
+                });
+                0.375 * (pa + pb) + 0.125 * opposite
+            };
+        }
+
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        let mut uvs = self.uvs.as_ref().map(|_| Vec::with_capacity(self.faces.len() * 4));
+        for (i, f) in self.faces.iter().enumerate() {
+            let mid = |x: usize, y: usize| edges[&key(f[x], f[y])].point;
+            let (ab, bc, ca) = (mid(0, 1), mid(1, 2), mid(2, 0));
+            faces.extend([vec![f[0], ab, ca], vec![ab, f[1], bc], vec![ca, bc, f[2]], vec![ab, bc, ca]]);
+            if let (Some(out), Some(uv)) = (&mut uvs, &self.uvs) {
+                let uv = &uv[i];
+                let (uab, ubc, uca) = (lerp(uv[0], uv[1]), lerp(uv[1], uv[2]), lerp(uv[2], uv[0]));
+                out.extend([vec![uv[0], uab, uca], vec![uab, uv[1], ubc], vec![uca, ubc, uv[2]], vec![uab, ubc, uca]]);
+            }
+        }
+        PolyMesh { positions, faces, uvs, creases: PolyMesh::split_creases(&edges) }
+    }
+
+    fn catmull_clark_level(&self) -> PolyMesh {
+        let n = self.positions.len();
+        let edges = self.edges(n);
+        let (neighbours, sharp) = self.neighbours(&edges);
+        let first_face_point = n + edges.len();
+        let mut positions = vec![Vec3::default(); first_face_point + self.faces.len()];
+
+        let face_points: Vec<Point3> = self.faces.iter()
+            .map(|f| f.iter().fold(Vec3::default(), |s, v| s + self.positions[*v]) / f.len() as f64)
+            .collect();
+        let mut vertex_faces = vec![vec![]; n];
+        for (i, f) in self.faces.iter().enumerate() {
+            for v in f {
+                vertex_faces[*v].push(i);
+            }
+        }
+
+        for v in 0..n {
+            if neighbours[v].is_empty() {
+                positions[v] = self.positions[v];
+                continue;
+            }
+            positions[v] = self.vertex_point(v, &sharp[v], vertex_faces[v].len(), || {
+                // (Q + 2R + (k - 3) P) / k with Q the average face point and R the average edge midpoint
+                let k = neighbours[v].len() as f64;
+                let q = vertex_faces[v].iter().fold(Vec3::default(), |s, f| s + face_points[*f]) / vertex_faces[v].len() as f64;
+                let r = neighbours[v].iter().fold(Vec3::default(), |s, w| s + 0.5 * (self.positions[v] + self.positions[*w])) / k;
+                (q + 2.0 * r + (k - 3.0) * self.positions[v]) / k
+            });
+        }
+        for (&(a, b), edge) in &edges {
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            positions[edge.point] = if edge.sharp {
+                0.5 * (pa + pb)
+            } else {
+                (pa + pb + face_points[edge.faces[0]] + face_points[edge.faces[1]]) / 4.0
+            };
+        }
+        positions[first_face_point..].copy_from_slice(&face_points);
+
+        // a quad for every corner of every face, keeping the winding
+        let mut faces = Vec::new();
+        let mut uvs = self.uvs.as_ref().map(|_| Vec::new());
+        for (i, f) in self.faces.iter().enumerate() {
+            let k = f.len();
+            let center = first_face_point + i;
+            let uv = self.uvs.as_ref().map(|uvs| &uvs[i]);
+            let uv_center = uv.map(|uv| {
+                let (su, sv) = uv.iter().fold((0.0, 0.0), |(su, sv), (u, v)| (su + u, sv + v));
+                (su / k as f64, sv / k as f64)
+            });
+            for c in 0..k {
+                let (prev, next) = ((c + k - 1) % k, (c + 1) % k);
+                let to_next = edges[&key(f[c], f[next])].point;
+                let from_prev = edges[&key(f[prev], f[c])].point;
+                faces.push(vec![f[c], to_next, center, from_prev]);
+                if let (Some(out), Some(uv), Some(uv_center)) = (&mut uvs, uv, uv_center) {
+                    out.push(vec![uv[c], lerp(uv[c], uv[next]), uv_center, lerp(uv[prev], uv[c])]);
+                }
+            }
+        }
+        PolyMesh { positions, faces, uvs, creases: PolyMesh::split_creases(&edges) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> PolyMesh {
+        PolyMesh {
+            positions: vec![Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, -1.0, -1.0), Vec3::new(-1.0, 1.0, -1.0), Vec3::new(-1.0, -1.0, 1.0)],
+            faces: vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]],
+            ..PolyMesh::default()
+        }
+    }
+
+    // corners of [-1, 1]^3, numbered by x + 2y + 4z
+    fn cube() -> PolyMesh {
+        let positions = (0..8).map(|i| Vec3::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        )).collect();
+        let faces = vec![vec![0, 4, 6, 2], vec![1, 3, 7, 5], vec![0, 1, 5, 4], vec![2, 6, 7, 3], vec![0, 2, 3, 1], vec![4, 5, 7, 6]];
+        PolyMesh { positions, faces, ..PolyMesh::default() }
+    }
+
+    fn triangulated(mesh: PolyMesh) -> PolyMesh {
+        let faces = mesh.faces.iter().flat_map(|f| [vec![f[0], f[1], f[2]], vec![f[0], f[2], f[3]]]).collect();
+        PolyMesh { faces, ..mesh }
+    }
+
+    fn assert_close(a: Point3, b: Point3) {
+        assert!((a - b).length() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    fn on_cube(p: Point3) -> bool {
+        let m = p.v.iter().fold(0.0f64, |m, x| m.max(x.abs()));
+        (m - 1.0).abs() < 1e-12
+    }
+
+    #[test]
+    fn loop_tetrahedron() {
+        let mesh = tetrahedron().subdivide(1);
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (4 + 6, 4 * 4));
+        assert!(mesh.is_triangles());
+        // valence 3: (1 - 3 * 3/16) p + 3/16 of the neighbours, which sum to -p
+        assert_close(mesh.positions[0], Vec3::new(0.25, 0.25, 0.25));
+        // 3/8 of the edge's ends and 1/8 of the opposite corners
+        assert!(mesh.positions[4..].iter().any(|p| (*p - Vec3::new(0.5, 0.0, 0.0)).length() < 1e-12));
+    }
+
+    #[test]
+    fn loop_cube() {
+        let mesh = triangulated(cube()).subdivide(1);
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (8 + 18, 12 * 4));
+        // smooth, the corners are pulled in
+        assert!(mesh.positions[..8].iter().all(|p| !on_cube(*p)));
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let mesh = cube().subdivide(1);
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (8 + 12 + 6, 6 * 4));
+        assert!(mesh.faces.iter().all(|f| f.len() == 4));
+        // (Q + 2R) / 3 with Q = (1/3, 1/3, 1/3) and R = (2/3, 2/3, 2/3)
+        assert_close(mesh.positions[7], Vec3::new(5.0, 5.0, 5.0) / 9.0);
+        // the face points are the face centres
+        assert_close(mesh.positions[20], Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn catmull_clark_tetrahedron() {
+        // subdivide would pick Loop for triangles
+        let mesh = tetrahedron().catmull_clark_level();
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (4 + 6 + 4, 4 * 3));
+        assert!(mesh.faces.iter().all(|f| f.len() == 4));
+    }
+
+    #[test]
+    fn creased_cube_keeps_its_shape() {
+        for mesh in [cube(), triangulated(cube())] {
+            let original = mesh.positions.clone();
+            let mut mesh = mesh;
+            mesh.mark_creases(30.0);
+            // the 12 cube edges, not the diagonals of the triangulated faces
+            assert_eq!(mesh.creases.len(), 12);
+            let mesh = mesh.subdivide(2);
+            // three creases meet at every corner
+            for (p, q) in mesh.positions.iter().zip(&original) {
+                assert_close(*p, *q);
+            }
+            // and the rest stays on the faces and edges
+            assert!(mesh.positions.iter().all(|p| on_cube(*p)));
+        }
+    }
+
+    #[test]
+    fn boundary_of_an_open_quad() {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let quad = PolyMesh {
+            positions: corners.iter().map(|(x, y)| Vec3::new(*x, *y, 0.0)).collect(),
+            faces: vec![vec![0, 1, 2, 3]],
+            uvs: Some(vec![corners.to_vec()]),
+            creases: vec![],
+        };
+        let mesh = quad.subdivide(1);
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (9, 4));
+        for (p, (x, y)) in mesh.positions.iter().zip(corners) {
+            assert_close(*p, Vec3::new(x, y, 0.0));
+        }
+        // boundary edges split at their midpoints, the face point in the middle
+        let mut rest: Vec<[f64; 3]> = mesh.positions[4..].iter().map(|p| p.v).collect();
+        rest.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(rest, [[0.0, 0.5, 0.0], [0.5, 0.0, 0.0], [0.5, 0.5, 0.0], [0.5, 1.0, 0.0], [1.0, 0.5, 0.0]]);
+        // uvs follow: corner, edge midpoint, centre, edge midpoint
+        assert_eq!(mesh.uvs.unwrap()[0], vec![(0.0, 0.0), (0.5, 0.0), (0.5, 0.5), (0.0, 0.5)]);
+    }
+
+    #[test]
+    fn loop_uvs_and_open_triangle() {
+        let triangle = PolyMesh {
+            positions: vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)],
+            faces: vec![vec![0, 1, 2]],
+            uvs: Some(vec![vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]]),
+            creases: vec![],
+        };
+        let mesh = triangle.subdivide(1);
+        assert_eq!((mesh.positions.len(), mesh.faces.len()), (6, 4));
+        assert_close(mesh.positions[1], Vec3::new(2.0, 0.0, 0.0));
+        let uvs = mesh.uvs.unwrap();
+        assert_eq!(uvs[0], vec![(0.0, 0.0), (0.5, 0.0), (0.0, 0.5)]);
+        assert_eq!(uvs[3], vec![(0.5, 0.0), (0.5, 0.5), (0.0, 0.5)]);
+    }
+}
@@ -0,0 +1,433 @@
+// Textures feed material parameters. Every parameter is looked up with the hit's
+// surface coordinates (u, v) and position, so constant values, bitmaps and
+// patterns can be used interchangeably.
+
+use std::{fmt, path::Path, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{color::Color, hittable::Record, noise, vec3::{Point3, WHITE}};
+
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Color),
+    Image(Arc<ImageTexture>),
+    // alternating squares in uv space, `scale` squares per unit
+    Checker { even: Color, odd: Color, scale: f64 },
+    // 3D patterns, they need no uvs
+    Procedural(Arc<Procedural>),
+    // the alpha channel of an image, as a grey value
+    Alpha(Arc<ImageTexture>),
+    // the colour of the mesh's vertices at the hit, white away from meshes that have them
+    VertexColor,
+}
+
+impl Texture {
+    pub fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.sample(u, v),
+            Texture::Checker { even, odd, scale } => {
+                let parity = (f64::floor(u * scale) + f64::floor(v * scale)) as i64;
+                if parity.rem_euclid(2) == 0 { *even } else { *odd }
+            }
+            Texture::Procedural(procedural) => procedural.value(p),
+            Texture::Alpha(image) => image.sample_alpha(u, v),
+            // only known from a hit record, see at
+            Texture::VertexColor => WHITE,
+        }
+    }
+
+    // Opacity maps use the alpha channel of images that have one and the grey value otherwise
+    pub fn into_opacity(self) -> Texture {
+        match self {
+            Texture::Image(image) if image.has_alpha() => Texture::Alpha(image),
+            texture => texture,
+        }
+    }
+
+    // single channel parameters (roughness, specularity) use the average of the three channels
+    pub fn scalar(&self, u: f64, v: f64, p: Point3) -> f64 {
+        let c = self.value(u, v, p);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+
+    // procedural textures pick the world or object space position of the hit
+    fn point(&self, record: &Record) -> Point3 {
+        match self {
+            Texture::Procedural(procedural) if procedural.space == TextureSpace::Object => record.object_point,
+            _ => record.point,
+        }
+    }
+
+    pub fn at(&self, record: &Record) -> Color {
+        match self {
+            Texture::VertexColor => record.color,
+            _ => self.value(record.u, record.v, self.point(record)),
+        }
+    }
+
+    pub fn scalar_at(&self, record: &Record) -> f64 {
+        let c = self.at(record);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+
+    // Change of the scalar value across one texel along u and v, for bump maps. Patterns count
+    // as 8 texels per feature, procedural ones step along the tangent and bitangent instead.
+    pub fn height_gradient(&self, record: &Record) -> (f64, f64) {
+        let d = match self {
+            Texture::Constant(_) | Texture::VertexColor => 1.0,
+            Texture::Image(image) | Texture::Alpha(image) => 1.0 / image.width.max(image.height) as f64,
+            Texture::Checker { scale, .. } => 1.0 / (8.0 * scale),
+            Texture::Procedural(procedural) => 1.0 / (8.0 * procedural.scale),
+        };
+        let (u, v, p) = (record.u, record.v, self.point(record));
+        let h = self.scalar(u, v, p);
+        let du = self.scalar(u + d, v, p + d * record.tangent) - h;
+        let dv = self.scalar(u, v + d, p + d * record.bitangent) - h;
+        (du, dv)
+    }
+}
+
+impl From<Color> for Texture {
+    fn from(color: Color) -> Self {
+        Texture::Constant(color)
+    }
+}
+
+impl From<f64> for Texture {
+    fn from(value: f64) -> Self {
+        Texture::Constant(Color::new(value, value, value))
+    }
+}
+
+impl From<ImageTexture> for Texture {
+    fn from(image: ImageTexture) -> Self {
+        Texture::Image(Arc::new(image))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureSpace {
+    #[default]
+    World,
+    // the hit position relative to the primitive, so the pattern moves with the object
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    // smooth gradient noise
+    Perlin,
+    // sum of octaves of noise
+    Turbulence,
+    // veins along x, distorted by turbulence
+    Marble,
+    // concentric rings around the y axis, distorted by noise
+    Wood,
+    // distance to the nearest random feature point
+    Worley,
+    // alternating unit cubes
+    Checker,
+}
+
+// A 3D pattern evaluated at the hit position. The pattern gives a value in [0, 1]
+// which blends between two colours.
+#[derive(Debug, Clone)]
+pub struct Procedural {
+    pub pattern: Pattern,
+    // pattern features per unit length
+    pub scale: f64,
+    pub octaves: u32,
+    pub colors: [Color; 2],
+    pub space: TextureSpace,
+}
+
+impl Procedural {
+    pub fn new(pattern: Pattern, scale: f64, colors: [Color; 2]) -> Self {
+        Procedural { pattern, scale, octaves: 6, colors, space: TextureSpace::World }
+    }
+
+    pub fn value(&self, p: Point3) -> Color {
+        let t = self.pattern_value(p * self.scale).clamp(0.0, 1.0);
+        (1.0 - t) * self.colors[0] + t * self.colors[1]
+    }
+
+    fn pattern_value(&self, p: Point3) -> f64 {
+        match self.pattern {
+            Pattern::Perlin => 0.5 * (noise::perlin(p) + 1.0),
+            Pattern::Turbulence => noise::turbulence(p, self.octaves),
+            Pattern::Marble => 0.5 * (1.0 + f64::sin(p.x() + 5.0 * noise::turbulence(p, self.octaves))),
+            Pattern::Wood => {
+                let r = f64::sqrt(p.x() * p.x() + p.z() * p.z()) + 0.4 * noise::fbm(p * 0.5, self.octaves.min(3));
+                r - r.floor()
+            }
+            Pattern::Worley => noise::worley(p),
+            Pattern::Checker => {
+                let parity = (p.x().floor() + p.y().floor() + p.z().floor()) as i64;
+                parity.rem_euclid(2) as f64
+            }
+        }
+    }
+}
+
+// What happens to uv coordinates outside [0,1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+// Colour images are stored as sRGB and converted to linear on load, data images
+// (roughness, bump and normal maps) are used as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    // only kept for images with transparent texels
+    alpha: Option<Vec<f64>>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alpha = if self.has_alpha() { ", alpha" } else { "" };
+        write!(f, "ImageTexture({}x{}{}, {:?}, {:?})", self.width, self.height, alpha, self.wrap, self.filter)
+    }
+}
+
+impl ImageTexture {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize, "pixel buffer does not match texture dimensions");
+        ImageTexture { width, height, pixels, alpha: None, wrap: WrapMode::default(), filter: Filter::default() }
+    }
+
+    // alpha is never colour managed
+    pub fn with_alpha(mut self, alpha: Vec<f64>) -> Self {
+        assert_eq!(alpha.len(), self.pixels.len(), "alpha buffer does not match texture dimensions");
+        self.alpha = Some(alpha);
+        self
+    }
+
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> image::ImageResult<Self> {
+        let img = image::open(path)?;
+        let has_alpha = img.color().has_alpha();
+        let img = img.into_rgba32f();
+        let decode = |c: f32| match color_space {
+            ColorSpace::Srgb => srgb_to_linear(c as f64),
+            ColorSpace::Linear => c as f64,
+        };
+        let pixels = img.pixels().map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2]))).collect();
+        let texture = ImageTexture::new(img.width(), img.height(), pixels);
+        if has_alpha && img.pixels().any(|p| p[3] < 1.0) {
+            Ok(texture.with_alpha(img.pixels().map(|p| p[3] as f64).collect()))
+        } else {
+            Ok(texture)
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // true when every texel is a shade of grey, e.g. a bump map rather than a normal map
+    pub fn is_grayscale(&self) -> bool {
+        self.pixels.iter().all(|p| p.x() == p.y() && p.y() == p.z())
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    // v = 0 is the bottom row of the image, as in OBJ texture coordinates
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        self.filtered(u, v, |i| self.pixels[i])
+    }
+
+    // opaque images have an alpha of 1 everywhere
+    pub fn sample_alpha(&self, u: f64, v: f64) -> Color {
+        match &self.alpha {
+            Some(alpha) => self.filtered(u, v, |i| Color::new(alpha[i], alpha[i], alpha[i])),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    // looks up the texels around (u, v) with fetch, which maps a pixel index to its value
+    fn filtered(&self, u: f64, v: f64, fetch: impl Fn(usize) -> Color) -> Color {
+        let x = u * self.width as f64;
+        let y = (1.0 - v) * self.height as f64;
+        let texel = |x: i64, y: i64| fetch(self.index(x, y));
+        match self.filter {
+            Filter::Nearest => texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                // texel centres sit at half integer coordinates
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+                let bottom = (1.0 - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+                (1.0 - fy) * top + fy * bottom
+            }
+        }
+    }
+
+    fn index(&self, x: i64, y: i64) -> usize {
+        let x = wrap(x, self.width as i64, self.wrap);
+        let y = wrap(y, self.height as i64, self.wrap);
+        (y * self.width as i64 + x) as usize
+    }
+}
+
+fn wrap(i: i64, n: i64, mode: WrapMode) -> i64 {
+    match mode {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::Clamp => i.clamp(0, n - 1),
+        WrapMode::Mirror => {
+            let i = i.rem_euclid(2 * n);
+            if i < n { i } else { 2 * n - 1 - i }
+        }
+    }
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        f64::powf((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(c: Color) -> f64 {
+        assert!(c.x() == c.y() && c.y() == c.z(), "{:?} is not grey", c);
+        c.x()
+    }
+
+    // one row, black then white
+    fn black_white(wrap: WrapMode, filter: Filter) -> ImageTexture {
+        let mut image = ImageTexture::new(2, 1, vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)]);
+        image.wrap = wrap;
+        image.filter = filter;
+        image
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let repeat: Vec<i64> = (-2..6).map(|i| wrap(i, 4, WrapMode::Repeat)).collect();
+        assert_eq!(repeat, [2, 3, 0, 1, 2, 3, 0, 1]);
+        let clamp: Vec<i64> = (-2..6).map(|i| wrap(i, 4, WrapMode::Clamp)).collect();
+        assert_eq!(clamp, [0, 0, 0, 1, 2, 3, 3, 3]);
+        // reflected at both edges, repeating every 2n
+        let mirror: Vec<i64> = (-2..10).map(|i| wrap(i, 4, WrapMode::Mirror)).collect();
+        assert_eq!(mirror, [1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn nearest_picks_the_texel_under_the_point() {
+        let image = black_white(WrapMode::Repeat, Filter::Nearest);
+        assert_eq!(grey(image.sample(0.25, 0.5)), 0.0);
+        assert_eq!(grey(image.sample(0.49, 0.5)), 0.0);
+        assert_eq!(grey(image.sample(0.75, 0.5)), 1.0);
+        assert_eq!(grey(image.sample(1.25, 0.5)), 0.0);
+        assert_eq!(grey(image.sample(-0.25, 0.5)), 1.0);
+    }
+
+    #[test]
+    fn bilinear_blends_between_texel_centres() {
+        let image = black_white(WrapMode::Clamp, Filter::Bilinear);
+        // texel centres give the texel itself
+        assert!(grey(image.sample(0.25, 0.5)).abs() < 1e-12);
+        assert!((grey(image.sample(0.75, 0.5)) - 1.0).abs() < 1e-12);
+        assert!((grey(image.sample(0.5, 0.5)) - 0.5).abs() < 1e-12);
+        assert!((grey(image.sample(0.375, 0.5)) - 0.25).abs() < 1e-12);
+        // at the left edge clamping blends black with itself, repeating blends it with the white texel
+        assert!(grey(image.sample(0.0, 0.5)).abs() < 1e-12);
+        let image = black_white(WrapMode::Repeat, Filter::Bilinear);
+        assert!((grey(image.sample(0.0, 0.5)) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn v_counts_up_from_the_bottom_row() {
+        // white top row, black bottom row
+        let mut image = ImageTexture::new(1, 2, vec![Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)]);
+        image.filter = Filter::Nearest;
+        assert_eq!(grey(image.sample(0.5, 0.25)), 0.0);
+        assert_eq!(grey(image.sample(0.5, 0.75)), 1.0);
+    }
+
+    #[test]
+    fn srgb_decodes_to_linear() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        // the linear toe and the power curve meet at the threshold
+        assert!((srgb_to_linear(0.04045) - srgb_to_linear(0.04045 + 1e-9)).abs() < 1e-6);
+        assert!((srgb_to_linear(0.04045) - 0.003131).abs() < 1e-6);
+    }
+
+    #[test]
+    fn checker_alternates_in_uv() {
+        let even = Color::new(1.0, 1.0, 1.0);
+        let checker = Texture::Checker { even, odd: Color::new(0.0, 0.0, 0.0), scale: 2.0 };
+        let at = |u, v| grey(checker.value(u, v, Point3::default()));
+        assert_eq!(at(0.1, 0.1), 1.0);
+        assert_eq!(at(0.6, 0.1), 0.0);
+        assert_eq!(at(0.1, 0.6), 0.0);
+        assert_eq!(at(0.6, 0.6), 1.0);
+        // the squares continue past 0 without a doubled row
+        assert_eq!(at(-0.1, 0.1), 0.0);
+        assert_eq!(at(-0.6, 0.1), 1.0);
+        assert_eq!(at(-0.1, -0.1), 1.0);
+    }
+
+    #[test]
+    fn procedural_patterns_blend_between_their_colours() {
+        let colors = [Color::new(0.2, 0.2, 0.2), Color::new(0.8, 0.8, 0.8)];
+        for pattern in [Pattern::Perlin, Pattern::Turbulence, Pattern::Marble, Pattern::Wood, Pattern::Worley, Pattern::Checker] {
+            let procedural = Procedural::new(pattern, 3.0, colors);
+            for i in 0..500 {
+                let i = i as f64;
+                let p = Point3::new(f64::sin(i * 1.3) * 5.0, f64::cos(i * 0.7) * 5.0, i * 0.01 - 2.5);
+                let c = grey(procedural.value(p));
+                assert!((0.2 - 1e-12..=0.8 + 1e-12).contains(&c), "{:?} at {} is {}", pattern, p, c);
+                assert_eq!(c, grey(procedural.value(p)));
+            }
+        }
+        // unit cubes in pattern space, so half a unit at scale 2
+        let checker = Procedural::new(Pattern::Checker, 2.0, colors);
+        assert_eq!(grey(checker.value(Point3::new(0.1, 0.1, 0.1))), 0.2);
+        assert_eq!(grey(checker.value(Point3::new(0.6, 0.1, 0.1))), 0.8);
+        assert_eq!(grey(checker.value(Point3::new(-0.1, 0.1, 0.1))), 0.8);
+    }
+}
@@ -0,0 +1,266 @@
+use std::f64::consts::PI;
+
+use crate::{vec3::{Point3, Vec3}, material::MaterialId, hittable::{Hittable, Record}, ray::Ray, aabb::AABB, transform::Transform, cylinder::around_y};
+
+// A ring around center in the plane perpendicular to axis: a tube of radius minor swept around a
+// circle of radius major. u runs around the axis and v around the tube.
+#[derive(Debug, Clone)]
+pub struct Torus {
+    major: f64,
+    minor: f64,
+    to_world: Transform,
+    material: MaterialId,
+    pub bounds: AABB,
+}
+
+impl Torus {
+    pub fn new(center: Point3, axis: Vec3, major: f64, minor: f64, material: MaterialId) -> Self {
+        let to_world = Transform::orient(center, axis);
+        let r = major + minor;
+        let local = AABB::new(Vec3::new(-r, -minor, -r), Vec3::new(r, minor, r));
+        Torus { major, minor, to_world, material, bounds: to_world.bounds(&local) }
+    }
+
+    pub fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
+            return None;
+        }
+        let local = self.to_world.inverse().ray(ray);
+
+        // The quartic is solved along a unit direction starting close to the torus, far away
+        // origins and long directions cost a lot of precision otherwise
+        let scale = local.direction().length();
+        let d = local.direction() / scale;
+        let start = (-Vec3::dot(local.origin(), d) - (self.major + self.minor)).max(0.0);
+        let o = local.origin() + start * d;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) with p = o + s d
+        let (r2, m2) = (self.major * self.major, self.minor * self.minor);
+        let od = Vec3::dot(o, d);
+        let oo = Vec3::dot(o, o);
+        let k = oo + r2 - m2;
+        let roots = solve_quartic(
+            1.0,
+            4.0 * od,
+            2.0 * k + 4.0 * od * od - 4.0 * r2 * (d.x() * d.x() + d.z() * d.z()),
+            4.0 * k * od - 8.0 * r2 * (o.x() * d.x() + o.z() * d.z()),
+            k * k - 4.0 * r2 * (o.x() * o.x() + o.z() * o.z()),
+        );
+
+        let t = roots.into_iter()
+            .map(|s| (s + start) / scale)
+            .filter(|t| *t >= t_min && *t <= t_max)
+            .min_by(f64::total_cmp)?;
+        let p = local.ray_at(t);
+
+        // the normal points away from the nearest point on the circle through the middle of the tube
+        let ring = Vec3::new(p.x(), 0.0, p.z());
+        let ring = if ring.length() > 0.0 { ring.unit() * self.major } else { ring };
+        let normal = (p - ring).unit();
+
+        let mut return_record = Record::new();
+        return_record.t = t;
+        return_record.object_point = p;
+        return_record.material = self.material;
+        let rho = f64::sqrt(p.x() * p.x() + p.z() * p.z());
+        return_record.u = around_y(p);
+        return_record.v = (f64::atan2(p.y(), rho - self.major) + PI) / (2.0 * PI);
+        let tangent = Vec3::new(p.z(), 0.0, -p.x());
+        (return_record.tangent, return_record.bitangent) = if tangent.length() > 0.0 {
+            let tangent = tangent.unit();
+            (tangent, Vec3::cross(normal, tangent))
+        } else {
+            Vec3::basis(normal)
+        };
+        return_record.calculate_normal(&local, normal);
+        return_record.transform(&self.to_world, ray);
+        Some(return_record)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.to_world.point(Vec3::default())
+    }
+}
+
+impl Hittable for Torus {
+    fn ray_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        Torus::ray_hit(self, ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn centroid(&self) -> Vec3 {
+        Torus::centroid(self)
+    }
+}
+
+// Real roots of a x^4 + b x^3 + c x^2 + d x + e with Ferrari's method, polished with a few Newton steps
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // depressed quartic y^4 + p y^2 + q y + r with x = y - b/4
+    let shift = b / 4.0;
+    let b2 = b * b;
+    let p = c - 3.0 * b2 / 8.0;
+    let q = d - b * c / 2.0 + b2 * b / 8.0;
+    let r = e - b * d / 4.0 + b2 * c / 16.0 - 3.0 * b2 * b2 / 256.0;
+
+    let mut ys: Vec<f64> = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // biquadratic, a quadratic in y^2
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        // any positive root m of the resolvent cubic splits the quartic into two quadratics
+        let m = solve_cubic(1.0, p, p * p / 4.0 - r, -q * q / 8.0).into_iter().fold(f64::NAN, f64::max);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+            ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+        }
+    }
+
+    let f = |x: f64| (((x + b) * x + c) * x + d) * x + e;
+    let df = |x: f64| ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+    ys.into_iter().map(|y| {
+        let mut x = y - shift;
+        for _ in 0..3 {
+            let slope = df(x);
+            if slope == 0.0 {
+                break;
+            }
+            x -= f(x) / slope;
+        }
+        x
+    }).collect()
+}
+
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return vec![];
+    }
+    // avoids cancellation between b and the square root
+    let q = -0.5 * (b + b.signum() * disc.sqrt());
+    if q == 0.0 {
+        return vec![0.0];
+    }
+    vec![q / a, c / q]
+}
+
+// real roots of a x^3 + b x^2 + c x + d
+fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    let (b, c, d) = (b / a, c / a, d / a);
+    let q = (b * b - 3.0 * c) / 9.0;
+    let r = (2.0 * b * b * b - 9.0 * b * c + 27.0 * d) / 54.0;
+    let shift = b / 3.0;
+
+    if r * r < q * q * q {
+        // three real roots, trigonometric form
+        let theta = f64::acos((r / (q * q * q).sqrt()).clamp(-1.0, 1.0));
+        let s = -2.0 * q.sqrt();
+        vec![
+            s * f64::cos(theta / 3.0) - shift,
+            s * f64::cos((theta + 2.0 * PI) / 3.0) - shift,
+            s * f64::cos((theta - 2.0 * PI) / 3.0) - shift,
+        ]
+    } else {
+        let a = -r.signum() * f64::cbrt(r.abs() + f64::sqrt(r * r - q * q * q));
+        let b = if a == 0.0 { 0.0 } else { q / a };
+        vec![a + b - shift]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        let roots = sorted(roots);
+        assert_eq!(roots.len(), expected.len(), "roots {:?}, expected {:?}", roots, expected);
+        for (root, want) in roots.iter().zip(expected) {
+            assert!((root - want).abs() < 1e-6, "roots {:?}, expected {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn quartic_four_real_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]);
+        // the same roots with a leading coefficient
+        assert_roots(solve_quartic(2.0, -20.0, 70.0, -100.0, 48.0), &[1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn quartic_two_real_roots() {
+        // (x - 1)(x + 2)(x^2 + 1)
+        assert_roots(solve_quartic(1.0, 1.0, -1.0, 1.0, -2.0), &[-2.0, 1.0]);
+        // x^4 + 1
+        assert_roots(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0), &[]);
+    }
+
+    #[test]
+    fn quartic_double_root() {
+        // (x - 1)^2 (x - 3)(x + 2)
+        let roots = sorted(solve_quartic(1.0, -3.0, -3.0, 11.0, -6.0));
+        assert!((roots[0] + 2.0).abs() < 1e-6 && (roots[roots.len() - 1] - 3.0).abs() < 1e-6, "{:?}", roots);
+        let double: Vec<f64> = roots[1..roots.len() - 1].to_vec();
+        assert!(!double.is_empty() && double.iter().all(|x| (x - 1.0).abs() < 1e-4), "{:?}", roots);
+        // biquadratic (x^2 - 4)^2
+        let roots = solve_quartic(1.0, 0.0, -8.0, 0.0, 16.0);
+        assert!(!roots.is_empty() && roots.iter().all(|x| (x.abs() - 2.0).abs() < 1e-6), "{:?}", roots);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(solve_cubic(1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        // (x - 2)(x^2 + 1)
+        assert_roots(solve_cubic(1.0, -2.0, 1.0, -2.0), &[2.0]);
+        // (x - 1)^2 (x + 2)
+        let roots = sorted(solve_cubic(1.0, 0.0, -3.0, 2.0));
+        assert!((roots[0] + 2.0).abs() < 1e-6, "{:?}", roots);
+        assert!(roots[1..].iter().all(|x| (x - 1.0).abs() < 1e-6), "{:?}", roots);
+    }
+
+    fn torus() -> Torus {
+        Torus::new(Point3::default(), Vec3::new(0.0, 1.0, 0.0), 2.0, 0.5, MaterialId::EMPTY)
+    }
+
+    #[test]
+    fn ray_through_the_hole_misses() {
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(torus().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ray_across_the_ring_hits_the_outside() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = torus().ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the tube");
+        assert!((hit.t - 2.5).abs() < 1e-6, "t = {}", hit.t);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-6, "normal = {:?}", hit.normal);
+    }
+
+    #[test]
+    fn grazing_ray_touches_the_top_of_the_tube() {
+        // runs along the top of the tube, just inside it, and touches the ring at x = -2
+        let ray = Ray::new(Point3::new(-5.0, 0.5 - 1e-6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = torus().ray_hit(&ray, 0.001, f64::INFINITY).expect("grazing ray should hit");
+        assert!((hit.t - 3.0).abs() < 1e-2, "t = {}", hit.t);
+        assert!(hit.normal.y() > 0.99, "normal = {:?}", hit.normal);
+        // just above the tube it misses
+        let ray = Ray::new(Point3::new(-5.0, 0.5 + 1e-4, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(torus().ray_hit(&ray, 0.001, f64::INFINITY).is_none());
+    }
+}
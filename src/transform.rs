@@ -0,0 +1,344 @@
+// Affine transformations as 4x4 matrices. Every transform carries its inverse, which is what
+// instances use to move rays into object space.
+//
+// let t = Transform::scale(Vec3::new(2.0, 2.0, 2.0))
+//     .then(&Transform::rotate_y(90.0))
+//     .then(&Transform::translate(Vec3::new(0.0, 1.0, 0.0)));
+
+use std::ops::Mul;
+
+use crate::{aabb::AABB, ray::Ray, vec3::{Point3, Vec3}};
+
+pub type Matrix4 = [[f64; 4]; 4];
+
+const IDENTITY: Matrix4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform { matrix: IDENTITY, inverse: IDENTITY }
+    }
+
+    // None if the matrix is singular
+    pub fn from_matrix(matrix: Matrix4) -> Option<Self> {
+        let inverse = invert(&matrix)?;
+        Some(Transform { matrix, inverse })
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for i in 0..3 {
+            matrix[i][3] = offset.v[i];
+            inverse[i][3] = -offset.v[i];
+        }
+        Transform { matrix, inverse }
+    }
+
+    // panics on a zero or non-finite scale factor, use try_scale for factors read from a file
+    pub fn scale(factors: Vec3) -> Self {
+        Transform::try_scale(factors).expect("scale factors must be finite and non-zero")
+    }
+
+    // None if a factor is zero, which would flatten the object, or not finite
+    pub fn try_scale(factors: Vec3) -> Option<Self> {
+        if !factors.v.iter().all(|f| f.is_finite() && *f != 0.0) {
+            return None;
+        }
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for i in 0..3 {
+            matrix[i][i] = factors.v[i];
+            inverse[i][i] = 1.0 / factors.v[i];
+        }
+        Some(Transform { matrix, inverse })
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    // Counter clockwise rotation around axis when looking down the axis towards the origin.
+    // Panics on a zero or non-finite axis or angle, use try_rotate for values read from a file
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        Transform::try_rotate(axis, degrees).expect("rotation axis must be finite and non-zero, and the angle finite")
+    }
+
+    // None if the axis is zero, which has no direction to turn around, or anything is not finite
+    pub fn try_rotate(axis: Vec3, degrees: f64) -> Option<Self> {
+        let length = axis.length();
+        if !(length > 0.0 && length.is_finite() && degrees.is_finite()) {
+            return None;
+        }
+        let a = axis / length;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let (sin, cos) = f64::to_radians(degrees).sin_cos();
+        let k = 1.0 - cos;
+        let matrix = [
+            [cos + x * x * k, x * y * k - z * sin, x * z * k + y * sin, 0.0],
+            [y * x * k + z * sin, cos + y * y * k, y * z * k - x * sin, 0.0],
+            [z * x * k - y * sin, z * y * k + x * sin, cos + z * z * k, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        // rotations are orthogonal, the inverse is the transpose
+        Some(Transform { matrix, inverse: transpose(&matrix) })
+    }
+
+    // Rigid transform that moves the origin to origin and turns the y axis to point along axis,
+    // local shapes built around +y use it to stand in any direction
+    pub fn orient(origin: Point3, axis: Vec3) -> Self {
+        let (t, b) = Vec3::basis(axis);
+        let n = axis.unit();
+        // x -> b, y -> n, z -> t keeps the frame right handed
+        let rotation = [
+            [b.x(), n.x(), t.x(), 0.0],
+            [b.y(), n.y(), t.y(), 0.0],
+            [b.z(), n.z(), t.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        Transform { matrix: rotation, inverse: transpose(&rotation) }.then(&Transform::translate(origin))
+    }
+
+    // this transform followed by next
+    pub fn then(&self, next: &Transform) -> Transform {
+        *next * *self
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        let m = &self.matrix;
+        let mut out = [0.0; 3];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = m[i][0] * p.x() + m[i][1] * p.y() + m[i][2] * p.z() + m[i][3];
+        }
+        Vec3 { v: out }
+    }
+
+    // directions ignore the translation
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.matrix;
+        let mut out = [0.0; 3];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = m[i][0] * v.x() + m[i][1] * v.y() + m[i][2] * v.z();
+        }
+        Vec3 { v: out }
+    }
+
+    // normals go through the inverse transpose so they stay perpendicular to the surface
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inverse;
+        let mut out = [0.0; 3];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = m[0][i] * n.x() + m[1][i] * n.y() + m[2][i] * n.z();
+        }
+        Vec3 { v: out }
+    }
+
+    // the direction is not normalised, so t is the same along the original and the transformed ray
+    pub fn ray(&self, ray: &Ray) -> Ray {
+        Ray::new(self.point(ray.origin()), self.vector(ray.direction()))
+    }
+
+    // box around the 8 transformed corners of bounds, unbounded boxes stay infinite
+    pub fn bounds(&self, bounds: &AABB) -> AABB {
+        if !bounds.is_finite() {
+            return AABB::infinite();
+        }
+        let mut out = AABB::default();
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { bounds.min.x() } else { bounds.max.x() },
+                if i & 2 == 0 { bounds.min.y() } else { bounds.max.y() },
+                if i & 4 == 0 { bounds.min.z() } else { bounds.max.z() },
+            );
+            out.add(self.point(corner));
+        }
+        out
+    }
+}
+
+// a * b applies b first, as with the matrices
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform { matrix: multiply(&self.matrix, &rhs.matrix), inverse: multiply(&rhs.inverse, &self.inverse) }
+    }
+}
+
+fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut out = [[0.0; 4]; 4];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn transpose(m: &Matrix4) -> Matrix4 {
+    let mut out = [[0.0; 4]; 4];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = m[j][i];
+        }
+    }
+    out
+}
+
+// Gauss-Jordan elimination with partial pivoting
+fn invert(m: &Matrix4) -> Option<Matrix4> {
+    let mut a = *m;
+    let mut inv = IDENTITY;
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let p = a[col][col];
+        for j in 0..4 {
+            a[col][j] /= p;
+            inv[col][j] /= p;
+        }
+        for row in 0..4 {
+            if row != col {
+                let f = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_identity(m: &Matrix4) {
+        for (i, row) in m.iter().enumerate() {
+            for (j, x) in row.iter().enumerate() {
+                let want = if i == j { 1.0 } else { 0.0 };
+                assert!((x - want).abs() < 1e-9, "{:?} is not the identity", m);
+            }
+        }
+    }
+
+    #[test]
+    fn invert_undoes_the_matrix() {
+        let matrix = [
+            [2.0, 0.0, 1.0, 3.0],
+            [0.0, 0.0, 3.0, -1.0],
+            [1.0, 4.0, 0.0, 2.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        let t = Transform::from_matrix(matrix).expect("matrix is invertible");
+        assert_identity(&multiply(t.matrix(), t.inverse().matrix()));
+        assert_identity(&multiply(t.inverse().matrix(), t.matrix()));
+        let p = Point3::new(0.5, -2.0, 7.0);
+        assert_near(t.inverse().point(t.point(p)), p);
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let mut flat = IDENTITY;
+        flat[1][1] = 0.0;
+        assert!(Transform::from_matrix(flat).is_none());
+        // two equal rows
+        let matrix = [[1.0, 2.0, 3.0, 0.0], [1.0, 2.0, 3.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+        assert!(Transform::from_matrix(matrix).is_none());
+    }
+
+    #[test]
+    fn composed_transforms_carry_their_inverse() {
+        let t = Transform::scale(Vec3::new(2.0, 3.0, 4.0))
+            .then(&Transform::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0))
+            .then(&Transform::translate(Vec3::new(1.0, -2.0, 5.0)));
+        assert_identity(&multiply(t.matrix(), &t.inverse));
+        let ray = Ray::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.0, -1.0, 2.0));
+        let back = t.inverse().ray(&t.ray(&ray));
+        assert_near(back.origin(), ray.origin());
+        assert_near(back.direction(), ray.direction());
+    }
+
+    #[test]
+    fn rotate_turns_counter_clockwise() {
+        assert_near(Transform::rotate_y(90.0).vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 0.0, -1.0));
+        assert_near(Transform::rotate_z(90.0).vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0));
+        // the axis does not have to be unit length
+        assert_near(Transform::rotate(Vec3::new(0.0, 5.0, 0.0), 90.0).vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn degenerate_rotations_are_rejected() {
+        assert!(Transform::try_rotate(Vec3::default(), 45.0).is_none());
+        assert!(Transform::try_rotate(Vec3::new(f64::NAN, 1.0, 0.0), 45.0).is_none());
+        assert!(Transform::try_rotate(Vec3::new(0.0, 1.0, 0.0), f64::INFINITY).is_none());
+        assert!(Transform::try_scale(Vec3::new(1.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        // the plane x + y = 0 squashed along x
+        let t = Transform::scale(Vec3::new(0.5, 2.0, 1.0));
+        let (normal, along) = (Vec3::new(1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let (n, v) = (t.normal(normal), t.vector(along));
+        assert!(Vec3::dot(n, v).abs() < 1e-9, "{:?} is not perpendicular to {:?}", n, v);
+        // plain vectors would not be
+        assert!(Vec3::dot(t.vector(normal), v).abs() > 0.1);
+    }
+
+    #[test]
+    fn bounds_cover_the_transformed_corners() {
+        let unit = AABB::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let moved = Transform::translate(Vec3::new(1.0, 2.0, 3.0)).bounds(&unit);
+        assert_near(moved.min, Point3::new(0.0, 1.0, 2.0));
+        assert_near(moved.max, Point3::new(2.0, 3.0, 4.0));
+        // a quarter turn around y keeps the box, an eighth turn widens it to the diagonal
+        let turned = Transform::rotate_y(45.0).bounds(&unit);
+        let d = f64::sqrt(2.0);
+        assert_near(turned.min, Point3::new(-d, -1.0, -d));
+        assert_near(turned.max, Point3::new(d, 1.0, d));
+        assert!(!Transform::rotate_y(45.0).bounds(&AABB::infinite()).is_finite());
+    }
+}
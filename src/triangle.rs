@@ -1,100 +1,91 @@
-use crate::{vec3::{Vec3, Point3, BLACK, WHITE}, material::{self, Material}, hittable::{Hittable, Record}, aabb::AABB, color::Color};
+use crate::{vec3::{Vec3, Point3}, color::Color, material::MaterialId, hittable::{Hittable, Record}, aabb::AABB, ray::Ray};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Triangle {
     p1: Point3,
     p2: Point3,
     p3: Point3,
-    e1: Vec3,
-    e2: Vec3,
     normal: Vec3,
     pub n1: Option<Vec3>,
     pub n2: Option<Vec3>,
     pub n3: Option<Vec3>,
-    material: Material,
+    pub uv1: Option<(f64, f64)>,
+    pub uv2: Option<(f64, f64)>,
+    pub uv3: Option<(f64, f64)>,
+    // per vertex tangents (direction of increasing u), see mesh::vertex_tangents
+    pub t1: Option<Vec3>,
+    pub t2: Option<Vec3>,
+    pub t3: Option<Vec3>,
+    // vertex colours, for materials with a vertex colour texture
+    pub c1: Option<Color>,
+    pub c2: Option<Color>,
+    pub c3: Option<Color>,
+    material: MaterialId,
     pub bounds: AABB,
 }
 
 impl Triangle {
-    pub fn new(p1: Point3, p2: Point3, p3: Point3, material: Material) -> Self{
-        let e1 = p2 - p1;
-        let e2 = p3 - p1;
-        let normal = Vec3::cross(e1, e2);
+    pub fn new(p1: Point3, p2: Point3, p3: Point3, material: MaterialId) -> Self{
+        let normal = Vec3::cross(p2 - p1, p3 - p1);
         let mut bounds = AABB::default();
         bounds.add(p1);
         bounds.add(p2);
         bounds.add(p3);
 
         Triangle {
-            p1, p2, p3, e1, e2, normal, bounds, material, n1: None, n2: None, n3: None
+            p1, p2, p3, normal, bounds, material, n1: None, n2: None, n3: None, uv1: None, uv2: None, uv3: None,
+            t1: None, t2: None, t3: None, c1: None, c2: None, c3: None
         }
     }
 
     pub fn ray_hit(&self, ray: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::Record> {
-
-        let v_dot_n = Vec3::dot(ray.direction(), self.normal);
-        if v_dot_n == 0.0 {
-            return None
-        }
-
-        let d = -Vec3::dot(self.p1, self.normal);
-        let qp: Vec3 = self.p1 - ray.origin();
-        let qp_dot_n = Vec3::dot(qp, self.normal);
-
-        let area = Vec3::cross(
-            (self.p2 - self.p1), (self.p3 - self.p1)
-        ).length() * 0.5;
-
-        let t: f64 = qp_dot_n/v_dot_n;
-
-        if t_min > t || t > t_max {
-            return None;
-        }
-
-        let p = ray.ray_at(t);
-
-        //E1
-
-        let v = self.p2 - self.p1;
-        let e = p - self.p1;
-        let c = Vec3::cross(v, e);
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
-
-        //E2
-
-        let v = self.p3 - self.p2;
-        let e = p - self.p2;
-        let c = Vec3::cross(v, e);
-        let x = (c.length() / 2.0) / area;
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
-
-        //E3
-
-        let v = self.p1 - self.p3;
-        let e = p - self.p3;
-        let c = Vec3::cross(v, e);
-        let y = (c.length() / 2.0) / area;
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
-
+        let (t, [w1, w2, w3]) = intersect([self.p1, self.p2, self.p3], self.normal, ray, t_min, t_max)?;
 
         let mut return_record = Record::new();
 
         return_record.t = t;
         return_record.point = ray.ray_at(t);
+        return_record.object_point = return_record.point;
         return_record.material = self.material;
 
+        // w1, w2, w3 are the barycentric weights of p1, p2, p3
         let normal: Vec3 = match (self.n1, self.n2, self.n3) {
             (Some(n1), Some(n2), Some(n3)) => {
-                ((1.0 - x - y) * n3) + (x * n1) + (y * n2)
+                (w1 * n1) + (w2 * n2) + (w3 * n3)
             }
             _ => self.normal
         };
-        return_record.calculate_normal(ray, normal.unit());
-        return Some(return_record)
+
+        let [uv1, uv2, uv3] = self.uvs();
+        return_record.u = w1 * uv1.0 + w2 * uv2.0 + w3 * uv3.0;
+        return_record.v = w1 * uv1.1 + w2 * uv2.1 + w3 * uv3.1;
+
+        if let (Some(c1), Some(c2), Some(c3)) = (self.c1, self.c2, self.c3) {
+            return_record.color = (w1 * c1) + (w2 * c2) + (w3 * c3);
+        }
+
+        let normal = normal.unit();
+        let (face_tangent, handedness) = self.face_tangent();
+        let tangent = match (self.t1, self.t2, self.t3) {
+            (Some(t1), Some(t2), Some(t3)) => (w1 * t1) + (w2 * t2) + (w3 * t3),
+            _ => face_tangent
+        };
+        (return_record.tangent, return_record.bitangent) = tangent_frame(normal, tangent, handedness);
+
+        return_record.calculate_normal(ray, normal);
+        Some(return_record)
     }
 
-    fn bounds(&self) -> &AABB {
-        &self.bounds
+    // without texture coordinates the corners get (0,0), (1,0) and (0,1)
+    pub fn uvs(&self) -> [(f64, f64); 3] {
+        match (self.uv1, self.uv2, self.uv3) {
+            (Some(uv1), Some(uv2), Some(uv3)) => [uv1, uv2, uv3],
+            _ => [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+        }
+    }
+
+    pub fn face_tangent(&self) -> (Vec3, f64) {
+        face_tangent([self.p1, self.p2, self.p3], self.uvs())
     }
 
     pub fn centroid(&self) -> Vec3 {
@@ -102,70 +93,159 @@ impl Triangle {
     }
 }
 
-impl Hittable for Triangle {
-    fn ray_hit(&self, ray: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::Record> {
+// Where ray crosses the triangle with corners p and (unnormalised) face normal: t and the
+// barycentric weights of the three corners
+pub(crate) fn intersect(p: [Point3; 3], normal: Vec3, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, [f64; 3])> {
+    let [p1, p2, p3] = p;
+    let v_dot_n = Vec3::dot(ray.direction(), normal);
+    if v_dot_n == 0.0 {
+        return None
+    }
 
-        if !self.bounds.hit(ray) {
-            return None;
-        }
+    let qp: Vec3 = p1 - ray.origin();
+    let qp_dot_n = Vec3::dot(qp, normal);
+    let n_dot_n = Vec3::dot(normal, normal);
+
+    let t: f64 = qp_dot_n/v_dot_n;
 
+    if t_min > t || t > t_max {
+        return None;
+    }
 
-        let v_dot_n = Vec3::dot(ray.direction(), self.normal);
+    let p = ray.ray_at(t);
 
-        if v_dot_n == 0.0 {
-            return None
-        }
+    //E1
 
-        let d = -Vec3::dot(self.p1, self.normal);
+    let v = p2 - p1;
+    let e = p - p1;
+    let c = Vec3::cross(v, e);
+    let w3 = Vec3::dot(normal, c) / n_dot_n;
+    if w3 < 0.0 {return None;}
 
-        let qp: Vec3 = self.p1 - ray.origin();
+    //E2
 
+    let v = p3 - p2;
+    let e = p - p2;
+    let c = Vec3::cross(v, e);
+    let w1 = Vec3::dot(normal, c) / n_dot_n;
+    if w1 < 0.0 {return None;}
 
-        let qp_dot_n = Vec3::dot(qp, self.normal);
+    //E3
 
-        let t: f64 = qp_dot_n/v_dot_n;
+    let v = p1 - p3;
+    let e = p - p3;
+    let c = Vec3::cross(v, e);
+    let w2 = Vec3::dot(normal, c) / n_dot_n;
+    if w2 < 0.0 {return None;}
 
-        if t_min > t || t > t_max {
+    Some((t, [w1, w2, w3]))
+}
+
+// The unnormalised direction of increasing u across the face, and whether the uvs are
+// mirrored (-1.0) relative to the winding of the face
+pub(crate) fn face_tangent(p: [Point3; 3], uvs: [(f64, f64); 3]) -> (Vec3, f64) {
+    let [uv1, uv2, uv3] = uvs;
+    let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+    let (du1, dv1) = (uv2.0 - uv1.0, uv2.1 - uv1.1);
+    let (du2, dv2) = (uv3.0 - uv1.0, uv3.1 - uv1.1);
+    let det = du1 * dv2 - du2 * dv1;
+    if det == 0.0 {
+        return (Vec3::default(), 1.0);
+    }
+    ((e1 * dv2 - e2 * dv1) / det, det.signum())
+}
+
+// Orthonormal tangent and bitangent around the shading normal. As in MikkTSpace, only the
+// tangent is interpolated, the bitangent is rebuilt from it and the handedness of the face.
+pub(crate) fn tangent_frame(normal: Vec3, tangent: Vec3, handedness: f64) -> (Vec3, Vec3) {
+    let tangent = tangent - Vec3::dot(tangent, normal) * normal;
+    if tangent.length() < 1e-12 {
+        return Vec3::basis(normal);
+    }
+    let tangent = tangent.unit();
+    (tangent, handedness * Vec3::cross(normal, tangent))
+}
+
+impl Hittable for Triangle {
+    fn ray_hit(&self, ray: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<Record> {
+        if !self.bounds.hit(ray) {
             return None;
         }
+        Triangle::ray_hit(self, ray, t_min, t_max)
+    }
 
-        let p = ray.ray_at(t);
-
-        //E1
+    fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
 
-        let v = self.p2 - self.p1;
-        let e = p - self.p1;
-        let c = Vec3::cross(v, e);
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
+    fn centroid(&self) -> Vec3 {
+        (self.p1 + self.p2 + self.p3) / 3.0
+    }
+}
 
-        //E2
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let v = self.p3 - self.p2;
-        let e = p - self.p2;
-        let c = Vec3::cross(v, e);
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
+    // the corner triangle of a unit square facing +z
+    fn triangle(uvs: [(f64, f64); 3]) -> Triangle {
+        let mut triangle = Triangle::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), MaterialId::EMPTY);
+        (triangle.uv1, triangle.uv2, triangle.uv3) = (Some(uvs[0]), Some(uvs[1]), Some(uvs[2]));
+        triangle
+    }
 
-        //E3
+    fn hit(triangle: &Triangle) -> Record {
+        let ray = Ray::new(Point3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        triangle.ray_hit(&ray, 0.001, f64::INFINITY).expect("ray should hit the triangle")
+    }
 
-        let v3 = self.p1 - self.p3;
-        let e = p - self.p3;
-        let c = Vec3::cross(v3, e);
-        if (Vec3::dot(self.normal, c) < 0.0) {return None;}
+    fn assert_vec(v: Vec3, expected: Vec3) {
+        assert!((v - expected).length() < 1e-9, "{:?}, expected {:?}", v, expected);
+    }
 
-        let mut return_record = Record::new();
+    #[test]
+    fn tangent_frame_follows_the_uvs() {
+        let record = hit(&triangle([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]));
+        assert_vec(record.tangent, Vec3::new(1.0, 0.0, 0.0));
+        assert_vec(record.bitangent, Vec3::new(0.0, 1.0, 0.0));
+        // u runs the other way on a mirrored face, v still points up the texture
+        let mirrored = triangle([(1.0, 0.0), (0.0, 0.0), (1.0, 1.0)]);
+        assert_eq!(mirrored.face_tangent().1, -1.0);
+        let record = hit(&mirrored);
+        assert_vec(record.tangent, Vec3::new(-1.0, 0.0, 0.0));
+        assert_vec(record.bitangent, Vec3::new(0.0, 1.0, 0.0));
+    }
 
-        return_record.t = t;
-        return_record.point = ray.ray_at(t);
-        return_record.material = self.material;
-        return_record.calculate_normal(ray, self.normal.unit());
-        return Some(return_record)
+    #[test]
+    fn degenerate_uvs_still_give_a_frame() {
+        let triangle = triangle([(0.5, 0.5); 3]);
+        let (tangent, handedness) = triangle.face_tangent();
+        assert!(tangent.length() == 0.0 && handedness == 1.0);
+        let record = hit(&triangle);
+        for v in [record.tangent, record.bitangent] {
+            assert!((v.length() - 1.0).abs() < 1e-9 && Vec3::dot(v, record.normal).abs() < 1e-9, "{:?}", v);
+        }
+        assert!(Vec3::dot(record.tangent, record.bitangent).abs() < 1e-9);
     }
 
-    fn bounds(&self) -> &AABB {
-        &self.bounds
+    #[test]
+    fn uvs_blend_with_the_barycentric_weights() {
+        // (0.25, 0.25) weighs the corners 0.5, 0.25, 0.25
+        let record = hit(&triangle([(0.1, 0.2), (0.9, 0.2), (0.1, 0.8)]));
+        assert!((record.u - 0.3).abs() < 1e-9 && (record.v - 0.35).abs() < 1e-9, "uv ({}, {})", record.u, record.v);
+        // the corners get their own uvs
+        let triangle = triangle([(0.1, 0.2), (0.9, 0.2), (0.1, 0.8)]);
+        for (corner, uv) in [(Point3::new(0.0, 0.0, 0.0), (0.1, 0.2)), (Point3::new(1.0, 0.0, 0.0), (0.9, 0.2)), (Point3::new(0.0, 1.0, 0.0), (0.1, 0.8))] {
+            let ray = Ray::new(corner + Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            let record = triangle.ray_hit(&ray, 0.001, f64::INFINITY).expect("corners are on the triangle");
+            assert!((record.u - uv.0).abs() < 1e-9 && (record.v - uv.1).abs() < 1e-9, "{:?} has uv ({}, {})", corner, record.u, record.v);
+        }
     }
 
-    fn centroid(&self) -> Vec3 {
-        (self.p1 + self.p2 + self.p3) / 3.0
+    #[test]
+    fn triangles_without_uvs_use_their_barycentrics() {
+        let triangle = Triangle::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), MaterialId::EMPTY);
+        let record = hit(&triangle);
+        assert!((record.u - 0.25).abs() < 1e-9 && (record.v - 0.25).abs() < 1e-9, "uv ({}, {})", record.u, record.v);
     }
-}
\ No newline at end of file
+}
@@ -6,7 +6,7 @@ use std::fmt::Display;
 use crate::util::gen_random_range;
 
 //used to simplify dev, may remove
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Vec3 {
     pub v: [f64; 3]
 }
@@ -19,10 +19,6 @@ pub const BLACK: Vec3 = Vec3 {
     v: [0.0;3]
 };
 
-pub const ORIGIN: Vec3 = Vec3 {
-    v: [0.0;3]
-};
-
 pub const MIN: Vec3 = Vec3 {
     v:[f64::NEG_INFINITY;3]
 };
@@ -47,12 +43,6 @@ impl Vec3 {
         }
     }
 
-    pub fn default() -> Vec3 {
-        Vec3 {
-            v: [0.0,0.0,0.0]
-        }
-    }
-
     //getters for x, y, z
     pub fn x(&self) -> f64 {
         self.v[0]
@@ -147,6 +137,15 @@ impl Vec3 {
         dir_in - (2.0 * Vec3::dot(dir_in, normal) * normal)
     }
 
+    // two unit vectors perpendicular to n and to each other, (tangent, bitangent, n) is right handed
+    pub fn basis(n: Vec3) -> (Vec3, Vec3) {
+        let n = n.unit();
+        let helper = if n.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = Vec3::cross(helper, n).unit();
+        let bitangent = Vec3::cross(n, tangent);
+        (tangent, bitangent)
+    }
+
     pub fn refract(dir_in: Vec3, normal: Vec3, refractive_ratio: f64) -> Vec3 {
         let cos = Vec3::dot(-dir_in, normal);
         let r_perp = refractive_ratio * (dir_in + cos * normal);